@@ -1,10 +1,11 @@
 use anyhow::{Error as E, Result};
-use candle_core::{Device, Tensor};
+use candle_core::Tensor;
 use candle_transformers::generation::LogitsProcessor;
 // 중요: 속도를 위해 'quantized_phi3' 모듈을 사용합니다.
 use candle_transformers::models::quantized_phi3::ModelWeights as Phi3;
 use hf_hub::api::sync::Api;
 use std::io::Write;
+use suprascalar::models::TokenStreamDecoder;
 use tokenizers::Tokenizer;
 
 #[tokio::main]
@@ -41,7 +42,7 @@ async fn main() -> Result<()> {
     // 2. 엔진 초기화 (Boilerplate 없이 라이브러리 기능 직접 사용)
     // =========================================================================
     // let device = Device::Cpu;
-    let device = Device::new_cuda(0)?;
+    let device = suprascalar::util::select_device()?;
 
     println!("⚙️ Loading GGUF model...");
     let mut file = std::fs::File::open(&model_path)?;
@@ -62,14 +63,14 @@ async fn main() -> Result<()> {
 
     // (1) Encode
     let tokens = tokenizer.encode(prompt, true).map_err(E::msg)?;
-    let mut tokens = tokens.get_ids().to_vec();
-    let prompt_len = tokens.len();
+    let tokens = tokens.get_ids().to_vec();
     let mut generated_tokens = 0usize;
     let sample_len = 200; // 최대 생성 길이
 
     print!("{}", prompt);
     std::io::stdout().flush()?;
-    let mut last_printed = 0usize;
+    // 증분 디코더: 매 스텝 전체를 다시 디코딩하지 않고 새로 확정된 텍스트만 받음
+    let mut stream = TokenStreamDecoder::new();
 
     let start_gen = std::time::Instant::now();
 
@@ -92,17 +93,13 @@ async fn main() -> Result<()> {
 
         // Sampling
         let next_token = logits_processor.sample(&logits)?;
-        tokens.push(next_token);
         generated_tokens += 1;
 
-        // 누적 디코딩을 통해 공백을 포함한 원문 형태를 복원한다.
-        let decoded = tokenizer
-            .decode(&tokens[prompt_len..], true)
-            .map_err(E::msg)?;
-        let new_text = &decoded[last_printed..];
-        print!("{}", new_text);
-        std::io::stdout().flush()?;
-        last_printed = decoded.len();
+        // 새로 확정된 텍스트만 출력 (여러 토큰에 걸친 문자는 완성된 뒤 출력)
+        if let Some(new_text) = stream.push(&tokenizer, next_token)? {
+            print!("{}", new_text);
+            std::io::stdout().flush()?;
+        }
 
         if next_token == 32000 || next_token == 32007 {
             break;
@@ -117,6 +114,10 @@ async fn main() -> Result<()> {
         input = Tensor::new(&[next_token], &device)?.unsqueeze(0)?;
     }
 
+    if let Some(rest) = stream.flush(&tokenizer)? {
+        print!("{}", rest);
+    }
+
     let dt = start_gen.elapsed();
     println!(
         "\n\n---\n⚡ {} tokens generated ({:.2} token/s)",
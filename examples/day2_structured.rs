@@ -1,5 +1,5 @@
 use anyhow::{Error as E, Result};
-use candle_core::{Device, Tensor};
+use candle_core::Tensor;
 use candle_transformers::generation::LogitsProcessor;
 use candle_transformers::models::quantized_phi3::ModelWeights as Phi3;
 use hf_hub::api::sync::Api;
@@ -28,7 +28,7 @@ async fn main() -> Result<()> {
         .model("bartowski/Phi-3-mini-4k-instruct-GGUF".to_string())
         .get("Phi-3-mini-4k-instruct-Q4_K_M.gguf")?;
 
-    let device = Device::new_cuda(0)?;
+    let device = suprascalar::util::select_device()?;
     let mut file = std::fs::File::open(&model_path)?;
     let model_content = candle_core::quantized::gguf_file::Content::read(&mut file)?;
     let mut model = Phi3::from_gguf(false, model_content, &mut file, &device)?;
@@ -2,39 +2,14 @@ use anyhow::{Error as E, Result};
 use candle_core::{Device, Tensor};
 use candle_transformers::generation::LogitsProcessor;
 
-// [수정] 사용자가 요청한 Qwen 전용 모듈 사용 (Llama 집착 버림)
-use candle_transformers::models::quantized_qwen2::ModelWeights as Qwen2;
-use candle_transformers::models::quantized_qwen3::ModelWeights as Qwen3;
-// use candle_transformers::models::qwen3_moe::ModelWeights as
-// use suprascalar::candle_patched::quantized_qwen3::ModelWeights as Qwen3;
-
 use hf_hub::api::sync::Api;
 use std::io::Write;
+use suprascalar::models::QuantizedModel;
 use tokenizers::Tokenizer;
 
-// 두 모델을 아우르는 Enum 정의
-enum Model {
-    Qwen2(Qwen2),
-    Qwen3(Qwen3),
-}
-
-impl Model {
-    fn forward(&mut self, x: &Tensor, pos: usize) -> Result<Tensor> {
-        match self {
-            Model::Qwen2(m) => m.forward(x, pos).map_err(E::from),
-            Model::Qwen3(m) => m.forward(x, pos).map_err(E::from),
-        }
-    }
-}
-
-// 모델 로드 시 어떤 타입인지 구분하기 위한 플래그
-enum ModelType {
-    Qwen2,
-    Qwen3,
-}
-
 struct Engine {
-    model: Model,
+    // Qwen2/Qwen3/MoE를 GGUF 메타데이터로 골라 주는 공용 래퍼
+    model: QuantizedModel,
     tokenizer: Tokenizer,
     device: Device,
     name: String,
@@ -47,7 +22,6 @@ impl Engine {
         model_file: &str,
         tokenizer_repo: &str,
         device: &Device,
-        model_type: ModelType, // 모델 타입 지정
     ) -> Result<Self> {
         println!("⏳ Loading [{}]...", name);
         let api = Api::new()?;
@@ -58,23 +32,9 @@ impl Engine {
             .get("tokenizer.json")?;
         let tokenizer = Tokenizer::from_file(tokenizer_path).map_err(E::msg)?;
 
-        // 2. 모델 로드 (GGUF)
+        // 2. 모델 로드 (GGUF 메타데이터의 아키텍처에 따라 적절한 모듈 사용)
         let model_path = api.model(repo.to_string()).get(model_file)?;
-        let mut file = std::fs::File::open(&model_path)?;
-        // 첨부해주신 파일(qwen2.rs, qwen3.rs)의 로직을 그대로 따름
-        let content = candle_core::quantized::gguf_file::Content::read(&mut file)?;
-
-        // 3. 타입에 따라 적절한 모듈 사용
-        let model = match model_type {
-            ModelType::Qwen2 => {
-                let m = Qwen2::from_gguf(content, &mut file, device)?;
-                Model::Qwen2(m)
-            }
-            ModelType::Qwen3 => {
-                let m = Qwen3::from_gguf(content, &mut file, device)?;
-                Model::Qwen3(m)
-            }
-        };
+        let model = QuantizedModel::load(&model_path, device)?;
 
         println!("✅ [{}] Loaded!", name);
         Ok(Self {
@@ -129,7 +89,7 @@ async fn main() -> Result<()> {
     println!("🔥 Day 4: Dual Brain Loading (Qwen3 0.6B & Qwen3 14B)");
     println!("--------------------------------------------------");
 
-    let device = Device::new_cuda(0)?;
+    let device = suprascalar::util::select_device()?;
 
     // 1. Verifier (Main): Qwen3-14B (Using quantized_qwen3)
     // [수정] qwen3.rs 참조하여 14B 모델 경로 설정
@@ -140,7 +100,6 @@ async fn main() -> Result<()> {
         "Qwen3-14B-Q4_K_M.gguf",
         "Qwen/Qwen3-14B", // Tokenizer Repo
         &device,
-        ModelType::Qwen3, // Qwen3 모듈 사용
     )?;
     // let mut verifier = Engine::new(
     //     "Draft (DeepSeek-R1-Distill-Qwen)",
@@ -148,7 +107,6 @@ async fn main() -> Result<()> {
     //     "DeepSeek-R1-Distill-Qwen-14B-Q4_K_M.gguf",
     //     "deepseek-ai/DeepSeek-R1-Distill-Qwen-14B",
     //     &device,
-    //     ModelType::Qwen2,
     // )?;
 
     // let mut verifier = Engine::new(
@@ -157,7 +115,6 @@ async fn main() -> Result<()> {
     //     "Qwen3-30B-A3B-Instruct-2507-Q4_K_M.gguf",
     //     "Qwen/Qwen3-30B-A3B-Instruct-2507",
     //     &device,
-    //     ModelType::Qwen3,
     // )?;
 
     // 2. Draft (Fast): Qwen3-0.6B (Using quantized_qwen3)
@@ -168,7 +125,6 @@ async fn main() -> Result<()> {
     //     "Qwen3-0.6B-Q4_K_M.gguf",
     //     "Qwen/Qwen3-0.6B",
     //     &device,
-    //     ModelType::Qwen3,
     // )?;
 
     println!("--------------------------------------------------");
@@ -1,64 +1,469 @@
 use anyhow::{Error as E, Result};
-use candle_core::backend::BackendDevice;
-use candle_core::{Device, IndexOp, Tensor};
+use candle_core::{DType, Device, IndexOp, Tensor};
 
-// Assuming you have the patched Qwen3 or wrapper with forward_speculative
-use candle_transformers::models::quantized_qwen2::ModelWeights as Qwen2;
-use suprascalar::candle_transformers_patched::quantized_qwen3::ModelWeights as Qwen3;
+use suprascalar::models::{GenerationConfig, QuantizedModel};
+use suprascalar::util::sync_device;
 
 use hf_hub::api::sync::Api;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::io::Write;
 use std::time::{Duration, Instant};
 use tokenizers::Tokenizer;
 
-enum Model {
-    Qwen2(Qwen2),
-    Qwen3(Qwen3),
+/// draft 토큰 수용 기준
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum AcceptPolicy {
+    /// verifier의 argmax와 정확히 일치할 때만 수용 (출력이 verifier 단독 greedy와 동일)
+    #[default]
+    Greedy,
+    /// verifier 로짓의 상위 k개 안에 들면 수용 (품질을 조금 내주고 수용률을 높임)
+    TopK(usize),
+    /// rejection sampling: draft/verifier 각각의 샘플링 분포로 확률적으로 수용
+    /// (출력 분포가 `verifier_sampling`으로 verifier 단독 샘플링한 것과 같음)
+    Stochastic,
 }
 
-impl Model {
-    fn forward(&mut self, x: &Tensor, offset: usize) -> Result<Tensor> {
-        match self {
-            Model::Qwen2(m) => m.forward(x, offset).map_err(E::from),
-            Model::Qwen3(m) => m.forward(x, offset).map_err(E::from),
+impl AcceptPolicy {
+    /// 위치별 수용 여부. `logits`: [n, vocab], `draft_tokens`와 `pred_tokens`(argmax)는 길이 n
+    fn accepted(
+        &self,
+        logits: &Tensor,
+        draft_tokens: &[u32],
+        pred_tokens: &[u32],
+    ) -> Result<Vec<bool>> {
+        match *self {
+            AcceptPolicy::Greedy => Ok(draft_tokens
+                .iter()
+                .zip(pred_tokens.iter())
+                .map(|(d, p)| d == p)
+                .collect()),
+            // 확률적 수용은 교정 토큰까지 함께 정해야 하므로 `stochastic_verify`에서 처리
+            AcceptPolicy::Stochastic => Ok(vec![false; draft_tokens.len()]),
+            AcceptPolicy::TopK(k) => {
+                // draft 토큰보다 로짓이 큰 토큰 수 = draft 토큰의 순위 (0이면 argmax)
+                let ids = Tensor::new(draft_tokens, logits.device())?.unsqueeze(1)?;
+                let draft_logits = logits.gather(&ids, 1)?;
+                let ranks = logits
+                    .broadcast_gt(&draft_logits)?
+                    .to_dtype(DType::U32)?
+                    .sum(1)?
+                    .to_vec1::<u32>()?;
+                Ok(ranks
+                    .iter()
+                    .map(|&rank| (rank as usize) < k.max(1))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// 로짓([vocab])에 `config`의 temperature/top-k/top-p/min-p를 적용한 확률 분포를 만듭니다.
+/// greedy 설정이면 argmax 위치만 1인 분포입니다.
+fn distribution(logits: &Tensor, config: &GenerationConfig) -> Result<Vec<f32>> {
+    let logits = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut probs = vec![0f32; logits.len()];
+    if config.is_greedy() {
+        if let Some(i) = logits.iter().position(|&l| l == max) {
+            probs[i] = 1.0;
+        }
+        return Ok(probs);
+    }
+
+    let temperature = config.temperature.unwrap_or(1.0) as f32;
+    for (p, &l) in probs.iter_mut().zip(&logits) {
+        *p = ((l - max) / temperature).exp();
+    }
+    let total: f32 = probs.iter().sum();
+
+    // 확률 내림차순으로 앞에서부터 남길 개수를 정함
+    let mut order: Vec<usize> = (0..probs.len()).collect();
+    order.sort_unstable_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+    let mut keep = order.len();
+    if let Some(k) = config.top_k {
+        keep = keep.min(k.max(1));
+    }
+    if let Some(top_p) = config.top_p {
+        let mut cumulative = 0.0;
+        let n = order
+            .iter()
+            .position(|&i| {
+                cumulative += probs[i] / total;
+                cumulative >= top_p as f32
+            })
+            .map_or(order.len(), |i| i + 1);
+        keep = keep.min(n);
+    }
+    if let Some(min_p) = config.min_p {
+        let floor = probs[order[0]] * min_p as f32;
+        let n = order.iter().take_while(|&&i| probs[i] >= floor).count();
+        keep = keep.min(n.max(1));
+    }
+    for &i in &order[keep..] {
+        probs[i] = 0.0;
+    }
+
+    let kept: f32 = probs.iter().sum();
+    probs.iter_mut().for_each(|p| *p /= kept);
+    Ok(probs)
+}
+
+/// 정규화되지 않아도 되는 가중치 분포에서 토큰 하나를 뽑습니다.
+fn sample(weights: &[f32], rng: &mut StdRng) -> u32 {
+    let total: f32 = weights.iter().sum();
+    let mut u = rng.random::<f32>() * total;
+    for (i, &w) in weights.iter().enumerate() {
+        if u < w {
+            return i as u32;
+        }
+        u -= w;
+    }
+    // 부동소수점 오차로 끝까지 온 경우 마지막 양수 항목
+    weights.iter().rposition(|&w| w > 0.0).unwrap_or(0) as u32
+}
+
+/// draft/verifier 한쪽의 토큰 선택기 (샘플링 설정 + 전용 RNG)
+struct TokenPicker {
+    config: GenerationConfig,
+    rng: StdRng,
+}
+
+impl TokenPicker {
+    fn new(config: GenerationConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// 로짓([vocab])에서 다음 토큰([1, 1] 텐서)을 고릅니다.
+    /// greedy면 GPU에서 argmax만 하고(동기화 없음) 분포는 `None`,
+    /// 샘플링이면 CPU에서 분포를 만들어 뽑고 rejection sampling에 쓸 분포를 함께 돌려줍니다.
+    fn pick(&mut self, logits: &Tensor) -> Result<(Tensor, Option<Vec<f32>>)> {
+        if self.config.is_greedy() {
+            return Ok((logits.argmax(0)?.reshape((1, 1))?, None));
+        }
+        let probs = distribution(logits, &self.config)?;
+        let token = sample(&probs, &mut self.rng);
+        Ok((
+            Tensor::new(&[token], logits.device())?.reshape((1, 1))?,
+            Some(probs),
+        ))
+    }
+}
+
+/// 표준 speculative sampling(rejection sampling)으로 draft 토큰을 앞에서부터 검증합니다.
+///
+/// 위치 i의 draft 토큰 x는 확률 min(1, p(x)/q(x))로 수용하고 (p: verifier 분포, q: draft 분포),
+/// 처음 거절된 위치에서는 max(0, p - q)를 정규화한 분포에서 교정 토큰을 뽑습니다.
+/// 모두 수용되면 마지막 위치의 p에서 보너스 토큰을 뽑습니다.
+/// `logits`: [n + 1, vocab]. `draft_dists[i]`가 `None`이면 draft가 greedy였던 것으로 보고
+/// x에 몰린 one-hot 분포를 씁니다. 반환값은 (수용된 draft 토큰 수, 교정 또는 보너스 토큰)입니다.
+fn stochastic_verify(
+    logits: &Tensor,
+    draft_tokens: &[u32],
+    draft_dists: &[Option<Vec<f32>>],
+    verifier: &mut TokenPicker,
+) -> Result<(usize, u32)> {
+    for (i, &token) in draft_tokens.iter().enumerate() {
+        let p = distribution(&logits.i(i)?, &verifier.config)?;
+        let x = token as usize;
+        let q = draft_dists.get(i).and_then(Option::as_ref);
+        let q_x = q.map_or(1.0, |q| q[x]);
+        if q_x > 0.0 && verifier.rng.random::<f32>() * q_x < p[x] {
+            continue;
+        }
+
+        let mut residual = p.clone();
+        match q {
+            Some(q) => residual
+                .iter_mut()
+                .zip(q)
+                .for_each(|(r, &q)| *r = (*r - q).max(0.0)),
+            None => residual[x] = 0.0,
+        }
+        let next = if residual.iter().any(|&r| r > 0.0) {
+            sample(&residual, &mut verifier.rng)
+        } else {
+            sample(&p, &mut verifier.rng)
+        };
+        return Ok((i, next));
+    }
+
+    let p = distribution(&logits.i(draft_tokens.len())?, &verifier.config)?;
+    Ok((draft_tokens.len(), sample(&p, &mut verifier.rng)))
+}
+
+/// 적응형 draft 윈도우(K) 설정.
+///
+/// 최근 `adjust_window` 스텝의 평균 수용률이 `up_threshold`보다 높으면 K를 1 늘리고,
+/// `down_threshold`보다 낮으면 1 줄입니다. K는 항상 `[min_k, max_k]` 범위로 제한됩니다.
+/// `fixed_k`를 지정하면 적응을 끄고 K를 그 값으로 고정합니다 (벤치마크/디버깅용).
+///
+/// `draft_sampling`은 draft 토큰을 고르는 방식이고(기본 greedy, temperature를 조금 높이면
+/// 후보가 다양해짐), `verifier_sampling`은 `AcceptPolicy::Stochastic`에서 목표로 하는 분포입니다.
+/// Greedy/TopK 수용은 verifier argmax 기준이므로 `verifier_sampling`을 쓰지 않습니다.
+#[derive(Clone, Debug)]
+struct SpeculativeConfig {
+    initial_k: usize,
+    min_k: usize,
+    max_k: usize,
+    adjust_window: usize,
+    up_threshold: f32,
+    down_threshold: f32,
+    fixed_k: Option<usize>,
+    accept: AcceptPolicy,
+    draft_sampling: GenerationConfig,
+    verifier_sampling: GenerationConfig,
+}
+
+impl Default for SpeculativeConfig {
+    fn default() -> Self {
+        Self {
+            initial_k: 3,
+            min_k: 1,
+            max_k: 8,
+            adjust_window: 12,
+            up_threshold: 0.6,
+            down_threshold: 0.4,
+            fixed_k: None,
+            accept: AcceptPolicy::Greedy,
+            draft_sampling: GenerationConfig::greedy(),
+            verifier_sampling: GenerationConfig::greedy(),
+        }
+    }
+}
+
+impl SpeculativeConfig {
+    /// `[min_k, max_k]` 범위 (min_k는 최소 1)
+    fn bounds(&self) -> (usize, usize) {
+        let min_k = self.min_k.max(1);
+        (min_k, self.max_k.max(min_k))
+    }
+
+    /// 루프 시작 시 사용할 K (고정 모드면 고정값, 아니면 클램프된 초기값)
+    fn start_k(&self) -> usize {
+        match self.fixed_k {
+            Some(k) => k.max(1),
+            None => {
+                let (min_k, max_k) = self.bounds();
+                self.initial_k.clamp(min_k, max_k)
+            }
         }
     }
-    // Assumes this returns [Batch, Seq_Len, Vocab]
-    fn forward_speculative(&mut self, x: &Tensor, offset: usize) -> Result<Tensor> {
-        match self {
-            Model::Qwen2(m) => m.forward(x, offset).map_err(E::from),
-            // Model::Qwen3(m) => m.forward_speculative(x, offset).map_err(E::from),
-            Model::Qwen3(m) => m.forward(x, offset).map_err(E::from),
+
+    /// draft/verifier 토큰 선택기. 같은 시드여도 난수열이 겹치지 않도록 verifier 쪽 시드를 바꿉니다.
+    fn pickers(&self) -> (TokenPicker, TokenPicker) {
+        (
+            TokenPicker::new(self.draft_sampling.clone(), self.draft_sampling.seed),
+            TokenPicker::new(
+                self.verifier_sampling.clone(),
+                self.verifier_sampling.seed.wrapping_add(1),
+            ),
+        )
+    }
+
+    /// 윈도우 평균 수용률로 다음 K를 계산합니다. 고정 모드에서는 항상 그대로입니다.
+    fn next_k(&self, current_k: usize, avg: f32) -> usize {
+        if self.fixed_k.is_some() {
+            return current_k;
+        }
+        let (min_k, max_k) = self.bounds();
+        if avg > self.up_threshold && current_k < max_k {
+            current_k + 1
+        } else if avg < self.down_threshold && current_k > min_k {
+            current_k - 1
+        } else {
+            current_k
         }
     }
 }
 
-// ... [ModelType, Engine struct, Engine::new implementations are same as before] ...
-enum ModelType {
-    Qwen2,
-    Qwen3,
+/// `Engine`이 쓰는 모델 연산. 예제는 `QuantizedModel`을, 테스트는 가짜 모델을 씁니다.
+trait KvModel {
+    /// `input`([1, n])을 `offset` 위치에 이어서 forward 합니다. KV 캐시에는 덧붙기만 합니다.
+    fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor>;
+    fn clear_kv_cache(&mut self);
+    /// KV 캐시를 앞의 `len`개 위치만 남기고 자릅니다. 자를 수 없는 모델이면 `Ok(false)`
+    fn truncate_kv_cache(&mut self, len: usize) -> Result<bool>;
+    /// KV 캐시에 들어 있는 위치 수 (알 수 없으면 `None`)
+    fn kv_cache_len(&self) -> Option<usize>;
 }
-struct Engine {
-    model: Model,
+
+impl KvModel for QuantizedModel {
+    fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        Ok(QuantizedModel::forward(self, input, offset)?)
+    }
+
+    fn clear_kv_cache(&mut self) {
+        QuantizedModel::clear_kv_cache(self);
+    }
+
+    fn truncate_kv_cache(&mut self, len: usize) -> Result<bool> {
+        if !self.supports_kv_truncate() {
+            return Ok(false);
+        }
+        QuantizedModel::truncate_kv_cache(self, len)?;
+        Ok(true)
+    }
+
+    fn kv_cache_len(&self) -> Option<usize> {
+        QuantizedModel::kv_cache_len(self)
+    }
+}
+
+struct Engine<M = QuantizedModel> {
+    model: M,
     device: Device,
+    // draft/verifier가 서로 다른 vocab을 쓸 수 있으므로 엔진마다 토크나이저를 가집니다.
+    tokenizer: Tokenizer,
+    // 다음 입력 토큰이 들어갈 위치 (= KV 캐시가 반영하는 확정 시퀀스 길이)
+    pos: usize,
 }
 impl Engine {
-    fn new(repo: &str, model_file: &str, device: &Device, model_type: ModelType) -> Result<Self> {
+    fn new(repo: &str, model_file: &str, tokenizer_repo: &str, device: &Device) -> Result<Self> {
         let api = Api::new()?;
+        let tokenizer_path = api
+            .model(tokenizer_repo.to_string())
+            .get("tokenizer.json")?;
+        let tokenizer = Tokenizer::from_file(tokenizer_path).map_err(E::msg)?;
         let model_path = api.model(repo.to_string()).get(model_file)?;
-        let mut file = std::fs::File::open(&model_path)?;
-        let content = candle_core::quantized::gguf_file::Content::read(&mut file)?;
-        let model = match model_type {
-            ModelType::Qwen2 => Model::Qwen2(Qwen2::from_gguf(content, &mut file, device)?),
-            ModelType::Qwen3 => Model::Qwen3(Qwen3::from_gguf(content, &mut file, device)?),
-        };
+        // GGUF 메타데이터의 아키텍처로 모듈 선택
+        let model = QuantizedModel::load(&model_path, device)?;
         Ok(Self {
             model,
             device: device.clone(),
+            tokenizer,
+            pos: 0,
         })
     }
 }
 
+impl<M: KvModel> Engine<M> {
+    /// KV 캐시를 비우고 위치를 0으로 되돌립니다.
+    fn reset(&mut self) {
+        self.model.clear_kv_cache();
+        self.pos = 0;
+    }
+
+    /// `input`([1, n])을 현재 위치에 이어서 forward 하고 위치를 n만큼 전진시킵니다.
+    /// 마지막 위치의 logits([vocab])를 반환합니다.
+    fn feed(&mut self, input: &Tensor) -> Result<Tensor> {
+        let (_b, seq_len) = input.dims2()?;
+        let logits = self.model.forward(input, self.pos)?;
+        self.pos += seq_len;
+        Ok(logits.squeeze(0)?)
+    }
+
+    /// KV 캐시를 확정 시퀀스 `committed`의 앞 `len`개 위치만 남기도록 되감습니다.
+    /// 캐시는 offset과 무관하게 덧붙기만 하므로, 거절된 토큰의 K/V는 잘라내야 다음 forward에 섞이지 않습니다.
+    /// 캐시를 자를 수 없는 모델(qwen2)은 캐시를 비우고 `committed[..len]`을 다시 prefill 합니다.
+    fn rewind(&mut self, committed: &[u32], len: usize) -> Result<()> {
+        anyhow::ensure!(
+            len <= committed.len(),
+            "invalid rewind: {} positions for a committed length of {}",
+            len,
+            committed.len()
+        );
+        if !self.model.truncate_kv_cache(len)? {
+            self.model.clear_kv_cache();
+            if len > 0 {
+                let input = Tensor::new(&committed[..len], &self.device)?.unsqueeze(0)?;
+                self.model.forward(&input, 0)?;
+            }
+        }
+        self.pos = len;
+        Ok(())
+    }
+
+    /// 확정 시퀀스 `committed` 중 아직 캐시에 없는 마지막 `pending`개 토큰을 넣습니다.
+    /// 그 앞까지로 캐시를 되감은 뒤 넣으므로 끝나면 캐시와 `pos`가 모두 `committed.len()`이 됩니다.
+    fn resync(&mut self, committed: &[u32], pending: usize) -> Result<Tensor> {
+        let start = resync_start(committed.len(), pending)?;
+        self.rewind(committed, start)?;
+        let input = Tensor::new(&committed[start..], &self.device)?.unsqueeze(0)?;
+        self.feed(&input)
+    }
+}
+
+/// verifier prefill: 마지막 확정 토큰은 첫 검증 입력의 첫 토큰으로 다시 넣으므로 그 직전까지만 캐시에 넣습니다.
+fn prefill_verifier<M: KvModel>(verifier: &mut Engine<M>, tokens: &[u32]) -> Result<()> {
+    verifier.reset();
+    if tokens.len() > 1 {
+        let input = Tensor::new(&tokens[..tokens.len() - 1], &verifier.device)?.unsqueeze(0)?;
+        verifier.feed(&input)?;
+    }
+    Ok(())
+}
+
+/// 라운드 결과를 `tokens`에 확정한 뒤 두 엔진의 캐시를 맞춥니다.
+/// verifier는 검증 입력으로 넣은 거절 토큰을 잘라 마지막 확정 토큰 직전까지만 남기고,
+/// draft는 아직 캐시에 없는 마지막 `pending`개 토큰을 넣습니다. 다음 draft 토큰을 고를 logits를 반환합니다.
+fn sync_engines<M: KvModel>(
+    draft: &mut Engine<M>,
+    verifier: &mut Engine<M>,
+    tokens: &[u32],
+    pending: usize,
+) -> Result<Tensor> {
+    verifier.rewind(tokens, tokens.len() - 1)?;
+    draft.resync(tokens, pending)
+}
+
+/// `resync`가 `pending_len`개의 토큰을 다시 넣기 시작할 위치를 계산합니다.
+fn resync_start(committed_len: usize, pending_len: usize) -> Result<usize> {
+    anyhow::ensure!(
+        pending_len != 0 && pending_len <= committed_len,
+        "invalid resync: {} pending tokens for a committed length of {}",
+        pending_len,
+        committed_len
+    );
+    Ok(committed_len - pending_len)
+}
+
+/// 매 라운드 시작 시 draft/verifier 위치와 실제 KV 캐시 길이가 확정 시퀀스와 맞는지 확인합니다.
+/// draft는 확정 토큰을 모두 캐시에 넣은 상태, verifier는 마지막 확정 토큰을 검증 입력의
+/// 첫 토큰으로 다시 넣으므로 한 칸 뒤에 있어야 합니다. 어긋나면 이후 토큰이 모두 오염되므로 즉시 중단합니다.
+/// (캐시 길이를 알 수 없는 모델은 위치만 확인)
+fn check_alignment<M: KvModel>(
+    draft: &Engine<M>,
+    verifier: &Engine<M>,
+    committed_len: usize,
+) -> Result<()> {
+    let draft_cache = draft.model.kv_cache_len();
+    let verifier_cache = verifier.model.kv_cache_len();
+    anyhow::ensure!(
+        draft.pos == committed_len
+            && verifier.pos + 1 == committed_len
+            && draft_cache.is_none_or(|len| len == draft.pos)
+            && verifier_cache.is_none_or(|len| len == verifier.pos),
+        "speculative state drifted: draft_pos={}, draft_cache={:?}, verifier_pos={}, verifier_cache={:?}, committed={}",
+        draft.pos,
+        draft_cache,
+        verifier.pos,
+        verifier_cache,
+        committed_len
+    );
+    Ok(())
+}
+
+/// speculative 라운드 하나의 결과 (`on_step` 콜백으로 매 라운드 전달)
+#[derive(Clone, Copy, Debug)]
+struct StepStats {
+    /// 1부터 시작하는 라운드 번호
+    round: usize,
+    /// 이번 라운드에서 검증한 draft 토큰 수
+    step_k: usize,
+    /// 그중 수용된 수
+    accepted: usize,
+    /// 모두 수용되어 verifier가 보너스 토큰을 추가했는지
+    bonus: bool,
+    /// 다음 라운드에 사용할 K (적응 결과 반영)
+    window_k: usize,
+    /// 지금까지의 누적 수용률 (0.0 ~ 1.0)
+    acceptance_rate: f32,
+}
+
 #[derive(Default)]
 struct PerfStats {
     draft_forward: Duration,
@@ -69,32 +474,208 @@ struct PerfStats {
     verifier_resync_verifier_only: Duration,
 }
 
-fn sync_device(device: &Device) -> Result<()> {
-    match device {
-        Device::Cuda(dev) => dev.synchronize().map_err(E::from),
-        _ => Ok(()),
+/// 두 토크나이저가 같은 vocab(같은 토큰 id 체계)을 쓰는지 확인합니다.
+fn shares_vocab(a: &Tokenizer, b: &Tokenizer) -> bool {
+    a.get_vocab_size(true) == b.get_vocab_size(true) && a.get_vocab(true) == b.get_vocab(true)
+}
+
+/// draft 공간 토큰을 verifier 공간으로 변환합니다 (decode → re-encode).
+/// 재인코딩 결과가 원문으로 되돌아오지 않으면 (예: 멀티바이트 문자가 잘린 경우)
+/// 매핑 불가로 보고 `None`을 반환합니다.
+fn translate_tokens(from: &Tokenizer, to: &Tokenizer, ids: &[u32]) -> Result<Option<Vec<u32>>> {
+    let text = from.decode(ids, false).map_err(E::msg)?;
+    let mapped = to
+        .encode(text.as_str(), false)
+        .map_err(E::msg)?
+        .get_ids()
+        .to_vec();
+    let round_trip = to.decode(&mapped, false).map_err(E::msg)?;
+    if mapped.is_empty() || round_trip != text {
+        return Ok(None);
+    }
+    Ok(Some(mapped))
+}
+
+/// draft와 verifier의 토크나이저가 다를 때의 speculative decoding.
+///
+/// 검증 경계에서 draft 토큰을 verifier 공간으로 변환하며, 변환할 수 없는 draft는
+/// 통째로 거절하고 verifier가 한 토큰을 직접 생성합니다.
+/// 비용: 매 라운드 draft의 KV 캐시를 비우고 확정된 텍스트 전체를 다시 prefill 하므로
+/// 같은 vocab 경로보다 느리고, 토큰 경계가 어긋나는 만큼 수용률도 낮아집니다.
+fn run_speculative_cross(
+    draft: &mut Engine,
+    verifier: &mut Engine,
+    prompt: &str,
+    n_tokens: usize,
+    config: &SpeculativeConfig,
+    on_step: &mut impl FnMut(StepStats),
+) -> Result<()> {
+    println!("\n🚀 Speculative Decoding (Cross-Tokenizer)");
+    println!("Prompt: {}\n---", prompt);
+
+    // 확정된 토큰은 항상 verifier 공간으로 관리합니다.
+    let mut tokens = verifier
+        .tokenizer
+        .encode(prompt, true)
+        .map_err(E::msg)?
+        .get_ids()
+        .to_vec();
+    if tokens.is_empty() {
+        return Err(E::msg("prompt produced no tokens"));
+    }
+    let prompt_len = tokens.len();
+
+    prefill_verifier(verifier, &tokens)?;
+
+    // 재-prefill 비용 때문에 수용률 추정이 부정확하므로 cross 경로는 K를 적응시키지 않습니다.
+    let k_draft = config.start_k();
+    // draft 분포는 verifier 공간으로 옮길 수 없으므로 Stochastic 수용은 draft를 결정적 제안자로 보고
+    // one-hot 분포로 검증합니다 (draft가 greedy일 때만 verifier 단독 샘플링과 정확히 같은 분포).
+    let (mut draft_picker, mut verifier_picker) = config.pickers();
+    let mut generated_cnt = 0;
+    let mut total_drafted = 0;
+    let mut total_accepted = 0;
+    let mut unmappable_rounds = 0;
+    let mut last_printed = 0;
+    let mut round = 0;
+
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+
+    while generated_cnt < n_tokens {
+        // Step 1: 확정 텍스트를 draft 공간으로 다시 인코딩해 prefill 후 k개 drafting
+        let committed = verifier.tokenizer.decode(&tokens, false).map_err(E::msg)?;
+        let draft_prefix = draft
+            .tokenizer
+            .encode(committed.as_str(), false)
+            .map_err(E::msg)?
+            .get_ids()
+            .to_vec();
+        draft.reset();
+        let input = Tensor::new(draft_prefix.as_slice(), &draft.device)?.unsqueeze(0)?;
+        let mut logits = draft.feed(&input)?;
+        let mut drafted = Vec::with_capacity(k_draft);
+        for _ in 0..k_draft {
+            let next = draft_picker
+                .pick(&logits)?
+                .0
+                .i((0, 0))?
+                .to_scalar::<u32>()?;
+            drafted.push(next);
+            let input = Tensor::new(&[next], &draft.device)?.unsqueeze(0)?;
+            logits = draft.feed(&input)?;
+        }
+        sync_device(&draft.device)?;
+
+        // Step 2: 검증 경계에서 verifier 공간으로 변환 (불가하면 draft 전체 거절)
+        let remaining = n_tokens - generated_cnt;
+        let mut proposed = translate_tokens(&draft.tokenizer, &verifier.tokenizer, &drafted)?
+            .unwrap_or_else(|| {
+                unmappable_rounds += 1;
+                Vec::new()
+            });
+        proposed.truncate(remaining.saturating_sub(1));
+        total_drafted += proposed.len();
+
+        // Step 3: verifier 병렬 검증 ([직전 확정 토큰, proposed...])
+        let mut verify_ids = Vec::with_capacity(proposed.len() + 1);
+        verify_ids.push(tokens[tokens.len() - 1]);
+        verify_ids.extend_from_slice(&proposed);
+        let verify_input = Tensor::new(verify_ids.as_slice(), &verifier.device)?.unsqueeze(0)?;
+        let verifier_logits = verifier.feed(&verify_input)?;
+        sync_device(&verifier.device)?;
+        let verifier_logits = if verifier_logits.rank() == 1 {
+            verifier_logits.unsqueeze(0)?
+        } else {
+            verifier_logits
+        };
+        let pred_tokens = verifier_logits.argmax(1)?.to_vec1::<u32>()?;
+
+        let (accepted, next) = if config.accept == AcceptPolicy::Stochastic {
+            stochastic_verify(&verifier_logits, &proposed, &[], &mut verifier_picker)?
+        } else {
+            let accepted = if proposed.is_empty() {
+                0
+            } else {
+                let ref_logits = verifier_logits.narrow(0, 0, proposed.len())?;
+                config
+                    .accept
+                    .accepted(&ref_logits, &proposed, &pred_tokens)?
+                    .iter()
+                    .take_while(|ok| **ok)
+                    .count()
+            };
+            // 불일치 지점의 교정 토큰 또는 전부 수락 시 보너스 토큰
+            let Some(&next) = pred_tokens.get(accepted) else {
+                break;
+            };
+            (accepted, next)
+        };
+        tokens.extend_from_slice(&proposed[..accepted]);
+        tokens.push(next);
+        total_accepted += accepted;
+
+        round += 1;
+        on_step(StepStats {
+            round,
+            step_k: proposed.len(),
+            accepted,
+            bonus: accepted == proposed.len(),
+            window_k: k_draft,
+            acceptance_rate: total_accepted as f32 / total_drafted.max(1) as f32,
+        });
+
+        let advanced = accepted + 1;
+        generated_cnt += advanced;
+        // verifier KV 캐시에 들어간 거절된 draft를 잘라냄
+        verifier.rewind(&tokens, tokens.len() - 1)?;
+
+        let text = verifier
+            .tokenizer
+            .decode(&tokens[prompt_len + last_printed..], true)
+            .map_err(E::msg)?;
+        if !text.is_empty() {
+            print!("{}", text);
+            std::io::stdout().flush()?;
+            last_printed = tokens.len() - prompt_len;
+        }
     }
+
+    println!("\n\nDone.");
+    let rate = if total_drafted == 0 {
+        0.0
+    } else {
+        total_accepted as f32 / total_drafted as f32 * 100.0
+    };
+    println!(
+        "Acceptance Rate (cross-tokenizer, {:?}): {:.2}% | Unmappable drafts: {}",
+        config.accept, rate, unmappable_rounds
+    );
+    Ok(())
 }
 
 fn run_speculative(
     draft: &mut Engine,
     verifier: &mut Engine,
-    tokenizer: &Tokenizer,
     prompt: &str,
     n_tokens: usize,
-    k_draft: usize,
+    config: &SpeculativeConfig,
+    on_step: &mut impl FnMut(StepStats),
 ) -> Result<()> {
     println!("\n🚀 Speculative Decoding (GPU-Resident Optimization)");
     println!("Prompt: {}\n---", prompt);
 
+    if !shares_vocab(&draft.tokenizer, &verifier.tokenizer) {
+        return run_speculative_cross(draft, verifier, prompt, n_tokens, config, on_step);
+    }
+    let tokenizer = verifier.tokenizer.clone();
+
     let mut tokens = tokenizer
         .encode(prompt, true)
         .map_err(E::msg)?
         .get_ids()
         .to_vec();
     let mut generated_cnt = 0;
-    let mut draft_pos = 0;
-    let mut verifier_pos = 0;
     let mut last_printed = 0;
     let mut total_drafted = 0;
     let mut total_draft_accepted = 0;
@@ -105,12 +686,11 @@ fn run_speculative(
     let mut verifier_forward_count_total: usize = 0;
     let mut verifier_forward_speculative_count: usize = 0;
 
-    let mut current_k = k_draft.max(1);
-    const MAX_K: usize = 8;
-    const MIN_K: usize = 1;
-    const ADJUST_WINDOW: usize = 12;
+    let mut current_k = config.start_k();
+    let (mut draft_picker, mut verifier_picker) = config.pickers();
     let mut adjust_acc_sum = 0f32;
     let mut adjust_cnt = 0usize;
+    let mut round = 0;
 
     print!("{}", prompt);
     std::io::stdout().flush()?;
@@ -121,31 +701,27 @@ fn run_speculative(
 
     // Draft Prefill
     let t_pre = Instant::now();
-    let draft_prefill_logits = draft.model.forward(&input, 0)?;
+    draft.reset();
+    let mut last_draft_logits = draft.feed(&input)?;
     sync_device(&draft.device)?;
     stats.draft_forward += t_pre.elapsed();
 
-    let mut last_draft_logits = draft_prefill_logits.squeeze(0)?;
-    // [Optimized] Keep token on GPU to avoid Sync
-    let mut draft_init_token_tensor = last_draft_logits.argmax(0)?.reshape((1, 1))?;
-
     // 🔥 중요: 첫 턴의 Verifier 결과(Logits)를 저장해둬야 함 (첫 Draft 검증용)
     // let t_pre_v = Instant::now();
     // Extract the last token from `input` as a [1, 1] tensor on the verifier device
     let mut bonus_token_tensor = input
         .narrow(1, tokens.len().saturating_sub(1), 1)?
         .reshape((1, 1))?;
-    let _ = verifier.model.forward(&input, 0)?;
+    prefill_verifier(verifier, &tokens)?;
     verifier_forward_count_total += 1;
     // sync_device(&verifier.device)?;
     // stats.verifier_chunk += t_pre_v.elapsed();
 
     // let mut last_verifier_logits = logits.squeeze(0)?; // [vocab]
 
-    draft_pos += tokens.len();
-    verifier_pos += tokens.len() - 1;
-
     while generated_cnt < n_tokens {
+        check_alignment(draft, verifier, tokens.len())?;
+
         let remaining = n_tokens - generated_cnt;
         let step_k = remaining.min(current_k).max(1);
 
@@ -155,17 +731,18 @@ fn run_speculative(
         // CPU 대기(Sync) 없이 GPU 안에서만 텐서를 돌립니다.
         let t_draft = Instant::now();
 
-        // 1. 초기 토큰 설정 (GPU Resident)
-        // draft_init_token_tensor is already [1, 1] on GPU
-        let mut current_input = draft_init_token_tensor.clone(); //굳이 필요없을 수도
+        // 1. 초기 토큰 설정 (greedy면 GPU Resident, 샘플링이면 토큰마다 CPU에서 분포 계산)
+        // 직전 라운드의 마지막 draft logits에서 첫 draft 토큰([1, 1])을 고름
+        let (mut current_input, first_dist) = draft_picker.pick(&last_draft_logits)?;
+        // rejection sampling용 draft 분포 (greedy면 None)
+        let mut draft_dists = vec![first_dist];
 
         // [Optimized] Pre-allocate verify_input_gpu
         // We need to store [init, draft_1, draft_2, ...]
         // Total length = step_k
         // We can use a pre-allocated tensor and update it.
         // Note: DType must match. Tokenizer produces u32.
-        // But draft_init_token_tensor is u32?
-        // Let's check dtype.
+        // argmax와 Tensor::new(&[u32]) 모두 u32이므로 첫 토큰의 dtype을 그대로 사용
         let dtype = current_input.dtype();
         let mut verify_input_gpu = Tensor::zeros((1, step_k + 1), dtype, &draft.device)?;
 
@@ -175,10 +752,11 @@ fn run_speculative(
 
         for i in 1..step_k {
             // A. Forward (Async Kernel Launch)
-            let logits = draft.model.forward(&current_input, draft_pos)?;
+            let logits = draft.feed(&current_input)?;
 
-            // B. Argmax (GPU Operation)
-            let next_token_tensor = logits.squeeze(0)?.argmax(0)?.reshape((1, 1))?;
+            // B. 토큰 선택 (greedy면 GPU argmax)
+            let (next_token_tensor, dist) = draft_picker.pick(&logits)?;
+            draft_dists.push(dist);
 
             // C. 저장 (In-place update)
             verify_input_gpu =
@@ -186,8 +764,6 @@ fn run_speculative(
 
             // D. 다음 입력 준비
             current_input = next_token_tensor;
-
-            draft_pos += 1;
         }
         sync_device(&draft.device)?;
         stats.draft_forward += t_draft.elapsed();
@@ -200,17 +776,13 @@ fn run_speculative(
         // verify_input_gpu is already ready!
 
         // 2. 현재 pos에서 forward
-        let verifier_logits = verifier
-            .model
-            .forward_speculative(&verify_input_gpu, verifier_pos)?;
+        let verifier_logits = verifier.feed(&verify_input_gpu)?; // [step_k, vocab]
         verifier_forward_count_total += 1;
         verifier_forward_speculative_count += 1;
 
         sync_device(&verifier.device)?;
         stats.verifier_chunk += t_verify.elapsed();
 
-        let verifier_logits = verifier_logits.squeeze(0)?; // [step_k, vocab]
-
         // ================================================================
         // Step 3: Comparison Loop (Vectorized Logic)
         // ================================================================
@@ -228,11 +800,24 @@ fn run_speculative(
         let pred_tokens = ref_logits.argmax(1)?;
         let pred_tokens = pred_tokens.to_vec1::<u32>()?;
 
-        // 최초 불일치 지점 찾기
-        let mismatch_idx = draft_tokens
-            .iter()
-            .zip(pred_tokens.iter())
-            .position(|(draft_tok, pred_tok)| draft_tok != pred_tok);
+        // 최초 거절 지점 찾기 (TopK면 argmax가 아니어도 상위 k개 안이면 수용)
+        // Stochastic이면 교정/보너스 토큰도 verifier 분포에서 샘플링해서 함께 결정
+        let (mismatch_idx, sampled_next) = if config.accept == AcceptPolicy::Stochastic {
+            let (accepted, next) = stochastic_verify(
+                &verifier_logits,
+                &draft_tokens,
+                &draft_dists,
+                &mut verifier_picker,
+            )?;
+            ((accepted < step_k).then_some(accepted), Some(next))
+        } else {
+            let mismatch_idx = config
+                .accept
+                .accepted(&ref_logits, &draft_tokens, &pred_tokens)?
+                .iter()
+                .position(|ok| !ok);
+            (mismatch_idx, None)
+        };
 
         let mut accepted_from_draft = 0usize;
         let mut positions_advanced;
@@ -245,8 +830,8 @@ fn run_speculative(
                     tokens.extend_from_slice(&draft_tokens[..idx]);
                     accepted_from_draft += idx;
                 }
-                // 불일치 지점에서는 Verifier 토큰으로 교체
-                let replace_tok = pred_tokens[idx];
+                // 거절 지점에서는 Verifier 토큰(argmax 또는 잔여 분포 샘플)으로 교체
+                let replace_tok = sampled_next.unwrap_or(pred_tokens[idx]);
                 tokens.push(replace_tok);
                 final_token = Some(replace_tok);
             }
@@ -267,9 +852,11 @@ fn run_speculative(
         let t_resync = Instant::now();
         if final_token.is_none() {
             // All Accepted! -> Bonus Token
-            let bonus_logits = verifier_logits.i(step_k)?;
-            let bonus_token = bonus_logits.argmax(0)?.to_scalar::<u32>()?;
-            bonus_token_tensor = bonus_logits.argmax(0)?.reshape((1, 1))?;
+            let bonus_token = match sampled_next {
+                Some(token) => token,
+                None => verifier_logits.i(step_k)?.argmax(0)?.to_scalar::<u32>()?,
+            };
+            bonus_token_tensor = Tensor::new(&[bonus_token], &verifier.device)?.reshape((1, 1))?;
 
             tokens.push(bonus_token);
             positions_advanced += 1;
@@ -283,36 +870,19 @@ fn run_speculative(
             // stats.verifier_resync_verifier_only += t_verifier_only.elapsed();
             // last_verifier_logits = logits.squeeze(0)?;
 
-            // Draft 모델 싱크 맞추기
-            let len = tokens.len();
-            let last_two = &tokens[len - 2..len];
-            let input = Tensor::new(last_two, &draft.device)?.unsqueeze(0)?;
-            last_draft_logits = draft.model.forward(&input, draft_pos)?;
+            // Draft 모델 싱크 맞추기: 마지막 draft 토큰과 보너스 토큰은 아직 draft 캐시에 없음
+            last_draft_logits = sync_engines(draft, verifier, &tokens, 2)?;
             sync_device(&draft.device)?;
-            last_draft_logits = last_draft_logits.squeeze(0)?;
-            // [Optimized] Keep as Tensor
-            draft_init_token_tensor = last_draft_logits.argmax(0)?.reshape((1, 1))?;
-            draft_pos += 2;
         } else {
             // Rejected -> Correction & Sync
-            let accepted_idx = accepted_from_draft;
-
             // Draft 모델 싱크 맞추기 & 다음 턴 검증용 Logit 계산
             let correct_token = final_token.unwrap();
             let input = Tensor::new(&[correct_token], &verifier.device)?.unsqueeze(0)?;
 
             // Sync Draft Model State (not included in verifier-only timing)
-            let draft_input = input.clone();
-            last_draft_logits = draft
-                .model
-                .forward(&draft_input, verifier_pos + accepted_idx)?;
+            // 교정 토큰의 위치는 확정 시퀀스의 마지막 (= verifier.pos + accepted_from_draft + 1)
+            last_draft_logits = sync_engines(draft, verifier, &tokens, 1)?;
             sync_device(&draft.device)?;
-            last_draft_logits = last_draft_logits.squeeze(0)?;
-            // [Optimized] Keep as Tensor
-            draft_init_token_tensor = last_draft_logits.argmax(0)?.reshape((1, 1))?;
-
-            // Reset Draft Pos to correct position
-            draft_pos = verifier_pos + accepted_idx + 1;
 
             // Verifier: 다음 턴 검증용 Logit 계산 (verifier-only timing)
             bonus_token_tensor = input;
@@ -331,7 +901,6 @@ fn run_speculative(
         total_draft_accepted += accepted_from_draft;
         total_positions_accepted += positions_advanced;
 
-        verifier_pos += positions_advanced;
         generated_cnt += positions_advanced;
 
         // Print
@@ -347,17 +916,18 @@ fn run_speculative(
         let acc_ratio = accepted_from_draft as f32 / step_k as f32;
         adjust_acc_sum += acc_ratio;
         adjust_cnt += 1;
-        if adjust_cnt == ADJUST_WINDOW {
-            let avg = adjust_acc_sum / ADJUST_WINDOW as f32;
-            if avg > 0.6 && current_k < MAX_K {
-                current_k += 1;
+        if adjust_cnt >= config.adjust_window.max(1) {
+            let avg = adjust_acc_sum / adjust_cnt as f32;
+            let next_k = config.next_k(current_k, avg);
+            if next_k > current_k {
+                current_k = next_k;
                 println!(
                     "\n⬆️ Increasing speculative window to {} (avg acceptance {:.0}%)",
                     current_k,
                     avg * 100.0
                 );
-            } else if avg < 0.4 && current_k > MIN_K {
-                current_k -= 1;
+            } else if next_k < current_k {
+                current_k = next_k;
                 println!(
                     "\n⬇️ Decreasing speculative window to {} (avg acceptance {:.0}%)",
                     current_k,
@@ -367,13 +937,23 @@ fn run_speculative(
             adjust_acc_sum = 0.0;
             adjust_cnt = 0;
         }
+
+        round += 1;
+        on_step(StepStats {
+            round,
+            step_k,
+            accepted: accepted_from_draft,
+            bonus: final_token.is_none(),
+            window_k: current_k,
+            acceptance_rate: total_draft_accepted as f32 / total_drafted as f32,
+        });
     }
 
     println!("\n\nDone.");
     let rate = (total_draft_accepted as f32 / total_drafted as f32) * 100.0;
     println!(
-        "Acceptance Rate (draft only): {:.2}% | Bonus tokens: {} | Total advanced: {}",
-        rate, total_bonus, total_positions_accepted
+        "Acceptance Rate (draft only, {:?}): {:.2}% | Bonus tokens: {} | Total advanced: {}",
+        config.accept, rate, total_bonus, total_positions_accepted
     );
     println!(
         "⏱️ draft {:.2?} | verifier-batch {:.2?} | verifier-sync-total {:.2?} | verifier-sync-verifier_only {:.2?}",
@@ -393,34 +973,213 @@ fn run_speculative(
 async fn main() -> Result<()> {
     println!("🔥 Speculative Decoding (Batch Verification + GPU Resident)");
 
-    let device = Device::new_cuda(0)?;
-    let api = Api::new()?;
-
-    let tokenizer_path = api
-        .model("Qwen/Qwen3-14B".to_string())
-        .get("tokenizer.json")?;
-    let tokenizer = Tokenizer::from_file(tokenizer_path).map_err(E::msg)?;
+    let device = suprascalar::util::select_device()?;
 
     let mut verifier = Engine::new(
         "unsloth/Qwen3-14B-GGUF",
         "Qwen3-14B-Q4_K_M.gguf",
+        "Qwen/Qwen3-14B",
         &device,
-        ModelType::Qwen3,
     )?;
 
     let mut draft = Engine::new(
         "unsloth/Qwen3-0.6B-GGUF",
         "Qwen3-0.6B-Q4_K_M.gguf",
+        "Qwen/Qwen3-0.6B",
         &device,
-        ModelType::Qwen3,
     )?;
 
     let prompt = "Explain the difference between Mutex and RwLock in Rust.";
     let start = std::time::Instant::now();
 
-    // k_draft는 초기값일 뿐이며 루프 내부에서 수용률에 따라 자동 조정됩니다.
-    run_speculative(&mut draft, &mut verifier, &tokenizer, prompt, 1000, 3)?;
+    // initial_k는 초기값일 뿐이며 루프 내부에서 수용률에 따라 [min_k, max_k] 범위로 조정됩니다.
+    // K를 고정하려면 `fixed_k: Some(4)`처럼 지정하세요.
+    // SPEC_ACCEPT_TOPK=3 처럼 지정하면 top-k 수용으로 실행해 Greedy 기준선과 수용률/속도를 비교할 수 있습니다.
+    // SPEC_DRAFT_TEMP=0.9 / SPEC_VERIFIER_TEMP=0.7 처럼 temperature를 지정하면 Stochastic 수용으로 실행해
+    // draft 쪽 temperature가 수용률에 주는 영향을 실험할 수 있습니다 (둘 중 없는 쪽은 greedy).
+    // 토크나이저가 다르면 (예: 비-Qwen draft) 자동으로 cross-tokenizer 경로를 사용합니다.
+    let temperature = |name: &str| -> Option<f64> { std::env::var(name).ok()?.parse().ok() };
+    let draft_temp = temperature("SPEC_DRAFT_TEMP");
+    let verifier_temp = temperature("SPEC_VERIFIER_TEMP");
+    let sampling = |temperature: Option<f64>| GenerationConfig {
+        temperature,
+        top_p: None,
+        ..GenerationConfig::default()
+    };
+    let accept = if draft_temp.is_some() || verifier_temp.is_some() {
+        AcceptPolicy::Stochastic
+    } else {
+        std::env::var("SPEC_ACCEPT_TOPK")
+            .ok()
+            .and_then(|k| k.parse().ok())
+            .map_or(AcceptPolicy::Greedy, AcceptPolicy::TopK)
+    };
+    let config = SpeculativeConfig {
+        accept,
+        draft_sampling: sampling(draft_temp),
+        verifier_sampling: sampling(verifier_temp),
+        ..SpeculativeConfig::default()
+    };
+    // 라운드마다 호출됨 (대시보드/적응형 K 수렴 그래프용). 여기서는 로그만 남깁니다.
+    let mut rounds = Vec::new();
+    run_speculative(
+        &mut draft,
+        &mut verifier,
+        prompt,
+        1000,
+        &config,
+        &mut |step: StepStats| rounds.push(step),
+    )?;
+    if let Some(last) = rounds.last() {
+        let full_rounds = rounds.iter().filter(|step| step.bonus).count();
+        let accepted: usize = rounds.iter().map(|step| step.accepted).sum();
+        let drafted: usize = rounds.iter().map(|step| step.step_k).sum();
+        println!(
+            "Rounds: {} (fully accepted: {}) | accepted {}/{} | final K: {} | rolling acceptance: {:.2}%",
+            last.round,
+            full_rounds,
+            accepted,
+            drafted,
+            last.window_k,
+            last.acceptance_rate * 100.0
+        );
+    }
 
     println!("\n✅ Total time: {:.2?}", start.elapsed());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokenizers::models::wordlevel::WordLevel;
+
+    /// `ConcatKvCache`처럼 offset과 무관하게 캐시에 덧붙기만 하는 가짜 모델
+    struct AppendOnlyModel {
+        cache_len: usize,
+        truncatable: bool,
+    }
+
+    impl KvModel for AppendOnlyModel {
+        fn forward(&mut self, input: &Tensor, _offset: usize) -> Result<Tensor> {
+            let (_b, seq_len) = input.dims2()?;
+            self.cache_len += seq_len;
+            Ok(Tensor::zeros((1, 8), DType::F32, &Device::Cpu)?)
+        }
+
+        fn clear_kv_cache(&mut self) {
+            self.cache_len = 0;
+        }
+
+        fn truncate_kv_cache(&mut self, len: usize) -> Result<bool> {
+            if !self.truncatable {
+                return Ok(false);
+            }
+            anyhow::ensure!(len <= self.cache_len, "cannot grow the cache");
+            self.cache_len = len;
+            Ok(true)
+        }
+
+        fn kv_cache_len(&self) -> Option<usize> {
+            Some(self.cache_len)
+        }
+    }
+
+    fn engine(truncatable: bool) -> Engine<AppendOnlyModel> {
+        Engine {
+            model: AppendOnlyModel {
+                cache_len: 0,
+                truncatable,
+            },
+            device: Device::Cpu,
+            tokenizer: Tokenizer::new(WordLevel::default()),
+            pos: 0,
+        }
+    }
+
+    fn input(ids: &[u32]) -> Tensor {
+        Tensor::new(ids, &Device::Cpu)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap()
+    }
+
+    /// `run_speculative`의 prefill: draft는 프롬프트 전체, verifier는 마지막 토큰 직전까지
+    fn prefilled(
+        truncatable: bool,
+        prompt: &[u32],
+    ) -> (Engine<AppendOnlyModel>, Engine<AppendOnlyModel>) {
+        let mut draft = engine(truncatable);
+        let mut verifier = engine(truncatable);
+        draft.reset();
+        draft.feed(&input(prompt)).unwrap();
+        prefill_verifier(&mut verifier, prompt).unwrap();
+        (draft, verifier)
+    }
+
+    /// 라운드 하나: draft가 k-1개를 캐시에 넣고, verifier가 [마지막 확정 토큰, draft k개]를 검증한 뒤
+    /// draft `accepted`개와 교정/보너스 토큰 하나를 확정합니다.
+    fn round(
+        draft: &mut Engine<AppendOnlyModel>,
+        verifier: &mut Engine<AppendOnlyModel>,
+        tokens: &mut Vec<u32>,
+        k: usize,
+        accepted: usize,
+    ) {
+        for _ in 1..k {
+            draft.feed(&input(&[1])).unwrap();
+        }
+        verifier.feed(&input(&vec![1; k + 1])).unwrap();
+        tokens.extend(std::iter::repeat_n(2, accepted + 1));
+        let pending = if accepted == k { 2 } else { 1 };
+        sync_engines(draft, verifier, tokens, pending).unwrap();
+    }
+
+    #[test]
+    fn caches_stay_aligned_across_accepted_and_rejected_rounds() {
+        for truncatable in [true, false] {
+            let mut tokens = vec![3; 5];
+            let (mut draft, mut verifier) = prefilled(truncatable, &tokens);
+            check_alignment(&draft, &verifier, tokens.len()).unwrap();
+
+            for (k, accepted) in [(4, 4), (4, 1), (4, 0), (1, 1), (3, 2)] {
+                round(&mut draft, &mut verifier, &mut tokens, k, accepted);
+                check_alignment(&draft, &verifier, tokens.len())
+                    .unwrap_or_else(|e| panic!("k={k}, accepted={accepted}: {e}"));
+            }
+        }
+    }
+
+    #[test]
+    fn detects_stale_cache_entries() {
+        let tokens = vec![3; 5];
+
+        // 위치만 되돌리고 캐시를 자르지 않은 draft (rejected K/V가 남은 상태)
+        let (mut draft, verifier) = prefilled(true, &tokens);
+        draft.feed(&input(&[1, 1])).unwrap();
+        draft.pos = tokens.len();
+        let err = check_alignment(&draft, &verifier, tokens.len()).unwrap_err();
+        assert!(err.to_string().contains("draft_cache=Some(7)"), "{err}");
+
+        // 검증 입력을 넣은 뒤 위치만 옮긴 verifier
+        let (draft, mut verifier) = prefilled(true, &tokens);
+        verifier.feed(&input(&[1, 1, 1])).unwrap();
+        verifier.pos = tokens.len() - 1;
+        let err = check_alignment(&draft, &verifier, tokens.len()).unwrap_err();
+        assert!(err.to_string().contains("verifier_cache=Some(7)"), "{err}");
+
+        // 위치 자체가 어긋난 경우
+        let (mut draft, verifier) = prefilled(true, &tokens);
+        draft.pos += 1;
+        let err = check_alignment(&draft, &verifier, tokens.len()).unwrap_err();
+        assert!(err.to_string().contains("draft_pos=6"), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_resync() {
+        assert!(resync_start(4, 0).is_err());
+        assert!(resync_start(2, 3).is_err());
+        assert_eq!(resync_start(4, 4).unwrap(), 0);
+        assert_eq!(resync_start(9, 2).unwrap(), 7);
+    }
+}
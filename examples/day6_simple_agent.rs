@@ -1,15 +1,13 @@
 use suprascalar::{Agent, CandleQwen, SuprascalarError};
 
 fn main() -> Result<(), SuprascalarError> {
-    // 1. Setup paths
-    let repo = "unsloth/Qwen3-14B-GGUF";
-    let model_file = "Qwen3-14B-Q4_K_M.gguf";
-    let tokenizer_repo = "Qwen/Qwen3-14B";
+    // 1. Setup model (registered in ModelRegistry)
+    let model_name = "qwen3-14b";
 
     println!(">>> Initializing Suprascalar...");
 
     // 2. Load backend with custom error handling
-    let backend = match CandleQwen::new(repo, model_file, tokenizer_repo) {
+    let backend = match CandleQwen::from_name(model_name) {
         Ok(b) => Box::new(b),
         Err(SuprascalarError::Io(e)) => {
             eprintln!("File not found: {}", e);
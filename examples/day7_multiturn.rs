@@ -2,15 +2,13 @@ use std::io::{self, Write};
 use suprascalar::{Agent, CandleQwen, SuprascalarError};
 
 fn main() -> Result<(), SuprascalarError> {
-    // 1. 모델 설정 (day6_simple_agent와 동일)
-    let repo = "unsloth/Qwen3-14B-GGUF";
-    let model_file = "Qwen3-14B-Q4_K_M.gguf";
-    let tokenizer_repo = "Qwen/Qwen3-14B";
+    // 1. 모델 설정 (ModelRegistry에 등록된 이름, day6_simple_agent와 동일)
+    let model_name = "qwen3-14b";
 
     println!(">>> Loading Model (This may take a while)...");
 
     // 2. 백엔드 초기화
-    let backend = match CandleQwen::new(repo, model_file, tokenizer_repo) {
+    let backend = match CandleQwen::from_name(model_name) {
         Ok(b) => Box::new(b),
         Err(SuprascalarError::Io(e)) => {
             eprintln!("Failed to load model files: {}", e);
@@ -1,16 +1,17 @@
 use std::io::{self, Write};
-use suprascalar::{CandleQwen, SuprascalarError, agents::qwen_agent::Agent};
+use suprascalar::{Agent, CandleQwen, SuprascalarError};
 
 fn main() -> Result<(), SuprascalarError> {
-    // 1. 모델 설정 (day6_simple_agent와 동일)
-    let repo = "unsloth/Qwen3-14B-GGUF";
-    let model_file = "Qwen3-14B-Q4_K_M.gguf";
-    let tokenizer_repo = "Qwen/Qwen3-14B";
+    // 라이브러리 로그(Docker 대체 경고, 도구/생성 span 등)를 stderr로 출력
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
+
+    // 1. 모델 설정 (ModelRegistry에 등록된 이름, day6_simple_agent와 동일)
+    let model_name = "qwen3-14b";
 
     println!(">>> Loading Model (This may take a while)...");
 
     // 2. 백엔드 초기화
-    let backend = match CandleQwen::new(repo, model_file, tokenizer_repo) {
+    let backend = match CandleQwen::from_name(model_name) {
         Ok(b) => Box::new(b),
         Err(SuprascalarError::Io(e)) => {
             eprintln!("Failed to load model files: {}", e);
@@ -21,7 +22,8 @@ fn main() -> Result<(), SuprascalarError> {
 
     // 3. 에이전트 생성
     // Agent 내부의 'history' 벡터가 대화 내용을 저장합니다.
-    let docker_tool = suprascalar::tools::docker::DockerShell::new()?;
+    // Docker 데몬이 없으면 (예: CI) 경고와 함께 호스트 셸로 대체됩니다.
+    let docker_tool = suprascalar::tools::docker::DockerShell::new_or_host()?;
     let mut agent = Agent::builder(
         "Suprascalar",
         backend,
@@ -75,6 +77,14 @@ fn main() -> Result<(), SuprascalarError> {
                 // 여기서 오래된 기억을 지우는 로직(Memory Management)을 추가할 수 있습니다.
                 break;
             }
+            Err(SuprascalarError::MaxTurnsExceeded { turns, partial }) => {
+                // 도구 작업은 진행됐으므로 지금까지의 결과라도 보여줌
+                println!("{}", partial);
+                eprintln!(
+                    "\n[Warning] Stopped after {} turns without a final answer.",
+                    turns
+                );
+            }
             Err(e) => eprintln!("\n[Error] {}", e),
         }
 
@@ -0,0 +1,73 @@
+use super::qwen_agent::{Message, Role};
+
+/// 메시지 목록을 모델 입력 문자열로 조립할 때 쓰는 특수 토큰/역할 이름
+/// 기본값은 Qwen ChatML (`<|im_start|>role\n...<|im_end|>\n`)입니다.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChatTemplate {
+    /// 프롬프트 맨 앞에 한 번 붙는 토큰 (Qwen은 없음)
+    pub bos: String,
+    /// 메시지 시작. `{role}`은 역할 이름으로 치환됩니다.
+    pub role_open: String,
+    /// 메시지 끝
+    pub role_close: String,
+    /// 모델이 이어서 생성할 assistant 턴의 시작 (`{role}` 치환 없음)
+    pub generation_prompt: String,
+    /// 보조 호출(요약 등)에서 추론을 건너뛰도록 generation prompt 뒤에 붙이는 프리필
+    pub no_think_prefill: String,
+    pub system_role: String,
+    pub user_role: String,
+    pub assistant_role: String,
+    pub function_role: String,
+}
+
+impl Default for ChatTemplate {
+    fn default() -> Self {
+        Self::chatml()
+    }
+}
+
+impl ChatTemplate {
+    /// Qwen ChatML
+    pub fn chatml() -> Self {
+        Self {
+            bos: String::new(),
+            role_open: "<|im_start|>{role}\n".to_string(),
+            role_close: "<|im_end|>\n".to_string(),
+            generation_prompt: "<|im_start|>assistant\n".to_string(),
+            no_think_prefill: "<think>\n\n</think>\n\n".to_string(),
+            system_role: "system".to_string(),
+            user_role: "user".to_string(),
+            assistant_role: "assistant".to_string(),
+            function_role: "function".to_string(),
+        }
+    }
+
+    pub fn role_name(&self, role: &Role) -> &str {
+        match role {
+            Role::System => &self.system_role,
+            Role::User => &self.user_role,
+            Role::Assistant => &self.assistant_role,
+            Role::Function => &self.function_role,
+        }
+    }
+
+    /// 메시지 하나를 렌더링합니다.
+    pub fn render_turn(&self, role: &Role, content: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.role_open.replace("{role}", self.role_name(role)),
+            content,
+            self.role_close
+        )
+    }
+
+    /// 메시지 목록 전체를 렌더링하고 generation prompt를 붙입니다.
+    pub fn render(&self, messages: &[Message]) -> String {
+        let mut prompt = self.bos.clone();
+        for msg in messages {
+            prompt.push_str(&self.render_turn(&msg.role, &msg.content_as_string()));
+        }
+        prompt.push_str(&self.generation_prompt);
+        prompt
+    }
+}
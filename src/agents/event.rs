@@ -0,0 +1,25 @@
+use serde_json::Value;
+
+/// ReAct 루프 진행 상황을 외부(로깅, UI)에 알리기 위한 이벤트
+#[derive(Clone, Debug, PartialEq)]
+pub enum AgentEvent {
+    /// 계획 모드에서 첫 턴 전에 생성한 계획
+    Plan(String),
+    /// 새 턴 시작 (1부터 시작)
+    TurnStarted { turn: usize },
+    /// 생성 중인 응답 텍스트 조각 (`Agent::chat_stream`에서만, 백엔드가 지원하는 경우)
+    Token(String),
+    /// 모델의 원본 응답 텍스트
+    ModelResponse(String),
+    /// 도구 호출 직전
+    ToolCall { name: String, args: Value },
+    /// 도구 실행 결과 (히스토리에 들어가는 관찰 결과)
+    ToolResult { name: String, output: String },
+    /// `OverflowPolicy`에 따라 오래된 메시지를 정리함 (제거/요약된 메시지 수)
+    ContextCompacted { removed: usize },
+    /// 루프 종료 시 최종 답변
+    FinalAnswer(String),
+}
+
+/// 이벤트 콜백 타입
+pub type EventFn = Box<dyn FnMut(AgentEvent)>;
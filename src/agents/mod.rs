@@ -1 +1,6 @@
+pub mod chat_template;
+pub mod event;
+pub mod prompt_format;
 pub mod qwen_agent;
+pub(crate) mod tool_cache;
+pub mod tool_policy;
@@ -0,0 +1,1250 @@
+use super::qwen_agent::{ContentItem, FunctionCall, Message, Role};
+use crate::error::Result;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::env;
+use std::sync::LazyLock;
+
+// NousFnCallPrompt 포맷 상수
+const FN_CALL_TEMPLATE: &str = r#"# Tools
+
+You may call one or more functions to assist with the user query.
+
+You are provided with function signatures within <tools></tools> XML tags:
+<tools>
+{tool_descs}
+</tools>
+
+For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:
+<tool_call>
+{{\"name\": <function-name>, \"arguments\": <args-json-object>}}
+</tool_call>"#;
+
+const FN_CALL_TEMPLATE_WITH_CI: &str = r#"# Tools
+
+You may call one or more functions to assist with the user query.
+
+You are provided with function signatures within <tools></tools> XML tags:
+<tools>
+{tool_descs}
+</tools>
+
+For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:
+<tool_call>
+{{\"name\": <function-name>, \"arguments\": <args-json-object>}}
+</tool_call>
+For code parameters, use placeholders first, and then put the code within <code></code> XML tags, such as:
+<tool_call>
+{{\"name\": <function-name>, \"arguments\": {{\"code\": \"\"}}}}
+<code>
+Here is the code.
+</code>
+</tool_call>"#;
+
+const CODE_TOOL_PATTERN: &str = "code_interpreter";
+
+// 응답마다 쓰는 파싱용 정규식 (한 번만 컴파일)
+static FN_NAME_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#""name"\s*:\s*"((?:[^"\\]|\\.)*)""#).expect("valid function name regex")
+});
+static FN_ARGUMENTS_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#""arguments"\s*:\s*"#).expect("valid arguments regex"));
+static FENCED_BLOCK_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)```(?:json)?[ \t]*\n(.*?)```").expect("valid fenced block regex")
+});
+static JSON_ACTION_KEY_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"["']tool["']\s*:"#).expect("valid tool key regex"));
+
+/// 모델 계열별 도구 호출 프롬프트 형식
+/// ReAct 루프는 이 트레이트를 통해서만 도구 섹션 렌더링과 호출 파싱을 수행합니다.
+pub trait PromptFormat {
+    /// 도구 목록을 시스템 프롬프트에 들어갈 섹션으로 렌더링합니다.
+    fn render_tools(&self, tools: &[FunctionDescriptor]) -> Option<String>;
+
+    /// 히스토리(함수 호출/결과 포함)를 모델 입력용 메시지로 변환합니다.
+    fn preprocess(&self, messages: &[Message], tool_system: Option<&str>) -> Result<Vec<Message>>;
+
+    /// 모델 응답을 함수 호출 구조로 역변환합니다.
+    fn postprocess(&self, messages: Vec<Message>) -> Result<Vec<Message>>;
+
+    /// 응답이 도구 호출을 시도한 흔적을 담고 있는지 검사합니다.
+    /// `postprocess`가 호출을 하나도 찾지 못했는데 `true`라면 깨진 호출로 보고 재출력을 요청합니다.
+    fn looks_like_tool_call(&self, _text: &str) -> bool {
+        false
+    }
+
+    /// 도구 호출 하나가 끝났음을 나타내는 문자열 (예: `</tool_call>`).
+    /// 생성 중 이 문자열에서 멈추면 모델이 호출 뒤에 관찰 결과를 지어내기 전에 도구를 실행할 수 있습니다.
+    /// 끝 표시가 없는 포맷은 `None`입니다.
+    fn tool_call_end(&self) -> Option<&str> {
+        None
+    }
+
+    /// 도구 섹션 템플릿을 사용자 지정 문구로 바꿉니다. `{tool_descs}` 자리에 도구 목록이 들어갑니다.
+    /// 템플릿 교체를 지원하지 않는 포맷은 무시합니다.
+    fn set_tool_template(&mut self, _template: String) {}
+}
+
+/// Qwen 기본 포맷 (NousFnCallPrompt, `<tool_call>` XML 태그)
+#[derive(Clone, Debug)]
+pub struct QwenFnCallFormat {
+    /// `code_interpreter` 계열 도구의 코드를 `<code></code>` 블록으로 주고받는 모드
+    pub code_interpreter_mode: bool,
+    /// 기본 Nous 템플릿 대신 사용할 도구 섹션 템플릿 (`{tool_descs}` 자리에 도구 목록)
+    /// 설정하면 code interpreter 모드에서도 이 템플릿을 사용합니다.
+    pub tool_template: Option<String>,
+}
+
+impl QwenFnCallFormat {
+    pub fn new(code_interpreter_mode: bool) -> Self {
+        Self {
+            code_interpreter_mode,
+            tool_template: None,
+        }
+    }
+
+    /// 도구 섹션 템플릿을 지정합니다. 호출 형식(`<tool_call>` 태그)은 파서가 그대로 기대하므로
+    /// 문구만 바꾸고 호출 예시는 유지하세요.
+    pub fn with_tool_template(mut self, template: impl Into<String>) -> Self {
+        self.tool_template = Some(template.into());
+        self
+    }
+}
+
+impl Default for QwenFnCallFormat {
+    /// 명시적으로 설정하지 않으면 기존처럼 `SPECIAL_CODE_MODE` 환경 변수를 따릅니다.
+    fn default() -> Self {
+        Self::new(special_code_mode())
+    }
+}
+
+impl PromptFormat for QwenFnCallFormat {
+    fn render_tools(&self, tools: &[FunctionDescriptor]) -> Option<String> {
+        if tools.is_empty() {
+            return None;
+        }
+
+        let tool_descs = tools
+            .iter()
+            .map(|desc| {
+                let td = ToolDescriptor {
+                    kind: "function",
+                    function: desc.clone(),
+                };
+                serde_json::to_string(&td).unwrap_or_else(|_| "{}".into())
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let section = if let Some(template) = &self.tool_template {
+            template.replace("{tool_descs}", &tool_descs)
+        } else if self.code_interpreter_mode
+            && tools
+                .iter()
+                .any(|desc| desc.name.contains(CODE_TOOL_PATTERN))
+        {
+            FN_CALL_TEMPLATE_WITH_CI.replace("{tool_descs}", &tool_descs)
+        } else {
+            FN_CALL_TEMPLATE.replace("{tool_descs}", &tool_descs)
+        };
+
+        Some(section)
+    }
+
+    fn set_tool_template(&mut self, template: String) {
+        self.tool_template = Some(template);
+    }
+
+    /// NousFnCallPrompt: 입력 메시지를 함수 호출 가능 형태로 사전 처리
+    fn preprocess(&self, messages: &[Message], tool_system: Option<&str>) -> Result<Vec<Message>> {
+        let mut processed: Vec<Message> = Vec::new();
+
+        for msg in messages.iter().cloned() {
+            match msg.role {
+                Role::System | Role::User => processed.push(msg),
+                Role::Assistant => {
+                    let mut content = msg.content.clone();
+                    if let Some(fc) = msg.function_call.clone() {
+                        if !self.code_interpreter_mode || !fc.name.contains(CODE_TOOL_PATTERN) {
+                            let parsed_args: Value = json5::from_str(&fc.arguments)
+                                .unwrap_or_else(|_| Value::String(fc.arguments.clone()));
+                            let fc_obj = json!({"name": fc.name, "arguments": parsed_args});
+                            let fc_text = format!(
+                                "<tool_call>\n{}\n</tool_call>",
+                                serde_json::to_string(&fc_obj).unwrap_or_else(|_| "{}".into())
+                            );
+                            ContentItem::ensure_newline(&mut content);
+                            ContentItem::push_into(&mut content, fc_text);
+                        } else {
+                            let mut parsed_args: Value = json5::from_str(&fc.arguments)
+                                .unwrap_or_else(|_| Value::String(fc.arguments.clone()));
+                            let code = parsed_args
+                                .get("code")
+                                .and_then(|c| c.as_str())
+                                .unwrap_or("")
+                                .to_string();
+                            if let Some(obj) = parsed_args.as_object_mut() {
+                                obj.insert("code".to_string(), Value::String(String::new()));
+                            }
+                            let fc_obj = json!({"name": fc.name, "arguments": parsed_args});
+                            let fc_text = format!(
+                                "<tool_call>\n{}\n<code>\n{}\n</code>\n</tool_call>",
+                                serde_json::to_string(&fc_obj).unwrap_or_else(|_| "{}".into()),
+                                code
+                            );
+                            ContentItem::ensure_newline(&mut content);
+                            ContentItem::push_into(&mut content, fc_text);
+                        }
+                    }
+
+                    if let Some(last) = processed.last_mut()
+                        && last.role == Role::Assistant
+                    {
+                        ContentItem::ensure_newline(&mut last.content);
+                        last.content.extend(content);
+                        continue;
+                    }
+
+                    processed.push(Message {
+                        role: Role::Assistant,
+                        content,
+                        reasoning_content: msg.reasoning_content,
+                        function_call: None,
+                        extra: msg.extra,
+                    });
+                }
+                Role::Function => {
+                    let mut content = msg.content.clone();
+                    content.insert(0, ContentItem::text("<tool_response>\n"));
+                    content.push(ContentItem::text("\n</tool_response>"));
+
+                    if let Some(last) = processed.last_mut()
+                        && last.role == Role::User
+                    {
+                        ContentItem::push_into(&mut last.content, "\n");
+                        last.content.extend(content);
+                        continue;
+                    }
+
+                    processed.push(Message {
+                        role: Role::User,
+                        content,
+                        reasoning_content: None,
+                        function_call: None,
+                        extra: None,
+                    });
+                }
+            }
+        }
+
+        if let Some(tool_system) = tool_system {
+            if let Some(first) = processed.first_mut() {
+                if first.role == Role::System {
+                    ContentItem::push_into(&mut first.content, format!("\n\n{}", tool_system));
+                } else {
+                    processed.insert(0, Message::system_text(tool_system));
+                }
+            } else {
+                processed.push(Message::system_text(tool_system));
+            }
+        }
+
+        Ok(processed)
+    }
+
+    fn looks_like_tool_call(&self, text: &str) -> bool {
+        outside_think(text).contains("<tool_call>")
+    }
+
+    fn tool_call_end(&self) -> Option<&str> {
+        Some("</tool_call>")
+    }
+
+    /// NousFnCallPrompt: 모델 응답을 함수 호출 구조로 역변환
+    fn postprocess(&self, messages: Vec<Message>) -> Result<Vec<Message>> {
+        let mut new_messages = Vec::new();
+        let mut tool_id: usize = 1;
+
+        for msg in messages.into_iter() {
+            let role = msg.role;
+            let content = msg.content;
+            let reasoning_content = msg.reasoning_content;
+            let extra = msg.extra.unwrap_or_default();
+
+            match role {
+                Role::System | Role::User => {
+                    new_messages.push(Message {
+                        role,
+                        content,
+                        reasoning_content,
+                        function_call: None,
+                        extra: if extra.is_empty() { None } else { Some(extra) },
+                    });
+                }
+                Role::Assistant => {
+                    if let Some(reason) = reasoning_content {
+                        new_messages.push(Message {
+                            role: Role::Assistant,
+                            content: vec![],
+                            reasoning_content: Some(reason),
+                            function_call: None,
+                            extra: None,
+                        });
+                    }
+
+                    let mut new_content = Vec::new();
+
+                    for item in content.into_iter() {
+                        let (item_type, item_text) = item.get_type_and_value();
+
+                        if item_type != "text" {
+                            new_content.push(item);
+                            continue;
+                        }
+
+                        for segment in split_think_segments(item_text) {
+                            let remaining_text = match segment {
+                                ThinkSegment::Closed(thought) => {
+                                    new_content.push(ContentItem::text(thought));
+                                    continue;
+                                }
+                                ThinkSegment::Unclosed(thought) => {
+                                    // 닫히지 않은 추론은 도구 호출로 해석하지 않고 reasoning_content로 보존
+                                    if !new_content.is_empty() {
+                                        new_messages.push(Message {
+                                            role: Role::Assistant,
+                                            content: std::mem::take(&mut new_content),
+                                            reasoning_content: None,
+                                            function_call: None,
+                                            extra: None,
+                                        });
+                                    }
+                                    new_messages.push(Message {
+                                        role: Role::Assistant,
+                                        content: Vec::new(),
+                                        reasoning_content: Some(thought),
+                                        function_call: None,
+                                        extra: None,
+                                    });
+                                    continue;
+                                }
+                                ThinkSegment::Text(text) => text,
+                            };
+                            if remaining_text.contains("<tool_call>") {
+                                let tool_call_list: Vec<&str> =
+                                    remaining_text.split("<tool_call>").collect();
+                                // 호출을 ```json 펜스로 감싼 경우 호출 바로 앞의 여는 펜스 줄은 버림
+                                let (pre_thought, _) = strip_opening_fence(tool_call_list[0]);
+                                if !pre_thought.trim().is_empty() {
+                                    new_content.push(ContentItem::text(pre_thought));
+                                }
+
+                                for txt in tool_call_list.into_iter().skip(1) {
+                                    if txt.trim().is_empty() {
+                                        continue;
+                                    }
+
+                                    if !txt.contains("</tool_call>") {
+                                        let (fn_name, fn_args) = extract_fn(txt);
+                                        if !fn_name.is_empty() {
+                                            if !new_content.is_empty() {
+                                                new_messages.push(Message {
+                                                    role: Role::Assistant,
+                                                    content: new_content.clone(),
+                                                    reasoning_content: None,
+                                                    function_call: None,
+                                                    extra: None,
+                                                });
+                                                new_content.clear();
+                                            }
+
+                                            let mut extra_map = extra.clone();
+                                            extra_map
+                                                .insert("function_id".into(), tool_id.to_string());
+                                            tool_id += 1;
+
+                                            new_messages.push(Message {
+                                                role: Role::Assistant,
+                                                content: Vec::new(),
+                                                reasoning_content: None,
+                                                function_call: Some(FunctionCall {
+                                                    name: fn_name,
+                                                    arguments: fn_args,
+                                                }),
+                                                extra: Some(extra_map),
+                                            });
+                                        }
+                                        continue;
+                                    }
+
+                                    let parts: Vec<&str> = txt.split("</tool_call>").collect();
+                                    if !new_content.is_empty() {
+                                        new_messages.push(Message {
+                                            role: Role::Assistant,
+                                            content: new_content.clone(),
+                                            reasoning_content: None,
+                                            function_call: None,
+                                            extra: None,
+                                        });
+                                        new_content.clear();
+                                    }
+
+                                    let mut fn_obj: Option<Value> = None;
+
+                                    if self.code_interpreter_mode
+                                        && parts[0].contains("<code>")
+                                        && parts[0].contains("</code>")
+                                    {
+                                        let mut code_sections = parts[0].split("<code>");
+                                        if let Some(first) = code_sections.next()
+                                            && let Ok(v) = json5::from_str::<Value>(first)
+                                        {
+                                            fn_obj = Some(v);
+                                        }
+                                        if let Some(last_section) = code_sections.next() {
+                                            let code = last_section.replace("</code>", "");
+                                            if let Some(Value::Object(ref mut obj)) = fn_obj
+                                                && let Some(args_obj) = obj
+                                                    .get_mut("arguments")
+                                                    .and_then(Value::as_object_mut)
+                                            {
+                                                args_obj.insert("code".into(), Value::String(code));
+                                            }
+                                        }
+                                    } else {
+                                        let body = strip_wrapping_fence(parts[0]);
+                                        if let Ok(v) = json5::from_str::<Value>(body) {
+                                            fn_obj = Some(v);
+                                        }
+                                    }
+
+                                    if let Some(fn_obj) = fn_obj {
+                                        if let (Some(fn_name), Some(arguments)) = (
+                                            fn_obj.get("name").and_then(|v| v.as_str()),
+                                            fn_obj.get("arguments"),
+                                        ) {
+                                            let mut extra_map = extra.clone();
+                                            extra_map
+                                                .insert("function_id".into(), tool_id.to_string());
+                                            tool_id += 1;
+
+                                            new_messages.push(Message {
+                                                role: Role::Assistant,
+                                                content: Vec::new(),
+                                                reasoning_content: None,
+                                                function_call: Some(FunctionCall {
+                                                    name: fn_name.to_string(),
+                                                    arguments: serde_json::to_string(arguments)
+                                                        .unwrap_or_else(|_| "{}".into()),
+                                                }),
+                                                extra: Some(extra_map),
+                                            });
+                                        }
+                                    } else {
+                                        let (fn_name, fn_args) = extract_fn(parts[0].trim());
+                                        if !fn_name.is_empty() {
+                                            let mut extra_map = extra.clone();
+                                            extra_map
+                                                .insert("function_id".into(), tool_id.to_string());
+                                            tool_id += 1;
+
+                                            new_messages.push(Message {
+                                                role: Role::Assistant,
+                                                content: Vec::new(),
+                                                reasoning_content: None,
+                                                function_call: Some(FunctionCall {
+                                                    name: fn_name,
+                                                    arguments: fn_args,
+                                                }),
+                                                extra: Some(extra_map),
+                                            });
+                                        }
+                                    }
+                                }
+                            } else if let Some((calls, rest)) =
+                                fenced_function_calls(&remaining_text)
+                            {
+                                // 태그 없이 ```json 블록으로만 낸 호출
+                                for (before, function_call) in calls {
+                                    if !before.trim().is_empty() {
+                                        new_content.push(ContentItem::text(before));
+                                    }
+                                    if !new_content.is_empty() {
+                                        new_messages.push(Message {
+                                            role: Role::Assistant,
+                                            content: std::mem::take(&mut new_content),
+                                            reasoning_content: None,
+                                            function_call: None,
+                                            extra: None,
+                                        });
+                                    }
+
+                                    let mut extra_map = extra.clone();
+                                    extra_map.insert("function_id".into(), tool_id.to_string());
+                                    tool_id += 1;
+
+                                    new_messages.push(Message {
+                                        role: Role::Assistant,
+                                        content: Vec::new(),
+                                        reasoning_content: None,
+                                        function_call: Some(function_call),
+                                        extra: Some(extra_map),
+                                    });
+                                }
+                                if !rest.trim().is_empty() {
+                                    new_content.push(ContentItem::text(rest));
+                                }
+                            } else {
+                                if !remaining_text.is_empty() {
+                                    new_content.push(ContentItem::text(remaining_text));
+                                }
+                            }
+                        }
+                    }
+
+                    if !new_content.is_empty() {
+                        new_messages.push(Message {
+                            role: Role::Assistant,
+                            content: new_content,
+                            reasoning_content: None,
+                            function_call: None,
+                            extra: if extra.is_empty() { None } else { Some(extra) },
+                        });
+                    }
+                }
+                Role::Function => {
+                    // Function 역할은 입력으로만 들어오지 않고, 실행 결과로만 추가될 예정
+                }
+            }
+        }
+
+        Ok(new_messages)
+    }
+}
+
+#[derive(Serialize)]
+struct ToolDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: FunctionDescriptor,
+}
+
+/// 프롬프트에 노출되는 도구 명세 (이름, 설명, 인자 JSON Schema)
+#[derive(Clone, Debug, Serialize)]
+pub struct FunctionDescriptor {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+fn special_code_mode() -> bool {
+    env::var("SPECIAL_CODE_MODE")
+        .unwrap_or_else(|_| "false".to_string())
+        .to_lowercase()
+        == "true"
+}
+
+fn extract_fn(text: &str) -> (String, String) {
+    // 1. JSON5 파싱 우선 (공백, 키 순서, 중첩 객체에 영향받지 않음)
+    if let Some(parsed) = extract_fn_json(text) {
+        return parsed;
+    }
+
+    // 2. 파싱 실패 시(잘린 출력 등) substring 휴리스틱으로 fallback
+    let mut fn_name = String::new();
+    let mut fn_args = String::new();
+
+    if let Some(caps) = FN_NAME_RE.captures(text) {
+        fn_name = caps[1].to_string();
+    }
+
+    if let Some(m) = FN_ARGUMENTS_RE.find(text) {
+        let rest = text[m.end()..].trim();
+        if let Some(obj) = balanced_json_object(rest) {
+            fn_args = obj.to_string();
+        } else if rest.len() > 2 {
+            // 닫히지 않은 객체: 바깥쪽 닫는 괄호 한 글자를 제거하는 기존 동작 유지
+            let mut end = rest.len() - 1;
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            fn_args = rest[..end].to_string();
+        }
+    }
+
+    (fn_name, fn_args)
+}
+
+/// `{"name": ..., "arguments": ...}` 형태를 JSON5로 관대하게 파싱합니다.
+fn extract_fn_json(text: &str) -> Option<(String, String)> {
+    let trimmed = text.trim();
+    let value = json5::from_str::<Value>(trimmed).ok().or_else(|| {
+        let start = trimmed.find('{')?;
+        let obj = balanced_json_object(&trimmed[start..])?;
+        json5::from_str::<Value>(obj).ok()
+    })?;
+
+    let name = value.get("name")?.as_str()?.to_string();
+    let arguments = match value.get("arguments") {
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => serde_json::to_string(v).ok()?,
+        None => String::new(),
+    };
+    Some((name, arguments))
+}
+
+/// `<tool_call>` 태그 없이 ```json 코드 블록으로만 낸 `{"name": ..., "arguments": ...}` 호출을 찾습니다.
+/// (호출 앞의 텍스트, 호출) 목록과 마지막 호출 뒤의 텍스트를 반환하며, 호출이 없으면 None입니다.
+/// `name`과 `arguments`가 모두 있는 블록만 호출로 보므로 일반 JSON 예시는 텍스트로 남습니다.
+fn fenced_function_calls(text: &str) -> Option<(Vec<(String, FunctionCall)>, String)> {
+    let mut calls = Vec::new();
+    let mut cursor = 0;
+
+    for caps in FENCED_BLOCK_RE.captures_iter(text) {
+        let (Some(block), Some(body)) = (caps.get(0), caps.get(1)) else {
+            continue;
+        };
+        let Ok(value) = json5::from_str::<Value>(body.as_str().trim()) else {
+            continue;
+        };
+        let (Some(name), Some(arguments)) = (
+            value.get("name").and_then(Value::as_str),
+            value.get("arguments"),
+        ) else {
+            continue;
+        };
+        let arguments = match arguments {
+            Value::String(s) => s.clone(),
+            other => serde_json::to_string(other).unwrap_or_else(|_| "{}".into()),
+        };
+        calls.push((
+            text[cursor..block.start()].to_string(),
+            FunctionCall {
+                name: name.to_string(),
+                arguments,
+            },
+        ));
+        cursor = block.end();
+    }
+
+    if calls.is_empty() {
+        return None;
+    }
+    Some((calls, text[cursor..].to_string()))
+}
+
+/// 문자열 리터럴을 고려하여 첫 번째 균형 잡힌 `{...}` 구간을 반환합니다.
+fn balanced_json_object(text: &str) -> Option<&str> {
+    if !text.starts_with('{') {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut in_string: Option<char> = None;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if let Some(quote) = in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                in_string = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => in_string = Some(c),
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[..=i]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// JSON 액션 포맷 상수 (```json {"tool": ..., "args": ...}``` 규약)
+const JSON_ACTION_TEMPLATE: &str = r#"# Tools
+
+You can call the following tools:
+{tool_descs}
+
+To call a tool, respond with a JSON object inside a ```json code block:
+```json
+{"tool": <tool-name>, "args": <args-json-object>}
+```
+Tool results are returned to you as "Observation:" messages. When you no longer need a tool, answer normally without a JSON block."#;
+
+/// 범용 JSON 액션 포맷 (Llama/Mistral 등 Qwen 이외 모델용)
+/// 모델은 최상위에 `tool` 키를 가진 JSON 객체로 도구를 호출합니다.
+#[derive(Clone, Debug, Default)]
+pub struct JsonActionFormat;
+
+impl PromptFormat for JsonActionFormat {
+    fn render_tools(&self, tools: &[FunctionDescriptor]) -> Option<String> {
+        if tools.is_empty() {
+            return None;
+        }
+
+        let tool_descs = tools
+            .iter()
+            .map(|desc| serde_json::to_string(desc).unwrap_or_else(|_| "{}".into()))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        Some(JSON_ACTION_TEMPLATE.replace("{tool_descs}", &tool_descs))
+    }
+
+    fn preprocess(&self, messages: &[Message], tool_system: Option<&str>) -> Result<Vec<Message>> {
+        let mut processed: Vec<Message> = Vec::new();
+
+        for msg in messages.iter().cloned() {
+            match msg.role {
+                Role::System | Role::User => processed.push(msg),
+                Role::Assistant => {
+                    let mut content = msg.content.clone();
+                    if let Some(fc) = msg.function_call.clone() {
+                        let parsed_args: Value = json5::from_str(&fc.arguments)
+                            .unwrap_or_else(|_| Value::String(fc.arguments.clone()));
+                        let action = json!({"tool": fc.name, "args": parsed_args});
+                        ContentItem::ensure_newline(&mut content);
+                        ContentItem::push_into(
+                            &mut content,
+                            format!(
+                                "```json\n{}\n```",
+                                serde_json::to_string(&action).unwrap_or_else(|_| "{}".into())
+                            ),
+                        );
+                    }
+
+                    if let Some(last) = processed.last_mut()
+                        && last.role == Role::Assistant
+                    {
+                        ContentItem::ensure_newline(&mut last.content);
+                        last.content.extend(content);
+                        continue;
+                    }
+
+                    processed.push(Message {
+                        role: Role::Assistant,
+                        content,
+                        reasoning_content: msg.reasoning_content,
+                        function_call: None,
+                        extra: msg.extra,
+                    });
+                }
+                Role::Function => {
+                    let mut content = msg.content.clone();
+                    content.insert(0, ContentItem::text("Observation:\n"));
+
+                    if let Some(last) = processed.last_mut()
+                        && last.role == Role::User
+                    {
+                        ContentItem::push_into(&mut last.content, "\n");
+                        last.content.extend(content);
+                        continue;
+                    }
+
+                    processed.push(Message::new(Role::User, content));
+                }
+            }
+        }
+
+        if let Some(tool_system) = tool_system {
+            match processed.first_mut() {
+                Some(first) if first.role == Role::System => {
+                    ContentItem::push_into(&mut first.content, format!("\n\n{}", tool_system));
+                }
+                _ => processed.insert(0, Message::system_text(tool_system)),
+            }
+        }
+
+        Ok(processed)
+    }
+
+    fn looks_like_tool_call(&self, text: &str) -> bool {
+        JSON_ACTION_KEY_RE.is_match(&outside_think(text))
+    }
+
+    fn postprocess(&self, messages: Vec<Message>) -> Result<Vec<Message>> {
+        let mut new_messages = Vec::new();
+        let mut tool_id: usize = 1;
+
+        for msg in messages.into_iter() {
+            if msg.role != Role::Assistant {
+                new_messages.push(msg);
+                continue;
+            }
+
+            let text = msg.content_as_string();
+            let mut calls = Vec::new();
+            let mut prose = String::new();
+            let mut cursor = 0;
+
+            while let Some(offset) = text[cursor..].find('{') {
+                let start = cursor + offset;
+                let Some(obj) = balanced_json_object(&text[start..]) else {
+                    break;
+                };
+                let end = start + obj.len();
+
+                // 최상위에 "tool" 키가 있는 객체만 도구 호출로 인정 (중첩된 tool 키는 무시)
+                let action = json5::from_str::<Value>(obj).ok().and_then(|v| {
+                    let name = v.get("tool")?.as_str()?.to_string();
+                    let args = v.get("args").cloned().unwrap_or(Value::Null);
+                    Some((name, args))
+                });
+
+                match action {
+                    Some((name, args)) => {
+                        prose.push_str(&text[cursor..start]);
+                        calls.push((std::mem::take(&mut prose), name, args));
+                    }
+                    None => prose.push_str(&text[cursor..end]),
+                }
+                cursor = end;
+            }
+
+            if calls.is_empty() {
+                new_messages.push(msg);
+                continue;
+            }
+            prose.push_str(&text[cursor..]);
+
+            // 직전 호출이 펜스로 감싸져 있었는지 (그렇다면 다음 텍스트의 첫 ``` 줄은 그 펜스를 닫는 줄)
+            let mut fenced = false;
+            for (before, name, args) in calls {
+                let before = if fenced {
+                    strip_closing_fence(&before)
+                } else {
+                    &before
+                };
+                let (before, opened) = strip_opening_fence(before);
+                fenced = opened;
+                if !before.trim().is_empty() {
+                    new_messages.push(Message::assistant_text(before));
+                }
+
+                let mut extra = msg.extra.clone().unwrap_or_default();
+                extra.insert("function_id".into(), tool_id.to_string());
+                tool_id += 1;
+
+                let arguments = match args {
+                    Value::String(s) => s,
+                    other => serde_json::to_string(&other).unwrap_or_else(|_| "{}".into()),
+                };
+
+                new_messages.push(Message {
+                    role: Role::Assistant,
+                    content: Vec::new(),
+                    reasoning_content: None,
+                    function_call: Some(FunctionCall { name, arguments }),
+                    extra: Some(extra),
+                });
+            }
+
+            let after = if fenced {
+                strip_closing_fence(&prose)
+            } else {
+                &prose
+            };
+            if !after.trim().is_empty() {
+                new_messages.push(Message::assistant_text(after));
+            }
+        }
+
+        Ok(new_messages)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ThinkSegment {
+    /// 닫힌 추론 블록 (태그 포함 원문)
+    Closed(String),
+    /// 닫히지 않은 추론 블록 (태그 제외 내용)
+    Unclosed(String),
+    Text(String),
+}
+
+/// 텍스트를 `<think>` 블록 기준으로 나눕니다.
+/// - 블록이 여러 개이거나 도구 호출과 섞여 있어도 원래 순서대로 반환
+/// - 여는 태그 없이 나온 `</think>` (프롬프트에서 `<think>`를 프리필한 경우)는 그 앞까지를 닫힌 블록으로 취급
+fn split_think_segments(text: &str) -> Vec<ThinkSegment> {
+    const OPEN: &str = "<think>";
+    const CLOSE: &str = "</think>";
+
+    let mut segments = Vec::new();
+    let mut rest = text;
+
+    loop {
+        match (rest.find(OPEN), rest.find(CLOSE)) {
+            (open, Some(close)) if open.is_none_or(|open| close < open) => {
+                let end = close + CLOSE.len();
+                segments.push(ThinkSegment::Closed(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+            (Some(open), _) => {
+                if open > 0 {
+                    segments.push(ThinkSegment::Text(rest[..open].to_string()));
+                }
+                let body = &rest[open + OPEN.len()..];
+                let Some(close) = body.find(CLOSE) else {
+                    segments.push(ThinkSegment::Unclosed(body.to_string()));
+                    return segments;
+                };
+                let end = open + OPEN.len() + close + CLOSE.len();
+                segments.push(ThinkSegment::Closed(rest[open..end].to_string()));
+                rest = &rest[end..];
+            }
+            _ => break,
+        }
+    }
+
+    if !rest.is_empty() {
+        segments.push(ThinkSegment::Text(rest.to_string()));
+    }
+    segments
+}
+
+/// `<think>` 블록 바깥의 텍스트만 이어 붙입니다 (닫히지 않은 블록을 포함해 추론 중 언급된 태그는 호출로 보지 않음)
+fn outside_think(text: &str) -> String {
+    split_think_segments(text)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            ThinkSegment::Text(text) => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 도구 호출 바로 앞에 있는 여는 펜스(```json 또는 ```) 줄을 제거합니다.
+/// 마지막 줄이 앞선 코드 블록을 닫는 ```이면 그대로 두며, 펜스를 제거했는지 함께 반환합니다.
+fn strip_opening_fence(text: &str) -> (&str, bool) {
+    let trimmed = text.trim_end();
+    let start = trimmed.rfind('\n').map_or(0, |i| i + 1);
+    let last = trimmed[start..].trim();
+    // 앞쪽 펜스 줄 수가 홀수면 마지막 줄은 열린 코드 블록을 닫는 줄
+    let fences_before = trimmed[..start]
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    if (last == "```" || last == "```json") && fences_before % 2 == 0 {
+        (&trimmed[..start], true)
+    } else {
+        (text, false)
+    }
+}
+
+/// 펜스로 감싼 도구 호출 바로 뒤의 닫는 ``` 줄을 제거합니다.
+fn strip_closing_fence(text: &str) -> &str {
+    let trimmed = text.trim_start();
+    let (first, rest) = trimmed.split_once('\n').unwrap_or((trimmed, ""));
+    if first.trim() == "```" { rest } else { text }
+}
+
+/// `<tool_call>` 안쪽 본문을 감싼 ```json ... ``` 펜스를 벗겨냅니다.
+fn strip_wrapping_fence(text: &str) -> &str {
+    let trimmed = text.trim();
+    let Some(inner) = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+    else {
+        return trimmed;
+    };
+    inner.strip_suffix("```").unwrap_or(inner).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Qwen 포맷으로 어시스턴트 응답 하나를 후처리합니다.
+    fn parse(text: &str) -> Vec<Message> {
+        QwenFnCallFormat::new(false)
+            .postprocess(vec![Message::assistant_text(text)])
+            .unwrap()
+    }
+
+    /// 후처리 결과의 (도구 이름, 인자) 목록
+    fn calls(messages: &[Message]) -> Vec<(String, Value)> {
+        messages
+            .iter()
+            .filter_map(|m| m.function_call.as_ref())
+            .map(|fc| {
+                (
+                    fc.name.clone(),
+                    serde_json::from_str(&fc.arguments).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// 후처리 결과에서 도구 호출이 아닌 텍스트만 이어 붙인 것
+    fn prose(messages: &[Message]) -> String {
+        messages
+            .iter()
+            .filter(|m| m.function_call.is_none())
+            .map(|m| m.content_as_string())
+            .collect()
+    }
+
+    #[test]
+    fn parses_tool_call_wrapped_in_json_fence() {
+        let messages = parse(
+            "Listing files.\n```json\n<tool_call>\n\
+             {\"name\": \"ls\", \"arguments\": {\"path\": \".\"}}\n</tool_call>\n```",
+        );
+        assert_eq!(calls(&messages), vec![("ls".into(), json!({"path": "."}))]);
+        assert_eq!(prose(&messages).trim(), "Listing files.");
+    }
+
+    #[test]
+    fn parses_json_fence_inside_tool_call_tags() {
+        let messages = parse(
+            "<tool_call>\n```json\n{\"name\": \"ls\", \"arguments\": {\"path\": \"src\"}}\n```\n</tool_call>",
+        );
+        assert_eq!(
+            calls(&messages),
+            vec![("ls".into(), json!({"path": "src"}))]
+        );
+        assert!(prose(&messages).trim().is_empty());
+    }
+
+    #[test]
+    fn parses_bare_json_fence_as_tool_call() {
+        let messages = parse(
+            "Let me look.\n```json\n{\"name\": \"ls\", \"arguments\": {\"path\": \".\"}}\n```",
+        );
+        assert_eq!(calls(&messages), vec![("ls".into(), json!({"path": "."}))]);
+        assert_eq!(prose(&messages).trim(), "Let me look.");
+    }
+
+    #[test]
+    fn keeps_plain_json_fence_without_name_and_arguments() {
+        let text = "Example config:\n```json\n{\"a\": 1}\n```";
+        let messages = parse(text);
+        assert!(calls(&messages).is_empty());
+        assert_eq!(prose(&messages), text);
+    }
+
+    #[test]
+    fn keeps_unrelated_code_block_before_tool_call() {
+        let messages = parse(
+            "The current code:\n```rust\nfn main() {}\n```\nChecking the rest.\n```\nraw\n```\n\
+             <tool_call>\n{\"name\": \"ls\", \"arguments\": {}}\n</tool_call>",
+        );
+        assert_eq!(calls(&messages), vec![("ls".into(), json!({}))]);
+        assert_eq!(
+            prose(&messages),
+            "The current code:\n```rust\nfn main() {}\n```\nChecking the rest.\n```\nraw\n```\n"
+        );
+    }
+
+    #[test]
+    fn json_action_format_keeps_unrelated_code_block() {
+        let messages = JsonActionFormat
+            .postprocess(vec![Message::assistant_text(
+                "Run this:\n```sh\nls -la\n```\n```json\n{\"tool\": \"shell\", \"args\": {\"cmd\": \"ls\"}}\n```\nDone.",
+            )])
+            .unwrap();
+        assert_eq!(
+            calls(&messages),
+            vec![("shell".into(), json!({"cmd": "ls"}))]
+        );
+        assert_eq!(prose(&messages), "Run this:\n```sh\nls -la\n```\nDone.");
+    }
+
+    /// JSON 액션 포맷으로 어시스턴트 응답 하나를 후처리합니다.
+    fn parse_action(text: &str) -> Vec<Message> {
+        JsonActionFormat
+            .postprocess(vec![Message::assistant_text(text)])
+            .unwrap()
+    }
+
+    #[test]
+    fn json_action_accepts_trailing_commas_and_single_quotes() {
+        let messages = parse_action("Listing.\n{'tool': 'ls', 'args': {'path': '.',},}");
+        assert_eq!(calls(&messages), vec![("ls".into(), json!({"path": "."}))]);
+        assert_eq!(prose(&messages).trim(), "Listing.");
+    }
+
+    #[test]
+    fn json_action_parses_consecutive_actions_in_order() {
+        let messages = parse_action(
+            "{\"tool\": \"ls\", \"args\": {}}\n{\"tool\": \"cat\", \"args\": {\"path\": \"a\"}}",
+        );
+        assert_eq!(
+            calls(&messages),
+            vec![
+                ("ls".into(), json!({})),
+                ("cat".into(), json!({"path": "a"}))
+            ]
+        );
+    }
+
+    #[test]
+    fn json_action_ignores_nested_tool_key() {
+        let text = "Config: {\"settings\": {\"tool\": \"rm\"}}";
+        let messages = parse_action(text);
+        assert!(calls(&messages).is_empty());
+        assert_eq!(prose(&messages), text);
+
+        // 인자 안의 tool 키는 호출이 아니라 인자
+        let messages = parse_action("{\"tool\": \"ls\", \"args\": {\"tool\": \"nested\"}}");
+        assert_eq!(
+            calls(&messages),
+            vec![("ls".into(), json!({"tool": "nested"}))]
+        );
+    }
+
+    /// `extract_fn`이 돌려준 인자 문자열을 JSON으로 파싱
+    fn extracted(text: &str) -> (String, Value) {
+        let (name, args) = extract_fn(text);
+        (name, serde_json::from_str(&args).unwrap())
+    }
+
+    #[test]
+    fn extract_fn_parses_compact_json() {
+        assert_eq!(
+            extracted(r#"{"name":"ls","arguments":{"path":"."}}"#),
+            ("ls".into(), json!({"path": "."}))
+        );
+    }
+
+    #[test]
+    fn extract_fn_ignores_key_order() {
+        assert_eq!(
+            extracted(r#"{"arguments": {"path": "src"}, "name": "ls"}"#),
+            ("ls".into(), json!({"path": "src"}))
+        );
+    }
+
+    #[test]
+    fn extract_fn_keeps_nested_arguments() {
+        let args = json!({
+            "path": "a.txt",
+            "options": {"mode": "append", "lines": [1, 2, {"deep": true}]},
+            "text": "} { not json"
+        });
+        let text = format!(r#"{{"name": "write", "arguments": {}}}"#, args);
+        assert_eq!(extracted(&text), ("write".into(), args));
+    }
+
+    #[test]
+    fn extract_fn_accepts_json5_and_string_arguments() {
+        assert_eq!(
+            extracted("{name: 'ls', arguments: {path: '.',},}"),
+            ("ls".into(), json!({"path": "."}))
+        );
+        assert_eq!(
+            extract_fn(r#"{"name": "ls", "arguments": "{\"path\": \".\"}"}"#),
+            ("ls".to_string(), r#"{"path": "."}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn extract_fn_falls_back_on_truncated_output() {
+        // 바깥 객체가 닫히지 않은 출력
+        assert_eq!(
+            extracted(r#"{"name": "ls", "arguments": {"path": "."}"#),
+            ("ls".into(), json!({"path": "."}))
+        );
+        assert_eq!(extract_fn("no call here"), (String::new(), String::new()));
+    }
+
+    #[test]
+    fn split_think_keeps_unclosed_block_as_reasoning() {
+        assert_eq!(
+            split_think_segments("Answer first <think>still thinking"),
+            vec![
+                ThinkSegment::Text("Answer first ".into()),
+                ThinkSegment::Unclosed("still thinking".into()),
+            ]
+        );
+
+        // 닫히지 않은 추론 안의 도구 호출은 실행하지 않음
+        let call = r#"<tool_call>{"name": "ls", "arguments": {}}</tool_call>"#;
+        let messages = parse(&format!("<think>maybe {}", call));
+        assert!(calls(&messages).is_empty());
+        assert_eq!(
+            messages[0].reasoning_content.as_deref(),
+            Some(format!("maybe {}", call).as_str())
+        );
+    }
+
+    #[test]
+    fn tool_call_inside_reasoning_is_not_malformed() {
+        let qwen = QwenFnCallFormat::new(false);
+        // `</tool_call>` 정지 문자열이 추론 도중에 걸려 블록이 닫히지 않은 경우
+        assert!(!qwen.looks_like_tool_call(
+            r#"<think>I could emit <tool_call>{"name": "ls", "arguments": {}}</tool_call> later"#
+        ));
+        assert!(!qwen.looks_like_tool_call("<think>use <tool_call> here</think>Plain answer."));
+        assert!(qwen.looks_like_tool_call("<think>list</think>\n<tool_call>\n{\"name\": \"ls\""));
+        assert!(qwen.looks_like_tool_call("<tool_call>{\"name\"<think>oops"));
+
+        assert!(!JsonActionFormat.looks_like_tool_call(r#"<think>maybe {"tool": "ls""#));
+        assert!(JsonActionFormat.looks_like_tool_call(r#"<think>ok</think>{"tool": "ls", "#));
+    }
+
+    #[test]
+    fn split_think_handles_multiple_blocks() {
+        assert_eq!(
+            split_think_segments("<think>a</think>x<think>b</think>y"),
+            vec![
+                ThinkSegment::Closed("<think>a</think>".into()),
+                ThinkSegment::Text("x".into()),
+                ThinkSegment::Closed("<think>b</think>".into()),
+                ThinkSegment::Text("y".into()),
+            ]
+        );
+        // 프롬프트에서 `<think>`를 프리필한 경우
+        assert_eq!(
+            split_think_segments("reasoning</think>answer"),
+            vec![
+                ThinkSegment::Closed("reasoning</think>".into()),
+                ThinkSegment::Text("answer".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn think_blocks_interleaved_with_tool_calls_keep_order() {
+        let messages = parse(
+            "<think>list first</think>\n<tool_call>\n{\"name\": \"ls\", \"arguments\": {}}\n</tool_call>\
+             <think>then read</think>\n<tool_call>\n{\"name\": \"cat\", \"arguments\": {\"path\": \"a\"}}\n</tool_call>",
+        );
+        let sequence: Vec<String> = messages
+            .iter()
+            .map(|m| match &m.function_call {
+                Some(fc) => format!("call:{}", fc.name),
+                None => m.content_as_string(),
+            })
+            .collect();
+        assert_eq!(
+            sequence,
+            [
+                "<think>list first</think>",
+                "call:ls",
+                "<think>then read</think>",
+                "call:cat"
+            ]
+        );
+
+        // 닫힌 추론 안에서 언급한 호출은 호출로 보지 않음
+        let messages = parse(
+            "<think>I could use <tool_call>{\"name\": \"ls\", \"arguments\": {}}</tool_call></think>Plain answer.",
+        );
+        assert!(calls(&messages).is_empty());
+        assert!(prose(&messages).ends_with("Plain answer."));
+    }
+}
@@ -1,48 +1,18 @@
+use super::chat_template::ChatTemplate;
+use super::event::{AgentEvent, EventFn};
+use super::prompt_format::{FunctionDescriptor, PromptFormat, QwenFnCallFormat};
+use super::tool_cache::ToolCache;
+use super::tool_policy::{ToolLimiter, ToolPolicy};
 use crate::error::{Result, SuprascalarError};
-use crate::models::LLMBackend;
-use crate::tools::Tool;
+use crate::models::{GenerationConfig, LLMBackend, Usage};
+use crate::tools::{GitSnapshot, Tool};
+use crate::util::CancellationToken;
 use serde::{Deserialize, Serialize};
-use serde_json::{Value, json};
-use std::collections::HashMap;
-use std::env;
-
-// NousFnCallPrompt 포맷 상수
-const FN_CALL_TEMPLATE: &str = r#"# Tools
-
-You may call one or more functions to assist with the user query.
-
-You are provided with function signatures within <tools></tools> XML tags:
-<tools>
-{tool_descs}
-</tools>
-
-For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:
-<tool_call>
-{{\"name\": <function-name>, \"arguments\": <args-json-object>}}
-</tool_call>"#;
-
-const FN_CALL_TEMPLATE_WITH_CI: &str = r#"# Tools
-
-You may call one or more functions to assist with the user query.
-
-You are provided with function signatures within <tools></tools> XML tags:
-<tools>
-{tool_descs}
-</tools>
-
-For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:
-<tool_call>
-{{\"name\": <function-name>, \"arguments\": <args-json-object>}}
-</tool_call>
-For code parameters, use placeholders first, and then put the code within <code></code> XML tags, such as:
-<tool_call>
-{{\"name\": <function-name>, \"arguments\": {{\"code\": \"\"}}}}
-<code>
-Here is the code.
-</code>
-</tool_call>"#;
-
-const CODE_TOOL_PATTERN: &str = "code_interpreter";
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Role {
@@ -53,7 +23,7 @@ pub enum Role {
 }
 
 impl Role {
-    fn as_str(&self) -> &'static str {
+    pub(crate) fn as_str(&self) -> &'static str {
         match self {
             Role::System => "system",
             Role::User => "user",
@@ -69,19 +39,31 @@ pub enum ContentItem {
 }
 
 impl ContentItem {
-    fn text<T: Into<String>>(text: T) -> Self {
+    pub(crate) fn text<T: Into<String>>(text: T) -> Self {
         ContentItem::Text(text.into())
     }
 
-    fn get_type_and_value(&self) -> (&'static str, &str) {
+    pub(crate) fn get_type_and_value(&self) -> (&'static str, &str) {
         match self {
             ContentItem::Text(t) => ("text", t.as_str()),
         }
     }
 
-    fn push_into(target: &mut Vec<ContentItem>, text: impl Into<String>) {
+    pub(crate) fn push_into(target: &mut Vec<ContentItem>, text: impl Into<String>) {
         target.push(ContentItem::Text(text.into()));
     }
+
+    /// 앞선 텍스트가 줄바꿈으로 끝나지 않으면 줄바꿈을 추가합니다.
+    /// 도구 호출 블록 등이 앞 문장에 붙어버리지 않도록 블록을 덧붙이기 전에 호출합니다.
+    pub(crate) fn ensure_newline(target: &mut Vec<ContentItem>) {
+        let last_text = target.iter().rev().find_map(|c| match c {
+            ContentItem::Text(t) if !t.is_empty() => Some(t),
+            _ => None,
+        });
+        if last_text.is_some_and(|t| !t.ends_with('\n')) {
+            ContentItem::push_into(target, "\n");
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -94,13 +76,17 @@ pub struct FunctionCall {
 pub struct Message {
     pub role: Role,
     pub content: Vec<ContentItem>,
+    #[serde(default)]
     pub reasoning_content: Option<String>,
+    #[serde(default)]
     pub function_call: Option<FunctionCall>,
+    /// 도구 호출 연결 정보(`function_id` 등). 프롬프트에는 드러나지 않지만 내보내기/가져오기 시 그대로 보존됩니다.
+    #[serde(default)]
     pub extra: Option<HashMap<String, String>>,
 }
 
 impl Message {
-    fn new(role: Role, content: Vec<ContentItem>) -> Self {
+    pub fn new(role: Role, content: Vec<ContentItem>) -> Self {
         Self {
             role,
             content,
@@ -110,48 +96,207 @@ impl Message {
         }
     }
 
-    fn system_text(text: impl Into<String>) -> Self {
+    pub fn system_text(text: impl Into<String>) -> Self {
         Message::new(Role::System, vec![ContentItem::text(text)])
     }
 
-    fn user_text(text: impl Into<String>) -> Self {
+    pub fn user_text(text: impl Into<String>) -> Self {
         Message::new(Role::User, vec![ContentItem::text(text)])
     }
 
-    fn assistant_text(text: impl Into<String>) -> Self {
+    pub fn assistant_text(text: impl Into<String>) -> Self {
         Message::new(Role::Assistant, vec![ContentItem::text(text)])
     }
 
-    fn function_text(text: impl Into<String>) -> Self {
+    pub fn function_text(text: impl Into<String>) -> Self {
         Message::new(Role::Function, vec![ContentItem::text(text)])
     }
 
-    fn content_as_string(&self) -> String {
+    pub(crate) fn content_as_string(&self) -> String {
         self.content
             .iter()
-            .filter_map(|c| match c {
-                ContentItem::Text(t) => Some(t.as_str()),
+            .map(|c| match c {
+                ContentItem::Text(t) => t.as_str(),
             })
             .collect::<Vec<&str>>()
             .join("")
     }
 }
 
+/// 한 번의 도구 호출과 그 관찰 결과
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// 1부터 시작하는 ReAct 턴 번호
+    pub turn: usize,
+    pub name: String,
+    pub args: Value,
+    /// 모델에게 전달된 관찰 결과 (에러/거부 메시지 포함)
+    pub output: String,
+}
+
+/// `Agent::chat_with_steps`의 결과: 최종 답변과 중간 도구 호출 기록
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatResult {
+    pub answer: String,
+    pub steps: Vec<Step>,
+    /// 최대 턴 수에 도달해 중단된 경우 true. 이때 `answer`는 마지막으로 얻은 부분 답변입니다.
+    pub truncated: bool,
+}
+
+/// 다음 생성에 들어갈 프롬프트의 토큰 사용 현황 (`Agent::context_report`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContextReport {
+    /// 모델에게 실제로 전달되는 메시지별 (역할, 토큰 수). 시스템 메시지에는 도구 섹션이 포함됩니다.
+    pub messages: Vec<(Role, usize)>,
+    /// 프롬프트 전체의 토큰 수 (템플릿 토큰과 generation prompt 포함)
+    pub total: usize,
+    /// 모델의 컨텍스트 한도 (모르면 None)
+    pub limit: Option<usize>,
+}
+
+impl ContextReport {
+    /// 한도까지 남은 토큰 수 (넘었으면 0)
+    pub fn remaining(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.saturating_sub(self.total))
+    }
+
+    /// 한도 대비 사용 비율 (1.0 이상이면 다음 생성에서 `ContextLimitExceeded`)
+    pub fn usage_ratio(&self) -> Option<f32> {
+        self.limit
+            .filter(|&limit| limit > 0)
+            .map(|limit| self.total as f32 / limit as f32)
+    }
+
+    pub fn is_over_limit(&self) -> bool {
+        self.limit.is_some_and(|limit| self.total > limit)
+    }
+}
+
+/// 프롬프트가 모델의 컨텍스트 한도를 넘을 때의 처리 방식
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// `ContextLimitExceeded`를 그대로 반환 (기본값)
+    #[default]
+    Error,
+    /// 시스템 메시지를 제외한 가장 오래된 대화부터 들어갈 때까지 삭제
+    TrimOldest,
+    /// 이전 대화를 모델로 요약한 메시지 하나로 합침
+    Summarize,
+}
+
 pub struct Agent {
     #[allow(dead_code)]
     name: String,
     model: Box<dyn LLMBackend>,
     history: Vec<Message>,
     base_system_prompt: String,
-    tools: HashMap<String, Box<dyn Tool>>,
+    // 이름순으로 정렬된 맵: 시스템 프롬프트의 도구 순서가 실행마다 같도록 (재현성, 프롬프트 캐시)
+    tools: BTreeMap<String, Box<dyn Tool>>,
+    confirm: Option<ConfirmFn>,
+    usage: Usage,
+    prompt_format: Box<dyn PromptFormat>,
+    cancel: Option<CancellationToken>,
+    // `chat` 한 번에 허용하는 최대 실행 시간 (None이면 무제한)
+    max_duration: Option<Duration>,
+    on_event: Option<EventFn>,
+    // `chat_stream` 실행 중에만 설정되는 이벤트 채널
+    event_stream: Option<Sender<AgentEvent>>,
+    on_tool_result: Option<ToolResultFn>,
+    max_parse_retries: usize,
+    // true면 `final_answer` 도구를 시스템 프롬프트에 노출
+    final_answer_tool: bool,
+    // true면 없는 도구 이름을 유일하게 가장 가까운 등록 도구로 바꿔서 실행
+    autocorrect_tool_names: bool,
+    // true면 도구 호출 끝 표시(`</tool_call>`)가 나오는 즉시 생성을 멈춤
+    stop_at_tool_call: bool,
+    // true면 매 요청의 첫 턴 전에 계획을 생성
+    planning: bool,
+    last_plan: Option<String>,
+    // 도구 이름별 동시 실행/호출 간격 제한
+    tool_limits: HashMap<String, ToolLimiter>,
+    // 캐시 가능한 도구의 결과 캐시 (None이면 끔)
+    tool_cache: Option<ToolCache>,
+    // 관찰 결과가 이 토큰 수를 넘으면 요약해서 히스토리에 넣음
+    max_observation_tokens: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    // 현재 요청의 사용자 메시지 위치 (컨텍스트 정리는 이 메시지와 그 뒤를 건드리지 않음)
+    request_start: usize,
+    chat_template: ChatTemplate,
+    // `undo_last_mutation`이 되돌릴 저장소와 스냅샷 설정 (도구에 준 것과 같아야 함)
+    workspace: PathBuf,
+    git_snapshot: GitSnapshot,
 }
 
+/// 한 번의 `chat`에서 모델을 호출하는 최대 횟수 (ReAct 턴)
+const MAX_TURNS: usize = 5;
+
+/// 깨진 도구 호출에 대해 재출력을 요청하는 기본 횟수
+const DEFAULT_MAX_PARSE_RETRIES: usize = 2;
+
+/// 없는 도구 이름과 이 편집 거리 이내인 도구를 비슷한 이름으로 제안
+const MAX_TOOL_NAME_DISTANCE: usize = 2;
+
+/// 포함 관계(`run_shell` ⊂ `run_shell_command`)로 비슷한 이름을 찾을 때의 최소 길이
+const MIN_CONTAINED_NAME_LEN: usize = 4;
+
+/// 긴 도구 출력을 요약할 때 사용하는 시스템 프롬프트
+const OBSERVATION_SUMMARY_PROMPT: &str = "You condense tool outputs for an agent. \
+Keep every fact the agent may need: file paths, identifiers, numbers, error messages and \
+their locations. Drop repetition and boilerplate. Reply with the summary only.";
+
+/// 컨텍스트 초과로 이전 대화를 요약할 때 사용하는 시스템 프롬프트
+const HISTORY_SUMMARY_PROMPT: &str = "You condense a conversation between a user and an \
+agent so it can continue with less context. Keep the user's goals, decisions, facts learned \
+from tools (paths, identifiers, numbers, errors) and open questions. Reply with the summary only.";
+
+/// 도구 호출을 파싱하지 못했을 때 모델에게 돌려주는 교정 메시지
+const MALFORMED_TOOL_CALL_FEEDBACK: &str = "Your last tool call could not be parsed: it was not valid JSON \
+or was missing the function name. Please re-emit the tool call with valid JSON.";
+
+/// 계획 모드에서 행동 전에 계획을 요청하는 메시지 (계획 생성에만 쓰고 히스토리에는 남기지 않음)
+const PLANNING_PROMPT: &str = "Before taking any action, write a short numbered plan of the \
+steps you will take to complete the request above. Do not call any tools yet. Reply with the plan only.";
+
+/// 히스토리에 남기는 계획 뒤에 붙여 이어지는 턴에서 계획을 실행하도록 하는 문장
+const PLAN_HANDOFF: &str = "I will now carry out this plan step by step.";
+
+/// 루프 종료용 예약 도구 이름. 모델이 이 도구를 호출하면 인자를 최종 답변으로 즉시 반환합니다.
+pub const FINAL_ANSWER_TOOL: &str = "final_answer";
+
+/// 도구 실행 전 호출되는 승인 콜백 (도구 이름, 인자) -> 실행 허용 여부
+pub type ConfirmFn = Box<dyn Fn(&str, &Value) -> bool>;
+
+/// 도구 출력이 관찰 결과가 되기 전 호출되는 변환 콜백 (도구 이름, 원본 출력) -> 히스토리에 넣을 출력
+pub type ToolResultFn = Box<dyn FnMut(&str, String) -> String>;
+
 /// Builder for configuring an `Agent` before construction.
 pub struct AgentBuilder {
     name: String,
     model: Box<dyn LLMBackend>,
     system_prompt: String,
     tools: Vec<Box<dyn Tool>>,
+    confirm: Option<ConfirmFn>,
+    prompt_format: Box<dyn PromptFormat>,
+    cancel: Option<CancellationToken>,
+    max_duration: Option<Duration>,
+    on_event: Option<EventFn>,
+    on_tool_result: Option<ToolResultFn>,
+    max_parse_retries: usize,
+    thinking_config: Option<GenerationConfig>,
+    tool_prompt_template: Option<String>,
+    final_answer_tool: bool,
+    autocorrect_tool_names: bool,
+    stop_at_tool_call: bool,
+    planning: bool,
+    history: Vec<Message>,
+    tool_policies: Vec<(String, ToolPolicy)>,
+    tool_cache_ttl: Option<Duration>,
+    max_observation_tokens: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    chat_template: ChatTemplate,
+    // `undo_last_mutation`이 되돌릴 저장소와 스냅샷 설정 (도구에 준 것과 같아야 함)
+    workspace: PathBuf,
+    git_snapshot: GitSnapshot,
 }
 
 impl Agent {
@@ -162,7 +307,29 @@ impl Agent {
             model,
             history: Vec::new(),
             base_system_prompt: system_prompt.to_string(),
-            tools: HashMap::new(),
+            tools: BTreeMap::new(),
+            confirm: None,
+            usage: Usage::default(),
+            prompt_format: Box::new(QwenFnCallFormat::default()),
+            cancel: None,
+            max_duration: None,
+            on_event: None,
+            event_stream: None,
+            on_tool_result: None,
+            max_parse_retries: DEFAULT_MAX_PARSE_RETRIES,
+            final_answer_tool: false,
+            autocorrect_tool_names: false,
+            stop_at_tool_call: false,
+            planning: false,
+            last_plan: None,
+            tool_limits: HashMap::new(),
+            tool_cache: None,
+            max_observation_tokens: None,
+            overflow_policy: OverflowPolicy::Error,
+            request_start: 0,
+            chat_template: ChatTemplate::default(),
+            workspace: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            git_snapshot: GitSnapshot::default(),
         };
 
         agent.refresh_system_message();
@@ -176,9 +343,283 @@ impl Agent {
             model,
             system_prompt: system_prompt.to_string(),
             tools: Vec::new(),
+            confirm: None,
+            prompt_format: Box::new(QwenFnCallFormat::default()),
+            cancel: None,
+            max_duration: None,
+            on_event: None,
+            on_tool_result: None,
+            max_parse_retries: DEFAULT_MAX_PARSE_RETRIES,
+            thinking_config: None,
+            tool_prompt_template: None,
+            final_answer_tool: false,
+            autocorrect_tool_names: false,
+            stop_at_tool_call: false,
+            planning: false,
+            history: Vec::new(),
+            tool_policies: Vec::new(),
+            tool_cache_ttl: None,
+            max_observation_tokens: None,
+            overflow_policy: OverflowPolicy::Error,
+            chat_template: ChatTemplate::default(),
+            workspace: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            git_snapshot: GitSnapshot::default(),
+        }
+    }
+
+    /// 도구 실행 전 승인 콜백을 설정합니다 (Human-in-the-loop).
+    /// 콜백이 `false`를 반환하면 도구를 실행하지 않고 거부 메시지를 관찰 결과로 남깁니다.
+    pub fn set_confirm(&mut self, confirm: impl Fn(&str, &Value) -> bool + 'static) -> &mut Self {
+        self.confirm = Some(Box::new(confirm));
+        self
+    }
+
+    /// 취소 토큰을 설정합니다. 턴 경계와 토큰 생성 단계마다 확인되며,
+    /// 취소되면 `chat`은 `SuprascalarError::Cancelled`를 반환합니다.
+    pub fn set_cancellation(&mut self, token: CancellationToken) -> &mut Self {
+        self.model.set_cancellation(Some(token.clone()));
+        self.cancel = Some(token);
+        self
+    }
+
+    /// `chat` 한 번의 최대 실행 시간을 설정합니다 (`None`이면 무제한).
+    /// 턴 경계마다, 그리고 토큰 스트리밍을 지원하는 백엔드에서는 토큰마다 확인하며,
+    /// 시간이 다 되면 그때까지의 부분 답변을 담은 `SuprascalarError::Timeout`을 반환합니다.
+    pub fn set_max_duration(&mut self, max_duration: Option<Duration>) -> &mut Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// ReAct 루프 이벤트 콜백을 설정합니다 (로깅/UI 용도).
+    pub fn set_on_event(&mut self, on_event: impl FnMut(AgentEvent) + 'static) -> &mut Self {
+        self.on_event = Some(Box::new(on_event));
+        self
+    }
+
+    /// 도구 출력 후처리 콜백을 설정합니다 (비밀값 마스킹, 포맷 변경, 메타데이터 추가 등).
+    /// 관찰 결과 요약, `ToolResult` 이벤트, 히스토리 기록보다 먼저 적용되므로
+    /// 여기서 지운 내용은 모델에게도 로그에도 남지 않습니다.
+    pub fn set_on_tool_result(
+        &mut self,
+        on_tool_result: impl FnMut(&str, String) -> String + 'static,
+    ) -> &mut Self {
+        self.on_tool_result = Some(Box::new(on_tool_result));
+        self
+    }
+
+    /// 깨진 도구 호출에 대해 재출력을 요청할 최대 횟수를 설정합니다 (0이면 재시도 없음).
+    pub fn set_max_parse_retries(&mut self, retries: usize) -> &mut Self {
+        self.max_parse_retries = retries;
+        self
+    }
+
+    /// `final_answer` 도구를 시스템 프롬프트에 노출할지 설정합니다.
+    /// 노출하지 않아도 모델이 `final_answer`를 호출하면 루프는 종료됩니다.
+    pub fn set_final_answer_tool(&mut self, enabled: bool) -> &mut Self {
+        self.final_answer_tool = enabled;
+        self
+    }
+
+    /// 모델이 틀린 도구 이름(예: `list_file`, `run_shell`)을 호출했을 때
+    /// 비슷한 이름 중 가장 가까운 도구가 하나뿐이면 그 도구로 바꿔서 실행합니다 (기본: 꺼짐).
+    /// 꺼져 있으면 실행하지 않고 관찰 결과에 올바른 이름을 제안합니다.
+    pub fn set_autocorrect_tool_names(&mut self, enabled: bool) -> &mut Self {
+        self.autocorrect_tool_names = enabled;
+        self
+    }
+
+    /// 생성 중 도구 호출 하나가 끝나면(`</tool_call>`) 바로 멈추고 도구를 실행한 뒤 다음 턴에서 이어 씁니다 (기본: 꺼짐).
+    /// 호출 뒤에 모델이 관찰 결과를 지어내거나 토큰을 낭비하는 것을 막는 대신, 한 응답에 여러 호출을 묶지 못하고
+    /// 턴마다 호출 하나씩 실행됩니다. 백엔드가 stop 문자열을 지원하지 않거나 포맷에 끝 표시가 없으면 효과가 없습니다.
+    pub fn set_stop_at_tool_call(&mut self, enabled: bool) -> &mut Self {
+        self.stop_at_tool_call = enabled;
+        self
+    }
+
+    /// 계획 모드 (기본: 꺼짐). 켜면 요청마다 도구를 쓰기 전에 번호 매긴 계획을 먼저 생성해
+    /// 히스토리에 남기고(`AgentEvent::Plan`), 이어지는 ReAct 루프가 그 계획을 따라 진행합니다.
+    /// 생성이 한 번 늘어나는 대신 여러 단계가 필요한 작업에서 도구 사용이 안정적입니다.
+    pub fn set_planning(&mut self, enabled: bool) -> &mut Self {
+        self.planning = enabled;
+        self
+    }
+
+    /// 마지막 요청에서 생성한 계획 (계획 모드가 아니었으면 None)
+    pub fn last_plan(&self) -> Option<&str> {
+        self.last_plan.as_deref()
+    }
+
+    /// 도구별 동시 실행 수/호출 간격 제한을 설정합니다 (외부 API 호출 도구용).
+    /// 도구 호출이 디스패치될 때 제한을 만족할 때까지 기다린 뒤 실행합니다.
+    pub fn set_tool_policy(&mut self, tool_name: &str, policy: ToolPolicy) -> &mut Self {
+        self.tool_limits
+            .insert(tool_name.to_string(), ToolLimiter::new(policy));
+        self
+    }
+
+    /// 캐시 가능한 도구(`Tool::is_cacheable`, 예: `list_files`)의 결과를 `ttl` 동안 재사용합니다 (None이면 끔, 기본).
+    /// 같은 도구를 같은 인자로 다시 호출하면 실행하지 않고 저장된 결과를 돌려줍니다.
+    /// 캐시할 수 없는 도구가 성공하면 상태가 바뀌었을 수 있으므로 캐시를 비웁니다.
+    pub fn set_tool_cache(&mut self, ttl: Option<Duration>) -> &mut Self {
+        self.tool_cache = ttl.map(ToolCache::new);
+        self
+    }
+
+    /// 도구 출력이 `max_tokens`를 넘으면 모델로 한 번 요약한 뒤 히스토리에 넣습니다 (None이면 끔).
+    /// 여러 턴에 걸쳐 큰 파일을 읽어도 컨텍스트가 금방 차지 않도록 합니다.
+    pub fn set_max_observation_tokens(&mut self, max_tokens: Option<usize>) -> &mut Self {
+        self.max_observation_tokens = max_tokens;
+        self
+    }
+
+    /// 프롬프트가 컨텍스트 한도를 넘을 때의 처리 방식을 설정합니다 (기본: `Error`).
+    /// 현재 요청(마지막 사용자 메시지 이후)은 정리 대상에서 제외됩니다.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) -> &mut Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// 프롬프트 조립에 사용할 특수 토큰/역할 이름을 교체합니다 (기본: Qwen ChatML).
+    pub fn set_chat_template(&mut self, template: ChatTemplate) -> &mut Self {
+        self.chat_template = template;
+        self
+    }
+
+    /// [Advanced] `<think>` 구간에서만 사용할 샘플링 설정 (None이면 전체 응답에 단일 설정)
+    pub fn set_thinking_config(&mut self, config: Option<GenerationConfig>) -> &mut Self {
+        self.model.set_thinking_config(config);
+        self
+    }
+
+    fn emit(&mut self, event: AgentEvent) {
+        if let Some(on_event) = self.on_event.as_mut() {
+            on_event(event.clone());
+        }
+        if let Some(stream) = &self.event_stream {
+            // 받는 쪽이 끊겨도 루프는 계속 진행
+            let _ = stream.send(event);
+        }
+    }
+
+    /// 계획 모드의 첫 단계: 계획 요청을 잠시 붙여 계획을 생성하고, 요청은 빼고 계획만 assistant 메시지로 남깁니다.
+    /// 요청 메시지를 남기지 않으므로 마지막 사용자 메시지는 그대로 원래 요청이고, 컨텍스트 정리에서도 보호됩니다.
+    fn make_plan(&mut self) -> Result<()> {
+        self.history
+            .push(Message::user_text(PLANNING_PROMPT.to_string()));
+        let prompt = self.fit_context();
+        self.history.pop();
+        let response = self.model.generate(&prompt?)?;
+        if let Some(usage) = self.model.last_usage() {
+            self.usage += usage;
+        }
+
+        let plan = response
+            .rsplit_once("</think>")
+            .map_or(response.as_str(), |(_, rest)| rest)
+            .trim()
+            .to_string();
+        self.history.push(Message::assistant_text(format!(
+            "{}\n\n{}",
+            plan, PLAN_HANDOFF
+        )));
+        self.emit(AgentEvent::Plan(plan.clone()));
+        self.last_plan = Some(plan);
+        Ok(())
+    }
+
+    /// 메인 루프의 응답 생성. `chat_stream` 중이면 생성되는 텍스트 조각을 `Token` 이벤트로 보냅니다.
+    /// 요약 등 보조 생성은 `self.model.generate`를 직접 호출하므로 스트림에 섞이지 않습니다.
+    /// `stop_at_tool_call`이 켜져 있으면 이번 생성에만 도구 호출 끝 표시를 stop 문자열로 겁니다.
+    /// `deadline`이 있으면 이번 생성 전용 취소 토큰을 백엔드에 걸고, 토큰이 나올 때마다
+    /// 시간(과 사용자 취소 토큰)을 확인해 넘었으면 취소합니다. 생성 후 사용자 토큰을 되돌립니다.
+    fn generate_turn(&mut self, prompt: &str, deadline: Option<Instant>) -> Result<String> {
+        let stop = self
+            .prompt_format
+            .tool_call_end()
+            .filter(|_| self.stop_at_tool_call)
+            .map(str::to_string);
+        if let Some(stop) = stop.clone() {
+            self.model.set_stop_sequences(vec![stop]);
+        }
+        let timer = deadline.map(|_| CancellationToken::new());
+        if let Some(timer) = &timer {
+            self.model.set_cancellation(Some(timer.clone()));
+        }
+        let stream = self.event_stream.clone();
+        let result = if stream.is_some() || timer.is_some() {
+            let user_cancel = self.cancel.clone();
+            let timer = timer.clone();
+            self.model
+                .set_token_callback(Some(Box::new(move |text: &str| {
+                    if let Some(stream) = &stream {
+                        let _ = stream.send(AgentEvent::Token(text.to_string()));
+                    }
+                    if let Some(timer) = &timer
+                        && (deadline_passed(deadline)
+                            || user_cancel.as_ref().is_some_and(|c| c.is_cancelled()))
+                    {
+                        timer.cancel();
+                    }
+                })));
+            let result = self.model.generate(prompt);
+            self.model.set_token_callback(None);
+            result
+        } else {
+            self.model.generate(prompt)
+        };
+        if timer.is_some() {
+            self.model.set_cancellation(self.cancel.clone());
+        }
+        if stop.is_some() {
+            self.model.set_stop_sequences(Vec::new());
+        }
+        result
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+            return Err(SuprascalarError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// 제한 시간 초과 에러 (부분 답변은 최대 턴 도달 때와 같은 규칙)
+    fn timeout_error(&self, last_text: &str, steps: &[Step]) -> SuprascalarError {
+        SuprascalarError::Timeout {
+            limit: self.max_duration.unwrap_or_default(),
+            partial: partial_answer(last_text, steps),
         }
     }
 
+    /// 대화 전체에서 누적된 토큰 사용량
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// `undo_last_mutation`이 사용할 작업 디렉토리와 스냅샷 설정 (기본: 현재 디렉토리, 기본 접두어)
+    /// 도구에 `with_git_snapshot`으로 다른 접두어를 줬다면 같은 설정을 넘기세요.
+    pub fn set_git_snapshot(
+        &mut self,
+        workspace: impl Into<PathBuf>,
+        snapshot: GitSnapshot,
+    ) -> &mut Self {
+        self.workspace = workspace.into();
+        self.git_snapshot = snapshot;
+        self
+    }
+
+    /// 마지막 도구 실행이 파일에 남긴 변경을 자동 저장 스냅샷 시점으로 되돌립니다.
+    /// 최신 커밋이 자동 저장 커밋이 아니면(사용자 커밋이 뒤에 있거나 스냅샷이 없으면) 거부합니다.
+    /// 모델이 되돌려진 사실을 알 수 있도록 히스토리에도 기록합니다.
+    pub fn undo_last_mutation(&mut self) -> Result<String> {
+        let report = self.git_snapshot.undo_last(&self.workspace)?;
+        self.history.push(Message::user_text(format!(
+            "[The user undid the file changes from the last tool call. {}]",
+            report
+        )));
+        Ok(report)
+    }
+
     /// 도구 등록 메서드 (빌드 이후 런타임에 추가할 때 사용)
     pub fn register_tool(&mut self, tool: impl Tool + 'static) -> &mut Self {
         self.register_tool_box(Box::new(tool))
@@ -187,92 +628,186 @@ impl Agent {
     fn register_tool_box(&mut self, tool: Box<dyn Tool>) -> &mut Self {
         let name = tool.name().to_string();
         self.tools.insert(name, tool);
+        self
+    }
+
+    /// 페르소나(기본 시스템 프롬프트)를 교체합니다. 등록된 도구와 대화 기록은 유지됩니다.
+    pub fn set_system_prompt(&mut self, prompt: &str) -> &mut Self {
+        self.base_system_prompt = prompt.to_string();
         self.refresh_system_message();
         self
     }
 
     /// 시스템 메시지를 재구성하는 내부 메서드
+    /// 히스토리에는 기본 프롬프트만 저장합니다. 도구 섹션은 `preprocess`가 프롬프트를
+    /// 만들 때 한 번만 붙이므로, 여기서 함께 저장하면 도구 목록이 두 번 들어갑니다.
     fn refresh_system_message(&mut self) {
-        let mut full_prompt = self.base_system_prompt.clone();
-        if let Some(tool_section) = self.render_tool_system_prompt() {
-            full_prompt.push_str("\n\n");
-            full_prompt.push_str(&tool_section);
-        }
+        let prompt = self.base_system_prompt.clone();
 
-        if let Some(first_msg) = self.history.first_mut() {
-            if first_msg.role == Role::System {
-                first_msg.content = vec![ContentItem::text(full_prompt)];
-                return;
-            }
+        if let Some(first_msg) = self.history.first_mut()
+            && first_msg.role == Role::System
+        {
+            first_msg.content = vec![ContentItem::text(prompt)];
+            return;
         }
 
-        self.history.insert(0, Message::system_text(full_prompt));
+        self.history.insert(0, Message::system_text(prompt));
     }
 
-    /// Qwen 함수 호출 포맷을 따르는 시스템 프롬프트를 생성합니다.
+    /// 현재 프롬프트 포맷으로 도구 섹션을 생성합니다.
     fn render_tool_system_prompt(&self) -> Option<String> {
-        if self.tools.is_empty() {
-            return None;
-        }
-
-        let mut tool_descs = Vec::new();
-        let mut tool_names = Vec::new();
-
-        for tool in self.tools.values() {
-            let desc = FunctionDescriptor {
+        let mut specs = self
+            .tools
+            .values()
+            .map(|tool| FunctionDescriptor {
                 name: tool.name().to_string(),
                 description: tool.description().to_string(),
                 parameters: tool.parameters(),
-            };
-            tool_names.push(desc.name.clone());
-            tool_descs.push(ToolDescriptor {
-                kind: "function",
-                function: desc,
+            })
+            .collect::<Vec<_>>();
+        if self.final_answer_tool {
+            specs.push(FunctionDescriptor {
+                name: FINAL_ANSWER_TOOL.to_string(),
+                description: "Call this when the task is complete to give the final answer \
+                to the user. This ends the conversation turn."
+                    .to_string(),
+                parameters: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "answer": {
+                            "type": "string",
+                            "description": "The final answer shown to the user"
+                        }
+                    },
+                    "required": ["answer"]
+                }),
             });
         }
-
-        let tool_descs = tool_descs
-            .iter()
-            .map(|td| serde_json::to_string(td).unwrap_or_else(|_| "{}".into()))
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        let section = if special_code_mode()
-            && tool_names
-                .iter()
-                .any(|name| name.contains(CODE_TOOL_PATTERN))
-        {
-            FN_CALL_TEMPLATE_WITH_CI.replace("{tool_descs}", &tool_descs)
-        } else {
-            FN_CALL_TEMPLATE.replace("{tool_descs}", &tool_descs)
-        };
-
-        Some(section)
+        self.prompt_format.render_tools(&specs)
     }
 
     /// ReAct 루프가 적용된 Chat 메서드 (NousFnCallPrompt 스타일)
+    /// 최종 답변만 반환합니다. 중간 도구 호출이 필요하면 `chat_with_steps`를 사용하세요.
+    /// 최대 턴 수에 도달하면 부분 답변을 담은 `MaxTurnsExceeded`를 반환합니다.
     pub fn chat(&mut self, user_input: &str) -> Result<String> {
+        let result = self.chat_with_steps(user_input)?;
+        if result.truncated {
+            return Err(SuprascalarError::MaxTurnsExceeded {
+                turns: MAX_TURNS,
+                partial: result.answer,
+            });
+        }
+        Ok(result.answer)
+    }
+
+    /// `chat_with_steps`와 같지만 진행 상황을 `events` 채널로 실시간 전송합니다.
+    /// 모든 턴에 걸쳐 생성 중인 텍스트 조각(`Token`), 도구 호출/결과, 최종 답변이 순서대로 전달됩니다.
+    /// 호출은 끝날 때까지 블록되므로 받는 쪽(SSE 응답, TUI 등)은 다른 스레드에서 `Receiver`를 읽으세요.
+    /// `set_on_event`로 설정한 콜백도 그대로 호출됩니다.
+    pub fn chat_stream(
+        &mut self,
+        user_input: &str,
+        events: Sender<AgentEvent>,
+    ) -> Result<ChatResult> {
+        self.event_stream = Some(events);
+        let result = self.chat_with_steps(user_input);
+        self.event_stream = None;
+        result
+    }
+
+    /// `chat`과 같지만 이번 요청에서 실행된 도구 호출과 관찰 결과를 함께 반환합니다.
+    /// 최대 턴 수에 도달하면 에러 대신 `truncated: true`와 부분 답변을 반환합니다.
+    pub fn chat_with_steps(&mut self, user_input: &str) -> Result<ChatResult> {
+        let _span = tracing::info_span!("chat", agent = %self.name).entered();
+        let deadline = self.max_duration.map(|d| Instant::now() + d);
+        self.request_start = self.history.len();
         self.history.push(Message::user_text(user_input));
+        self.last_plan = None;
+        if self.planning {
+            self.check_cancelled()?;
+            self.make_plan()?;
+        }
+        let mut steps = Vec::new();
 
-        let max_turns = 5;
         let mut current_turn = 0;
+        let mut parse_retries = 0;
+        // 도구 호출과 함께 모델이 남긴 가장 최근의 텍스트 (최대 턴 도달 시 부분 답변)
+        let mut last_text = String::new();
 
         loop {
-            current_turn += 1;
-            if current_turn > max_turns {
-                return Err(SuprascalarError::Unknown(
-                    "Max agent turns exceeded".to_string(),
-                ));
+            self.check_cancelled()?;
+            if deadline_passed(deadline) {
+                return Err(self.timeout_error(&last_text, &steps));
             }
 
-            let prompt = self.build_prompt()?;
-            let response_text = self.model.generate(&prompt)?;
-            //log
-            // println!("{}", response_text);
+            current_turn += 1;
+            if current_turn > MAX_TURNS {
+                let answer = partial_answer(&last_text, &steps);
+                return Ok(ChatResult {
+                    answer,
+                    steps,
+                    truncated: true,
+                });
+            }
+            self.emit(AgentEvent::TurnStarted { turn: current_turn });
+            let _turn = tracing::debug_span!("turn", turn = current_turn).entered();
+
+            let prompt = self.fit_context()?;
+            let response_text = match self.generate_turn(&prompt, deadline) {
+                // 생성 도중 제한 시간이 지나 취소된 경우 (사용자 취소는 그대로 `Cancelled`)
+                Err(SuprascalarError::Cancelled)
+                    if deadline_passed(deadline) && self.check_cancelled().is_ok() =>
+                {
+                    return Err(self.timeout_error(&last_text, &steps));
+                }
+                result => result?,
+            };
+            if let Some(usage) = self.model.last_usage() {
+                self.usage += usage;
+                tracing::debug!(
+                    prompt_tokens = usage.prompt_tokens,
+                    completion_tokens = usage.completion_tokens,
+                    "model response"
+                );
+            }
+            self.emit(AgentEvent::ModelResponse(response_text.clone()));
 
             // 모델 응답을 우선 기록(원본 텍스트)
             let assistant_raw = Message::assistant_text(response_text.clone());
-            let parsed = self.postprocess_fncall_messages(vec![assistant_raw.clone()])?;
+            let parsed = self
+                .prompt_format
+                .postprocess(vec![assistant_raw.clone()])?;
+
+            let has_call = parsed.iter().any(|m| m.function_call.is_some());
+            if !has_call && self.prompt_format.looks_like_tool_call(&response_text) {
+                // 도구 호출을 시도했지만 파싱 실패: 원본을 남기고 재출력을 요청
+                if parse_retries >= self.max_parse_retries {
+                    return Err(SuprascalarError::MalformedToolCall {
+                        retries: parse_retries,
+                    });
+                }
+                parse_retries += 1;
+                self.history.push(assistant_raw);
+                self.history
+                    .push(Message::user_text(MALFORMED_TOOL_CALL_FEEDBACK.to_string()));
+                continue;
+            }
+
+            // 예약된 final_answer 호출이 있으면 다른 호출은 무시하고 즉시 종료
+            if let Some(fc) = parsed
+                .iter()
+                .filter_map(|m| m.function_call.as_ref())
+                .find(|fc| fc.name == FINAL_ANSWER_TOOL)
+            {
+                let answer = final_answer_text(&fc.arguments);
+                self.history.push(Message::assistant_text(answer.clone()));
+                self.emit(AgentEvent::FinalAnswer(answer.clone()));
+                return Ok(ChatResult {
+                    answer,
+                    steps,
+                    truncated: false,
+                });
+            }
 
             let mut function_calls: Vec<FunctionCall> = Vec::new();
             let mut answer_acc = String::new();
@@ -295,426 +830,1432 @@ impl Agent {
             }
 
             if function_calls.is_empty() {
-                if answer_acc.is_empty() {
-                    // 도구 호출이 없는 순수 답변
-                    return Ok(response_text);
-                }
-                return Ok(answer_acc);
+                // 도구 호출이 없으면 파싱된 답변(없으면 원본 응답)이 최종 답변
+                let answer = if answer_acc.is_empty() {
+                    response_text
+                } else {
+                    answer_acc
+                };
+                self.emit(AgentEvent::FinalAnswer(answer.clone()));
+                return Ok(ChatResult {
+                    answer,
+                    steps,
+                    truncated: false,
+                });
             }
 
-            for fc in function_calls {
+            let text = answer_acc
+                .rsplit_once("</think>")
+                .map_or(answer_acc.as_str(), |(_, rest)| rest)
+                .trim();
+            if !text.is_empty() {
+                last_text = text.to_string();
+            }
+
+            for mut fc in function_calls {
+                self.check_cancelled()?;
+
+                if self.autocorrect_tool_names
+                    && !self.tools.contains_key(&fc.name)
+                    && let Some(name) = self.nearest_tool(&fc.name)
+                {
+                    fc.name = name.to_string();
+                }
+
                 let args_value = serde_json::from_str::<Value>(&fc.arguments)
                     .unwrap_or_else(|_| Value::String(fc.arguments.clone()));
-                let tool_output = self.execute_tool(&fc.name, args_value);
-
-                let observation = Message::function_text(tool_output);
+                self.emit(AgentEvent::ToolCall {
+                    name: fc.name.clone(),
+                    args: args_value.clone(),
+                });
+                let executed = self.execute_tool(&fc.name, args_value.clone());
+                // 종료 도구가 성공하면 결과가 곧 최종 답변 (실패하면 모델이 다시 시도하도록 루프 유지)
+                let terminal = executed.is_ok()
+                    && self
+                        .tools
+                        .get(&fc.name)
+                        .is_some_and(|tool| tool.is_terminal());
+                let mut tool_output = executed.unwrap_or_else(|message| message);
+                if let Some(on_tool_result) = self.on_tool_result.as_mut() {
+                    tool_output = on_tool_result(&fc.name, tool_output);
+                }
+                // 최종 답변은 사용자에게 그대로 전달되므로 요약하지 않음
+                let tool_output = if terminal {
+                    tool_output
+                } else {
+                    self.compact_observation(&fc.name, tool_output)?
+                };
+                self.emit(AgentEvent::ToolResult {
+                    name: fc.name.clone(),
+                    output: tool_output.clone(),
+                });
+
+                let observation = Message::function_text(tool_output.clone());
                 self.history.push(observation);
+                steps.push(Step {
+                    turn: current_turn,
+                    name: fc.name,
+                    args: args_value,
+                    output: tool_output.clone(),
+                });
+
+                // 같은 응답의 남은 도구 호출은 실행하지 않음
+                if terminal {
+                    self.emit(AgentEvent::FinalAnswer(tool_output.clone()));
+                    return Ok(ChatResult {
+                        answer: tool_output,
+                        steps,
+                        truncated: false,
+                    });
+                }
             }
         }
     }
 
-    /// NousFnCallPrompt: 입력 메시지를 함수 호출 가능 형태로 사전 처리
-    fn preprocess_fncall_messages(&self, messages: &[Message]) -> Result<Vec<Message>> {
-        let mut processed: Vec<Message> = Vec::new();
-
-        for msg in messages.iter().cloned() {
-            match msg.role {
-                Role::System | Role::User => processed.push(msg),
-                Role::Assistant => {
-                    let mut content = msg.content.clone();
-                    if let Some(fc) = msg.function_call.clone() {
-                        if !special_code_mode() || !fc.name.contains(CODE_TOOL_PATTERN) {
-                            let parsed_args: Value = json5::from_str(&fc.arguments)
-                                .unwrap_or_else(|_| Value::String(fc.arguments.clone()));
-                            let fc_obj = json!({"name": fc.name, "arguments": parsed_args});
-                            let fc_text = format!(
-                                "<tool_call>\n{}\n</tool_call>",
-                                serde_json::to_string(&fc_obj).unwrap_or_else(|_| "{}".into())
-                            );
-                            ContentItem::push_into(&mut content, fc_text);
-                        } else {
-                            let mut parsed_args: Value = json5::from_str(&fc.arguments)
-                                .unwrap_or_else(|_| Value::String(fc.arguments.clone()));
-                            let code = parsed_args
-                                .get("code")
-                                .and_then(|c| c.as_str())
-                                .unwrap_or("")
-                                .to_string();
-                            if let Some(obj) = parsed_args.as_object_mut() {
-                                obj.insert("code".to_string(), Value::String(String::new()));
-                            }
-                            let fc_obj = json!({"name": fc.name, "arguments": parsed_args});
-                            let fc_text = format!(
-                                "<tool_call>\n{}\n<code>\n{}\n</code>\n</tool_call>",
-                                serde_json::to_string(&fc_obj).unwrap_or_else(|_| "{}".into()),
-                                code
-                            );
-                            ContentItem::push_into(&mut content, fc_text);
-                        }
-                    }
+    /// 관찰 결과가 토큰 예산을 넘으면 요약본으로 바꿉니다.
+    /// 요약에 실패하면 원본을 그대로 사용합니다 (취소는 그대로 전파).
+    fn compact_observation(&mut self, tool: &str, output: String) -> Result<String> {
+        let Some(max_tokens) = self.max_observation_tokens else {
+            return Ok(output);
+        };
+        // 토큰 수를 셀 수 없는 백엔드는 대략 4글자 = 1토큰으로 추정
+        let tokens = self
+            .model
+            .count_tokens(&output)
+            .unwrap_or_else(|_| output.chars().count() / 4);
+        if tokens <= max_tokens {
+            return Ok(output);
+        }
 
-                    if let Some(last) = processed.last_mut() {
-                        if last.role == Role::Assistant {
-                            if let Some(ContentItem::Text(t)) = last.content.last() {
-                                if !t.ends_with('\n') {
-                                    ContentItem::push_into(&mut last.content, "\n");
-                                }
-                            }
-                            last.content.extend(content);
-                            continue;
-                        }
-                    }
+        let prompt = self.auxiliary_prompt(
+            OBSERVATION_SUMMARY_PROMPT,
+            &format!(
+                "Summarize this output of the tool '{}' in at most {} tokens:\n\n{}",
+                tool, max_tokens, output
+            ),
+        );
+        let summary = match self.model.generate(&prompt) {
+            Ok(summary) => summary,
+            Err(SuprascalarError::Cancelled) => return Err(SuprascalarError::Cancelled),
+            Err(_) => return Ok(output),
+        };
+        if let Some(usage) = self.model.last_usage() {
+            self.usage += usage;
+        }
 
-                    processed.push(Message {
-                        role: Role::Assistant,
-                        content,
-                        reasoning_content: msg.reasoning_content,
-                        function_call: None,
-                        extra: msg.extra,
-                    });
-                }
-                Role::Function => {
-                    let mut content = msg.content.clone();
-                    content.insert(0, ContentItem::text("<tool_response>\n"));
-                    content.push(ContentItem::text("\n</tool_response>"));
-
-                    if let Some(last) = processed.last_mut() {
-                        if last.role == Role::User {
-                            ContentItem::push_into(&mut last.content, "\n");
-                            last.content.extend(content);
-                            continue;
-                        }
-                    }
+        let summary = summary
+            .rsplit_once("</think>")
+            .map_or(summary.as_str(), |(_, rest)| rest)
+            .trim();
+        Ok(format!(
+            "[Summarized: the original tool output was ~{} tokens]\n{}",
+            tokens, summary
+        ))
+    }
 
-                    processed.push(Message {
-                        role: Role::User,
-                        content,
-                        reasoning_content: None,
-                        function_call: None,
-                        extra: None,
-                    });
-                }
+    /// 생성 전에 프롬프트 길이를 확인합니다 (토큰 수를 셀 수 없는 백엔드는 건너뜀).
+    fn check_context(&self, prompt: &str) -> Result<()> {
+        let Some(limit) = self.model.context_limit() else {
+            return Ok(());
+        };
+        match self.model.count_tokens(prompt) {
+            Ok(current) if current > limit => {
+                Err(SuprascalarError::ContextLimitExceeded { limit, current })
             }
+            _ => Ok(()),
         }
+    }
 
-        if let Some(tool_system) = self.render_tool_system_prompt() {
-            if let Some(first) = processed.first_mut() {
-                if first.role == Role::System {
-                    ContentItem::push_into(&mut first.content, format!("\n\n{}", tool_system));
-                } else {
-                    processed.insert(0, Message::system_text(tool_system));
+    /// 프롬프트를 만들고, 한도를 넘으면 `overflow_policy`에 따라 히스토리를 정리합니다.
+    fn fit_context(&mut self) -> Result<String> {
+        let mut removed = 0;
+        let mut summarized = false;
+        let prompt = loop {
+            let prompt = self.build_prompt()?;
+            let err = match self.check_context(&prompt) {
+                Ok(()) => break prompt,
+                Err(err) => err,
+            };
+            // 정리 가능한 구간: 시스템 메시지 뒤부터 현재 요청의 사용자 메시지 앞까지
+            // (마지막 사용자 메시지는 재출력 요청 등일 수 있어 기준으로 삼으면 요청 자체가 정리됨)
+            let start = usize::from(self.history.first().is_some_and(|m| m.role == Role::System));
+            let end = self.request_start.min(self.history.len()).max(start);
+            if start == end {
+                return Err(err);
+            }
+
+            match self.overflow_policy {
+                OverflowPolicy::Error => return Err(err),
+                OverflowPolicy::TrimOldest => {
+                    // 도구 호출/결과가 짝을 잃지 않도록 다음 사용자 메시지 전까지 한 번에 삭제
+                    let next = self.history[start + 1..end]
+                        .iter()
+                        .position(|m| m.role == Role::User)
+                        .map_or(end, |i| start + 1 + i);
+                    removed += next - start;
+                    self.request_start -= next - start;
+                    self.history.drain(start..next);
+                }
+                OverflowPolicy::Summarize => {
+                    // 요약 후에도 넘치면 더 줄일 방법이 없음
+                    if summarized {
+                        return Err(err);
+                    }
+                    summarized = true;
+                    let summary = self.summarize_history(start, end)?;
+                    removed += end - start;
+                    self.request_start -= end - start - 1;
+                    self.history
+                        .splice(start..end, std::iter::once(Message::user_text(summary)));
                 }
-            } else {
-                processed.push(Message::system_text(tool_system));
             }
+        };
+
+        if removed > 0 {
+            self.emit(AgentEvent::ContextCompacted { removed });
         }
+        Ok(prompt)
+    }
 
-        Ok(processed)
+    /// `history[start..end]`를 모델로 요약합니다.
+    fn summarize_history(&mut self, start: usize, end: usize) -> Result<String> {
+        let transcript = self.history[start..end]
+            .iter()
+            .map(|msg| format!("[{}] {}", msg.role.as_str(), msg.content_as_string()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let prompt = self.auxiliary_prompt(
+            HISTORY_SUMMARY_PROMPT,
+            &format!("Summarize this conversation:\n\n{}", transcript),
+        );
+        let summary = self.model.generate(&prompt)?;
+        if let Some(usage) = self.model.last_usage() {
+            self.usage += usage;
+        }
+
+        let summary = summary
+            .rsplit_once("</think>")
+            .map_or(summary.as_str(), |(_, rest)| rest)
+            .trim();
+        Ok(format!(
+            "[Summary of the earlier conversation]\n{}",
+            summary
+        ))
     }
 
-    /// NousFnCallPrompt: 모델 응답을 함수 호출 구조로 역변환
-    fn postprocess_fncall_messages(&self, messages: Vec<Message>) -> Result<Vec<Message>> {
-        let mut new_messages = Vec::new();
-        let mut tool_id: usize = 1;
+    /// 모델에게 실제로 전달되는 시스템 프롬프트 (도구 섹션 포함, 디버깅용)
+    pub fn current_system_prompt(&self) -> Result<String> {
+        Ok(self
+            .preprocessed_history()?
+            .into_iter()
+            .find(|msg| msg.role == Role::System)
+            .map(|msg| msg.content_as_string())
+            .unwrap_or_default())
+    }
 
-        for msg in messages.into_iter() {
-            let role = msg.role;
-            let content = msg.content;
-            let reasoning_content = msg.reasoning_content;
-            let extra = msg.extra.unwrap_or_default();
+    /// 시스템 메시지를 제외한 대화 기록을 JSON으로 내보냅니다 (세션 저장용).
+    /// `reasoning_content`, `function_call`, `extra`까지 모두 포함되므로 `import_history`로 되돌리면
+    /// 같은 시스템 프롬프트와 도구 구성에서 `render_prompt`가 바이트 단위로 같은 결과를 냅니다.
+    pub fn export_history(&self) -> Result<String> {
+        let messages: Vec<&Message> = self
+            .history
+            .iter()
+            .filter(|msg| msg.role != Role::System)
+            .collect();
+        Ok(serde_json::to_string_pretty(&messages)?)
+    }
 
-            match role {
-                Role::System | Role::User => {
-                    new_messages.push(Message {
-                        role,
-                        content,
-                        reasoning_content,
-                        function_call: None,
-                        extra: if extra.is_empty() { None } else { Some(extra) },
-                    });
-                }
-                Role::Assistant => {
-                    if let Some(reason) = reasoning_content {
-                        new_messages.push(Message {
-                            role: Role::Assistant,
-                            content: vec![],
-                            reasoning_content: Some(reason),
-                            function_call: None,
-                            extra: None,
-                        });
-                    }
+    /// `export_history`로 내보낸 대화 기록으로 현재 기록을 교체합니다.
+    /// 시스템 메시지는 현재 에이전트의 것을 유지하고, JSON 안의 `System` 메시지는 무시합니다.
+    pub fn import_history(&mut self, json: &str) -> Result<&mut Self> {
+        let messages: Vec<Message> = serde_json::from_str(json)?;
+        self.history.retain(|msg| msg.role == Role::System);
+        self.history
+            .extend(messages.into_iter().filter(|msg| msg.role != Role::System));
+        Ok(self)
+    }
 
-                    let mut new_content = Vec::new();
+    /// 다음 `generate`에 들어갈 프롬프트의 메시지별 토큰 수와 한도를 계산합니다 (UI 진행 표시, 디버깅용).
+    /// 백엔드가 토큰을 셀 수 없으면 `Unsupported` 에러를 반환합니다.
+    pub fn context_report(&self) -> Result<ContextReport> {
+        let messages = self
+            .preprocessed_history()?
+            .into_iter()
+            .map(|msg| {
+                let turn = self
+                    .chat_template
+                    .render_turn(&msg.role, &msg.content_as_string());
+                Ok((msg.role, self.model.count_tokens(&turn)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ContextReport {
+            messages,
+            total: self.model.count_tokens(&self.build_prompt()?)?,
+            limit: self.model.context_limit(),
+        })
+    }
 
-                    for item in content.into_iter() {
-                        let (item_type, item_text) = item.get_type_and_value();
+    /// 다음 `generate`에 들어갈 프롬프트 전체를 렌더링합니다 (읽기 전용, 디버깅용).
+    pub fn render_prompt(&self) -> Result<String> {
+        self.build_prompt()
+    }
 
-                        if item_type != "text" {
-                            new_content.push(item);
-                            continue;
-                        }
+    fn preprocessed_history(&self) -> Result<Vec<Message>> {
+        let tool_system = self.render_tool_system_prompt();
+        self.prompt_format
+            .preprocess(&self.history, tool_system.as_deref())
+    }
 
-                        let mut thought_in_content = false;
-                        if item_text.contains("<think>") {
-                            thought_in_content = true;
-                        }
+    fn build_prompt(&self) -> Result<String> {
+        let processed = self.preprocessed_history()?;
+        Ok(self.chat_template.render(&processed))
+    }
 
-                        let mut remaining_text = item_text.to_string();
-                        if thought_in_content && remaining_text.contains("</think>") {
-                            let parts: Vec<&str> = remaining_text.split("</think>").collect();
-                            let before = parts[..parts.len() - 1].join("</think>") + "</think>";
-                            new_content.push(ContentItem::text(before));
-                            remaining_text = parts.last().unwrap_or(&"").to_string();
-                        }
+    /// 대화 기록과 무관한 보조 호출(요약 등)용 프롬프트
+    /// 추론 블록을 비워서 프리필합니다 (Qwen3 non-thinking 모드).
+    fn auxiliary_prompt(&self, system: &str, user: &str) -> String {
+        let template = &self.chat_template;
+        format!(
+            "{}{}{}{}{}",
+            template.bos,
+            template.render_turn(&Role::System, system),
+            template.render_turn(&Role::User, user),
+            template.generation_prompt,
+            template.no_think_prefill
+        )
+    }
 
-                        if let Some(idx) = remaining_text.find("<tool_call>") {
-                            let tool_call_list: Vec<&str> =
-                                remaining_text.split("<tool_call>").collect();
-                            let pre_thought = tool_call_list[0];
-                            if !pre_thought.trim().is_empty() {
-                                new_content.push(ContentItem::text(pre_thought));
-                            }
-
-                            for txt in tool_call_list.into_iter().skip(1) {
-                                if txt.trim().is_empty() {
-                                    continue;
-                                }
-
-                                if !txt.contains("</tool_call>") {
-                                    let (fn_name, fn_args) = extract_fn(txt);
-                                    if !fn_name.is_empty() {
-                                        if !new_content.is_empty() {
-                                            new_messages.push(Message {
-                                                role: Role::Assistant,
-                                                content: new_content.clone(),
-                                                reasoning_content: None,
-                                                function_call: None,
-                                                extra: None,
-                                            });
-                                            new_content.clear();
-                                        }
-
-                                        let mut extra_map = extra.clone();
-                                        extra_map.insert("function_id".into(), tool_id.to_string());
-                                        tool_id += 1;
-
-                                        new_messages.push(Message {
-                                            role: Role::Assistant,
-                                            content: Vec::new(),
-                                            reasoning_content: None,
-                                            function_call: Some(FunctionCall {
-                                                name: fn_name,
-                                                arguments: fn_args,
-                                            }),
-                                            extra: Some(extra_map),
-                                        });
-                                    }
-                                    continue;
-                                }
-
-                                let parts: Vec<&str> = txt.split("</tool_call>").collect();
-                                if !new_content.is_empty() {
-                                    new_messages.push(Message {
-                                        role: Role::Assistant,
-                                        content: new_content.clone(),
-                                        reasoning_content: None,
-                                        function_call: None,
-                                        extra: None,
-                                    });
-                                    new_content.clear();
-                                }
-
-                                let mut fn_obj: Option<Value> = None;
-
-                                if special_code_mode()
-                                    && parts[0].contains("<code>")
-                                    && parts[0].contains("</code>")
-                                {
-                                    let mut code_sections = parts[0].split("<code>");
-                                    if let Some(first) = code_sections.next() {
-                                        if let Ok(v) = json5::from_str::<Value>(first) {
-                                            fn_obj = Some(v);
-                                        }
-                                    }
-                                    if let Some(last_section) = code_sections.next() {
-                                        let code = last_section.replace("</code>", "");
-                                        if let Some(Value::Object(ref mut obj)) = fn_obj {
-                                            if let Some(args) = obj.get_mut("arguments") {
-                                                if let Some(args_obj) = args.as_object_mut() {
-                                                    args_obj
-                                                        .insert("code".into(), Value::String(code));
-                                                }
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    if let Ok(v) = json5::from_str::<Value>(parts[0].trim()) {
-                                        fn_obj = Some(v);
-                                    }
-                                }
-
-                                if let Some(fn_obj) = fn_obj {
-                                    if let (Some(fn_name), Some(arguments)) = (
-                                        fn_obj.get("name").and_then(|v| v.as_str()),
-                                        fn_obj.get("arguments"),
-                                    ) {
-                                        let mut extra_map = extra.clone();
-                                        extra_map.insert("function_id".into(), tool_id.to_string());
-                                        tool_id += 1;
-
-                                        new_messages.push(Message {
-                                            role: Role::Assistant,
-                                            content: Vec::new(),
-                                            reasoning_content: None,
-                                            function_call: Some(FunctionCall {
-                                                name: fn_name.to_string(),
-                                                arguments: serde_json::to_string(arguments)
-                                                    .unwrap_or_else(|_| "{}".into()),
-                                            }),
-                                            extra: Some(extra_map),
-                                        });
-                                    }
-                                } else {
-                                    let (fn_name, fn_args) = extract_fn(parts[0].trim());
-                                    if !fn_name.is_empty() {
-                                        let mut extra_map = extra.clone();
-                                        extra_map.insert("function_id".into(), tool_id.to_string());
-                                        tool_id += 1;
-
-                                        new_messages.push(Message {
-                                            role: Role::Assistant,
-                                            content: Vec::new(),
-                                            reasoning_content: None,
-                                            function_call: Some(FunctionCall {
-                                                name: fn_name,
-                                                arguments: fn_args,
-                                            }),
-                                            extra: Some(extra_map),
-                                        });
-                                    }
-                                }
-                            }
-                        } else {
-                            if !remaining_text.is_empty() {
-                                new_content.push(ContentItem::text(remaining_text));
-                            }
-                        }
-                    }
+    /// 이름이 비슷한 등록 도구 (가까운 순, 최대 3개)
+    /// 편집 거리가 짧거나 한쪽 이름이 다른 쪽에 포함되면(`run_shell` → `run_shell_command`) 비슷한 것으로 봅니다.
+    fn similar_tools(&self, name: &str) -> Vec<&str> {
+        let name = name.trim().to_lowercase();
+        let mut scored: Vec<(usize, &str)> = self
+            .tools
+            .keys()
+            .filter_map(|tool| {
+                let lowered = tool.to_lowercase();
+                let distance = levenshtein(&name, &lowered);
+                let contained = name.len() >= MIN_CONTAINED_NAME_LEN
+                    && (lowered.contains(name.as_str()) || name.contains(lowered.as_str()));
+                (distance <= MAX_TOOL_NAME_DISTANCE || contained)
+                    .then_some((distance, tool.as_str()))
+            })
+            .collect();
+        scored.sort();
+        scored.into_iter().take(3).map(|(_, tool)| tool).collect()
+    }
 
-                    if !new_content.is_empty() {
-                        new_messages.push(Message {
-                            role: Role::Assistant,
-                            content: new_content,
-                            reasoning_content: None,
-                            function_call: None,
-                            extra: if extra.is_empty() { None } else { Some(extra) },
-                        });
-                    }
-                }
-                Role::Function => {
-                    // Function 역할은 입력으로만 들어오지 않고, 실행 결과로만 추가될 예정
-                }
-            }
+    /// 비슷한 도구 중 가장 가까운 것이 하나뿐일 때만 그 이름
+    fn nearest_tool(&self, name: &str) -> Option<&str> {
+        let key = name.trim().to_lowercase();
+        let similar = self.similar_tools(name);
+        let distance = |tool: &str| levenshtein(&key, &tool.to_lowercase());
+        match similar.as_slice() {
+            [only] => Some(*only),
+            [first, second, ..] if distance(first) < distance(second) => Some(*first),
+            _ => None,
         }
-
-        Ok(new_messages)
     }
 
-    fn build_prompt(&self) -> Result<String> {
-        let processed = self.preprocess_fncall_messages(&self.history)?;
-        let mut prompt = String::new();
+    /// 도구를 실행합니다. `Err`는 실행되지 않았거나 실패한 경우로, 모델에게 그대로 보여줄 메시지입니다.
+    fn execute_tool(&self, name: &str, args: Value) -> std::result::Result<String, String> {
+        let _span = tracing::debug_span!("tool", name).entered();
+        if let Some(confirm) = &self.confirm
+            && !confirm(name, &args)
+        {
+            tracing::info!("tool call denied by the user");
+            return Err(format!(
+                "User denied this action: tool '{}' was not executed.",
+                name
+            ));
+        }
 
-        for msg in processed {
-            let role = msg.role.as_str();
-            let content = msg.content_as_string();
-            prompt.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", role, content));
+        let tool = self.tools.get(name);
+        // 캐시 가능한 도구면 같은 인자의 최근 결과를 그대로 사용
+        let cache_key = self
+            .tool_cache
+            .as_ref()
+            .filter(|_| tool.is_some_and(|t| t.is_cacheable()))
+            .map(|_| ToolCache::key(name, &args));
+        if let (Some(cache), Some(key)) = (&self.tool_cache, &cache_key)
+            && let Some(output) = cache.get(key)
+        {
+            tracing::debug!("tool result served from cache");
+            return Ok(output);
         }
 
-        prompt.push_str("<|im_start|>assistant\n");
-        Ok(prompt)
-    }
+        // 정책이 있으면 슬롯을 잡고 실행 (실행이 끝나면 반납)
+        let _permit = self.tool_limits.get(name).map(|limiter| limiter.acquire());
 
-    fn execute_tool(&self, name: &str, args: Value) -> String {
-        match self.tools.get(name) {
+        match tool {
             Some(tool) => match tool.execute(args) {
-                Ok(output) => output,
-                Err(e) => format!("Error executing tool: {}", e),
+                Ok(output) => {
+                    tracing::debug!(output_chars = output.len(), "tool finished");
+                    if let Some(cache) = &self.tool_cache {
+                        match cache_key {
+                            Some(key) => cache.insert(key, output.clone()),
+                            None => cache.clear(),
+                        }
+                    }
+                    Ok(output)
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "tool failed");
+                    // 인자 오류/정책 차단은 그대로, 나머지는 ToolExecution으로 감싸 일관된 형태로 전달
+                    let err = match e {
+                        SuprascalarError::InvalidToolInput(_)
+                        | SuprascalarError::CommandBlocked { .. }
+                        | SuprascalarError::CommandTimeout { .. }
+                        | SuprascalarError::ToolExecution { .. } => e,
+                        other => SuprascalarError::ToolExecution {
+                            tool: name.to_string(),
+                            message: other.to_string(),
+                        },
+                    };
+                    Err(format!("Error: {}", err))
+                }
             },
-            None => format!("Error: Tool '{}' not found.", name),
+            None => {
+                tracing::warn!("unknown tool");
+                let similar = self.similar_tools(name);
+                Err(if similar.is_empty() {
+                    format!(
+                        "Error: Tool '{}' not found. Available tools: {}.",
+                        name,
+                        self.tools.keys().cloned().collect::<Vec<_>>().join(", ")
+                    )
+                } else {
+                    format!(
+                        "Error: Tool '{}' not found. Did you mean '{}'?",
+                        name,
+                        similar.join("' or '")
+                    )
+                })
+            }
         }
     }
 }
 
-#[derive(Serialize)]
-struct ToolDescriptor {
-    #[serde(rename = "type")]
-    kind: &'static str,
-    function: FunctionDescriptor,
+/// 두 문자열의 편집 거리 (문자 단위 Levenshtein)
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
 }
 
-#[derive(Serialize)]
-struct FunctionDescriptor {
-    name: String,
-    description: String,
-    parameters: Value,
+/// 최대 턴 수에 도달했을 때 돌려줄 부분 답변: 모델이 마지막으로 남긴 텍스트,
+/// 없으면 마지막 도구 호출의 관찰 결과
+fn partial_answer(last_text: &str, steps: &[Step]) -> String {
+    if !last_text.is_empty() {
+        return last_text.to_string();
+    }
+    match steps.last() {
+        Some(step) => format!(
+            "(No final answer yet. Last result from '{}':)\n{}",
+            step.name, step.output
+        ),
+        None => String::new(),
+    }
 }
 
-fn special_code_mode() -> bool {
-    env::var("SPECIAL_CODE_MODE")
-        .unwrap_or_else(|_| "false".to_string())
-        .to_lowercase()
-        == "true"
+/// 제한 시간이 지났는지 (제한이 없으면 항상 `false`)
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| Instant::now() >= d)
 }
 
-// Mainly for removing incomplete special tokens when streaming the output
-fn remove_incomplete_special_tokens(text: &str) -> String {
-    if text == "<tool_call>\n{\"name\": " {
-        return String::new();
+/// `final_answer` 호출 인자에서 답변 텍스트를 꺼냅니다.
+/// `{"answer": ...}` 형태가 기본이며, 문자열이나 다른 형태의 인자는 그대로 사용합니다.
+fn final_answer_text(arguments: &str) -> String {
+    match serde_json::from_str::<Value>(arguments) {
+        Ok(Value::String(text)) => text,
+        Ok(Value::Object(map)) => ["answer", "content", "text"]
+            .iter()
+            .find_map(|key| map.get(*key).and_then(Value::as_str))
+            .map(str::to_string)
+            .unwrap_or_else(|| Value::Object(map).to_string()),
+        Ok(other) => other.to_string(),
+        Err(_) => arguments.to_string(),
     }
-    text.to_string()
 }
 
-fn extract_fn(text: &str) -> (String, String) {
-    let mut fn_name = String::new();
-    let mut fn_args = String::new();
+impl AgentBuilder {
+    /// Add a tool before building the agent.
+    pub fn with_tool(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.push(Box::new(tool));
+        self
+    }
 
-    let fn_name_s = "\"name\": \"";
-    let fn_name_e = "\", \"";
-    let fn_args_s = "\"arguments\": ";
+    /// Require approval before every tool execution.
+    pub fn with_confirm(mut self, confirm: impl Fn(&str, &Value) -> bool + 'static) -> Self {
+        self.confirm = Some(Box::new(confirm));
+        self
+    }
 
-    if let Some(i) = text.find(fn_name_s) {
-        let rest = &text[i + fn_name_s.len()..];
-        if let Some(j) = rest.find(fn_name_e) {
-            fn_name = rest[..j].to_string();
-        }
+    /// Select how tools are rendered and tool calls are parsed (default: Qwen fncall).
+    pub fn with_prompt_format(mut self, format: impl PromptFormat + 'static) -> Self {
+        self.prompt_format = Box::new(format);
+        self
     }
 
-    if let Some(k) = text.find(fn_args_s) {
-        let rest = &text[k + fn_args_s.len()..];
-        fn_args = rest.trim().to_string();
-        if fn_args.len() > 2 {
-            fn_args = fn_args[..fn_args.len() - 1].to_string();
-        } else {
-            fn_args.clear();
-        }
+    /// Replace the instructions of the tools section in the system prompt.
+    /// `{tool_descs}` is substituted with the tool signatures. Applied to the
+    /// prompt format at build time regardless of call order; formats without
+    /// template support (e.g. `JsonActionFormat`) keep their built-in text.
+    pub fn with_tool_prompt_template(mut self, template: impl Into<String>) -> Self {
+        self.tool_prompt_template = Some(template.into());
+        self
     }
 
-    (fn_name, fn_args)
-}
+    /// Use the Qwen fncall format with code-interpreter mode set explicitly
+    /// instead of reading `SPECIAL_CODE_MODE` from the environment.
+    pub fn with_code_interpreter_mode(self, enabled: bool) -> Self {
+        self.with_prompt_format(QwenFnCallFormat::new(enabled))
+    }
 
-impl AgentBuilder {
-    /// Add a tool before building the agent.
-    pub fn with_tool(mut self, tool: impl Tool + 'static) -> Self {
-        self.tools.push(Box::new(tool));
+    /// Abort `chat` when the token is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Fail `chat` with `SuprascalarError::Timeout` once it has run longer than
+    /// `max_duration`. Checked between turns and, on streaming backends, between tokens.
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Receive `AgentEvent`s while `chat` runs.
+    pub fn with_on_event(mut self, on_event: impl FnMut(AgentEvent) + 'static) -> Self {
+        self.on_event = Some(Box::new(on_event));
+        self
+    }
+
+    /// Post-process every tool output (e.g. redact secrets) before it becomes an observation.
+    pub fn with_on_tool_result(
+        mut self,
+        on_tool_result: impl FnMut(&str, String) -> String + 'static,
+    ) -> Self {
+        self.on_tool_result = Some(Box::new(on_tool_result));
+        self
+    }
+
+    /// Ask the model to re-emit a malformed tool call up to `retries` times (default: 2).
+    pub fn with_max_parse_retries(mut self, retries: usize) -> Self {
+        self.max_parse_retries = retries;
+        self
+    }
+
+    /// [Advanced] Sample with `config` inside `<think>` blocks and with the
+    /// backend's own config for the answer (default: one config for both).
+    pub fn with_thinking_config(mut self, config: GenerationConfig) -> Self {
+        self.thinking_config = Some(config);
+        self
+    }
+
+    /// Advertise the reserved `final_answer` tool so the model can end the loop explicitly.
+    pub fn with_final_answer_tool(mut self) -> Self {
+        self.final_answer_tool = true;
+        self
+    }
+
+    /// Dispatch a misspelled tool call to the unique nearest registered tool
+    /// instead of answering with a "did you mean" suggestion.
+    pub fn with_autocorrect_tool_names(mut self) -> Self {
+        self.autocorrect_tool_names = true;
+        self
+    }
+
+    /// Stop generating as soon as a tool call is complete (`</tool_call>`),
+    /// run the tool, and continue in the next turn.
+    pub fn with_stop_at_tool_call(mut self) -> Self {
+        self.stop_at_tool_call = true;
+        self
+    }
+
+    /// Limit concurrency and call rate for the tool named `tool_name`.
+    pub fn with_tool_policy(mut self, tool_name: &str, policy: ToolPolicy) -> Self {
+        self.tool_policies.push((tool_name.to_string(), policy));
+        self
+    }
+
+    /// Have the model write a numbered plan (without tool calls) before the
+    /// first turn of each request, then act on it in the normal ReAct loop.
+    pub fn with_planning(mut self, enabled: bool) -> Self {
+        self.planning = enabled;
+        self
+    }
+
+    /// Reuse results of cacheable tools (`Tool::is_cacheable`) called again
+    /// with the same arguments within `ttl`.
+    pub fn with_tool_cache(mut self, ttl: Duration) -> Self {
+        self.tool_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Summarize tool outputs longer than `max_tokens` before adding them to history.
+    pub fn with_max_observation_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_observation_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Trim or summarize old messages instead of failing when the prompt
+    /// exceeds the model's context (default: `OverflowPolicy::Error`).
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Assemble prompts with a non-Qwen chat template (default: `ChatTemplate::chatml()`).
+    pub fn with_chat_template(mut self, template: ChatTemplate) -> Self {
+        self.chat_template = template;
+        self
+    }
+
+    /// Repository and snapshot settings used by `Agent::undo_last_mutation`
+    /// (default: the current directory with the default auto-save prefix).
+    pub fn with_git_snapshot(
+        mut self,
+        workspace: impl Into<PathBuf>,
+        snapshot: GitSnapshot,
+    ) -> Self {
+        self.workspace = workspace.into();
+        self.git_snapshot = snapshot;
+        self
+    }
+
+    /// Seed the conversation with prior turns (few-shot examples or a resumed session).
+    /// Messages are placed right after the system message; `System` messages are ignored
+    /// because the agent builds its own from the system prompt and tools.
+    pub fn with_history(mut self, history: Vec<Message>) -> Self {
+        self.history = history;
         self
     }
 
     /// Finalize and construct the agent.
     pub fn build(self) -> Result<Agent> {
         let mut agent = Agent::new(&self.name, self.model, &self.system_prompt);
+        agent.confirm = self.confirm;
+        agent.prompt_format = self.prompt_format;
+        if let Some(template) = self.tool_prompt_template {
+            agent.prompt_format.set_tool_template(template);
+        }
+        agent.on_event = self.on_event;
+        agent.on_tool_result = self.on_tool_result;
+        agent.max_parse_retries = self.max_parse_retries;
+        if self.final_answer_tool {
+            agent.set_final_answer_tool(true);
+        }
+        agent.autocorrect_tool_names = self.autocorrect_tool_names;
+        agent.stop_at_tool_call = self.stop_at_tool_call;
+        agent.planning = self.planning;
+        if self.thinking_config.is_some() {
+            agent.set_thinking_config(self.thinking_config);
+        }
+        if let Some(token) = self.cancel {
+            agent.set_cancellation(token);
+        }
+        agent.max_duration = self.max_duration;
         for tool in self.tools {
             agent.register_tool_box(tool);
         }
+        agent.max_observation_tokens = self.max_observation_tokens;
+        agent.overflow_policy = self.overflow_policy;
+        agent.chat_template = self.chat_template;
+        agent.workspace = self.workspace;
+        agent.git_snapshot = self.git_snapshot;
+        for (name, policy) in self.tool_policies {
+            agent.set_tool_policy(&name, policy);
+        }
+        agent.set_tool_cache(self.tool_cache_ttl);
+        agent.history.extend(
+            self.history
+                .into_iter()
+                .filter(|msg| msg.role != Role::System),
+        );
         Ok(agent)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MockBackend;
+    use crate::tools::FnTool;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Qwen 형식의 도구 호출 응답
+    fn tool_call(name: &str, args: Value) -> String {
+        format!(
+            "<tool_call>\n{}\n</tool_call>",
+            json!({"name": name, "arguments": args})
+        )
+    }
+
+    /// `text` 인자를 "echo: ..."로 돌려주는 도구와 호출 횟수
+    fn echo_tool() -> (FnTool, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let tool = FnTool::new(
+            "echo",
+            "Echoes the text back",
+            json!({
+                "type": "object",
+                "properties": {"text": {"type": "string"}},
+                "required": ["text"]
+            }),
+            move |args| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(format!(
+                    "echo: {}",
+                    args["text"].as_str().unwrap_or_default()
+                ))
+            },
+        );
+        (tool, calls)
+    }
+
+    /// 정해진 응답을 돌려주는 모델과 `echo` 도구를 가진 에이전트, 도구 호출 횟수, 프롬프트 기록
+    fn echo_agent<S: Into<String>>(
+        responses: impl IntoIterator<Item = S>,
+    ) -> (Agent, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>) {
+        let backend = MockBackend::new(responses);
+        let prompts = backend.prompt_log();
+        let (tool, calls) = echo_tool();
+        let agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(tool)
+            .build()
+            .unwrap();
+        (agent, calls, prompts)
+    }
+
+    #[test]
+    fn runs_tool_then_returns_final_answer() {
+        let (mut agent, calls, prompts) = echo_agent([
+            format!("Let me echo.\n{}", tool_call("echo", json!({"text": "hi"}))),
+            "The tool said hi.".to_string(),
+        ]);
+
+        let result = agent.chat_with_steps("Say hi").unwrap();
+        assert_eq!(result.answer, "The tool said hi.");
+        assert!(!result.truncated);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            result.steps,
+            vec![Step {
+                turn: 1,
+                name: "echo".into(),
+                args: json!({"text": "hi"}),
+                output: "echo: hi".into(),
+            }]
+        );
+
+        let roles: Vec<Role> = agent.history.iter().map(|m| m.role.clone()).collect();
+        assert_eq!(
+            roles,
+            [
+                Role::System,
+                Role::User,
+                Role::Assistant,
+                Role::Assistant,
+                Role::Function,
+                Role::Assistant
+            ]
+        );
+        assert_eq!(agent.history[2].content_as_string(), "Let me echo.\n");
+        let call = agent.history[3].function_call.as_ref().unwrap();
+        assert_eq!(call.name, "echo");
+        assert_eq!(agent.history[4].content_as_string(), "echo: hi");
+        assert_eq!(agent.history[5].content_as_string(), "The tool said hi.");
+
+        // 두 번째 생성에는 관찰 결과가 들어감
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].contains("<tool_response>\necho: hi\n</tool_response>"));
+    }
+
+    #[test]
+    fn stops_after_max_turns_with_partial_answer() {
+        let response = format!(
+            "Still working.\n{}",
+            tool_call("echo", json!({"text": "again"}))
+        );
+        let (mut agent, calls, _) = echo_agent(vec![response.clone(); MAX_TURNS]);
+
+        let result = agent.chat_with_steps("Loop forever").unwrap();
+        assert!(result.truncated);
+        assert_eq!(result.answer, "Still working.");
+        assert_eq!(result.steps.len(), MAX_TURNS);
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_TURNS);
+
+        let (mut agent, _, _) = echo_agent(vec![response; MAX_TURNS]);
+        match agent.chat("Loop forever") {
+            Err(SuprascalarError::MaxTurnsExceeded { turns, partial }) => {
+                assert_eq!(turns, MAX_TURNS);
+                assert_eq!(partial, "Still working.");
+            }
+            other => panic!("expected MaxTurnsExceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_tool_is_reported_to_the_model() {
+        let (mut agent, calls, _) = echo_agent([
+            tool_call("delete_everything", json!({})),
+            "I cannot do that.".to_string(),
+        ]);
+
+        let result = agent.chat_with_steps("Clean up").unwrap();
+        assert_eq!(result.answer, "I cannot do that.");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(result.steps.len(), 1);
+        assert_eq!(
+            result.steps[0].output,
+            "Error: Tool 'delete_everything' not found. Available tools: echo."
+        );
+    }
+
+    #[test]
+    fn retries_after_malformed_tool_call() {
+        let broken = "<tool_call>\n{name: echo, arguments: {text: \n</tool_call>";
+        let (mut agent, calls, prompts) = echo_agent([
+            broken.to_string(),
+            tool_call("echo", json!({"text": "fixed"})),
+            "Done.".to_string(),
+        ]);
+
+        let result = agent.chat_with_steps("Echo something").unwrap();
+        assert_eq!(result.answer, "Done.");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(result.steps.len(), 1);
+        assert_eq!(result.steps[0].output, "echo: fixed");
+
+        // 깨진 원본과 재출력 요청이 히스토리에 남음
+        assert_eq!(agent.history[2].role, Role::Assistant);
+        assert_eq!(agent.history[2].content_as_string(), broken);
+        assert_eq!(agent.history[3].role, Role::User);
+        assert_eq!(
+            agent.history[3].content_as_string(),
+            MALFORMED_TOOL_CALL_FEEDBACK
+        );
+        let prompts = prompts.lock().unwrap();
+        assert!(prompts[1].contains(MALFORMED_TOOL_CALL_FEEDBACK));
+    }
+
+    #[test]
+    fn gives_up_after_max_parse_retries() {
+        let broken = "<tool_call>\n{name: echo\n</tool_call>";
+        let (mut agent, calls, _) = echo_agent(vec![broken; DEFAULT_MAX_PARSE_RETRIES + 1]);
+        match agent.chat("Echo something") {
+            Err(SuprascalarError::MalformedToolCall { retries }) => {
+                assert_eq!(retries, DEFAULT_MAX_PARSE_RETRIES)
+            }
+            other => panic!("expected MalformedToolCall, got {:?}", other),
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn tool_section_appears_once_in_every_prompt() {
+        let call = tool_call("echo", json!({"text": "hi"}));
+        let (mut agent, _, prompts) = echo_agent([
+            call.clone(),
+            call.clone(),
+            "First done.".into(),
+            call,
+            "Second done.".into(),
+        ]);
+        agent.chat("first").unwrap();
+        agent.chat("second").unwrap();
+
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 5);
+        for prompt in prompts.iter() {
+            assert_eq!(prompt.matches("<tools>\n").count(), 1, "{}", prompt);
+            assert_eq!(prompt.matches("Echoes the text back").count(), 1);
+        }
+        // 히스토리의 시스템 메시지에는 기본 프롬프트만 저장됨
+        assert_eq!(agent.history[0].content_as_string(), "You are a test.");
+    }
+
+    #[test]
+    fn system_prompt_does_not_depend_on_tool_registration_order() {
+        let tool = |name: &str| {
+            FnTool::new(
+                name,
+                format!("The {} tool", name),
+                json!({
+                    "type": "object",
+                    "properties": {"z": {"type": "string"}, "a": {"type": "integer"}}
+                }),
+                |_| Ok(String::new()),
+            )
+        };
+        let build = |names: &[&str]| {
+            let mut builder = Agent::builder(
+                "test",
+                Box::new(MockBackend::new(Vec::<String>::new())),
+                "You are a test.",
+            )
+            .with_prompt_format(QwenFnCallFormat::new(false));
+            for name in names {
+                builder = builder.with_tool(tool(name));
+            }
+            builder.build().unwrap()
+        };
+
+        let first = build(&["read", "write", "search"]);
+        let second = build(&["search", "read", "write"]);
+        let mut third = build(&[]);
+        for name in ["write", "search", "read"] {
+            third.register_tool(tool(name));
+        }
+
+        let expected = first.current_system_prompt().unwrap();
+        assert!(expected.contains("The search tool"));
+        for other in [&second, &third] {
+            assert_eq!(other.current_system_prompt().unwrap(), expected);
+            assert_eq!(
+                other.render_prompt().unwrap(),
+                first.render_prompt().unwrap()
+            );
+        }
+    }
+
+    /// 도구 호출만 담은 어시스턴트 메시지
+    fn call_message(name: &str, args: Value) -> Message {
+        Message {
+            role: Role::Assistant,
+            content: Vec::new(),
+            reasoning_content: None,
+            function_call: Some(FunctionCall {
+                name: name.to_string(),
+                arguments: args.to_string(),
+            }),
+            extra: None,
+        }
+    }
+
+    #[test]
+    fn merged_assistant_messages_are_newline_separated() {
+        let agent = Agent::builder(
+            "test",
+            Box::new(MockBackend::new(Vec::<String>::new())),
+            "You are a test.",
+        )
+        .with_prompt_format(QwenFnCallFormat::new(false))
+        .with_history(vec![
+            Message::user_text("List and read"),
+            Message::assistant_text("First part."),
+            Message::assistant_text("I will list files."),
+            call_message("ls", json!({"path": "."})),
+            call_message("cat", json!({"path": "a.txt"})),
+            Message::function_text("a.txt"),
+            Message::function_text("hello"),
+            Message::assistant_text("Done."),
+        ])
+        .build()
+        .unwrap();
+
+        let prompt = agent.render_prompt().unwrap();
+        assert!(
+            prompt.contains(
+                "First part.\nI will list files.\n\
+                 <tool_call>\n{\"arguments\":{\"path\":\".\"},\"name\":\"ls\"}\n</tool_call>\n\
+                 <tool_call>\n{\"arguments\":{\"path\":\"a.txt\"},\"name\":\"cat\"}\n</tool_call>"
+            ),
+            "{}",
+            prompt
+        );
+        assert!(
+            prompt.contains(
+                "<tool_response>\na.txt\n</tool_response>\n<tool_response>\nhello\n</tool_response>"
+            ),
+            "{}",
+            prompt
+        );
+    }
+
+    #[test]
+    fn exported_history_round_trips_to_identical_prompt() {
+        let (mut agent, _, _) = echo_agent([
+            format!(
+                "<think>Need the tool.</think>\nCalling it.\n{}",
+                tool_call("echo", json!({"text": "round trip"}))
+            ),
+            "It echoed.".to_string(),
+        ]);
+        agent.chat("Echo please").unwrap();
+        assert!(agent.history.iter().any(|m| m.extra.is_some()));
+        let exported = agent.export_history().unwrap();
+
+        let (mut restored, _, _) = echo_agent(Vec::<String>::new());
+        restored.import_history(&exported).unwrap();
+        assert_eq!(restored.history, agent.history);
+        assert_eq!(
+            restored.render_prompt().unwrap(),
+            agent.render_prompt().unwrap()
+        );
+        assert_eq!(restored.export_history().unwrap(), exported);
+    }
+
+    /// 이전 대화 한 번, 현재 요청, 그 뒤의 깨진 호출과 재출력 요청으로 이루어진 히스토리
+    /// (마지막 사용자 메시지가 현재 요청이 아닌 상태)
+    fn push_overflowing_history(agent: &mut Agent) {
+        agent.history.extend([
+            Message::user_text("Earlier question"),
+            Message::assistant_text("x".repeat(400)),
+        ]);
+        agent.request_start = agent.history.len();
+        agent.history.extend([
+            Message::user_text("Current task"),
+            Message::assistant_text("<tool_call>\n{broken"),
+            Message::user_text(MALFORMED_TOOL_CALL_FEEDBACK),
+        ]);
+    }
+
+    #[test]
+    fn trim_oldest_never_drops_current_request() {
+        let (mut agent, _, _) = echo_agent(Vec::<String>::new());
+        agent.set_overflow_policy(OverflowPolicy::TrimOldest);
+        push_overflowing_history(&mut agent);
+        let full = agent.build_prompt().unwrap().len();
+        let compacted = Arc::new(Mutex::new(Vec::new()));
+        let log = compacted.clone();
+        agent.set_on_event(move |event| {
+            if let AgentEvent::ContextCompacted { removed } = event {
+                log.lock().unwrap().push(removed);
+            }
+        });
+        agent.model = Box::new(MockBackend::new(Vec::<String>::new()).with_context_limit(full - 1));
+
+        let prompt = agent.fit_context().unwrap();
+        assert!(!prompt.contains("Earlier question"));
+        assert!(prompt.contains("Current task"));
+        assert_eq!(*compacted.lock().unwrap(), vec![2]);
+        assert_eq!(
+            agent.history[agent.request_start].content_as_string(),
+            "Current task"
+        );
+
+        // 이전 대화를 모두 지워도 넘치면 요청은 남기고 에러
+        agent.model = Box::new(MockBackend::new(Vec::<String>::new()).with_context_limit(10));
+        assert!(matches!(
+            agent.fit_context(),
+            Err(SuprascalarError::ContextLimitExceeded { .. })
+        ));
+        assert_eq!(
+            agent.history[agent.request_start].content_as_string(),
+            "Current task"
+        );
+    }
+
+    #[test]
+    fn summarize_leaves_current_request_out_of_summary() {
+        let (mut agent, _, _) = echo_agent(Vec::<String>::new());
+        agent.set_overflow_policy(OverflowPolicy::Summarize);
+        push_overflowing_history(&mut agent);
+        let full = agent.build_prompt().unwrap().len();
+        let backend =
+            MockBackend::new(["They asked something earlier."]).with_context_limit(full - 1);
+        let prompts = backend.prompt_log();
+        agent.model = Box::new(backend);
+
+        let prompt = agent.fit_context().unwrap();
+        let summarizer_prompt = prompts.lock().unwrap()[0].clone();
+        assert!(summarizer_prompt.contains("Earlier question"));
+        assert!(!summarizer_prompt.contains("Current task"));
+        assert!(prompt.contains("They asked something earlier."));
+        assert!(prompt.contains("Current task"));
+        assert_eq!(
+            agent.history[agent.request_start].content_as_string(),
+            "Current task"
+        );
+    }
+
+    #[test]
+    fn zero_max_duration_times_out_before_first_turn() {
+        let (mut agent, calls, prompts) = echo_agent(["Never generated."]);
+        agent.set_max_duration(Some(Duration::ZERO));
+
+        match agent.chat("Say hi") {
+            Err(SuprascalarError::Timeout { limit, partial }) => {
+                assert_eq!(limit, Duration::ZERO);
+                assert_eq!(partial, "");
+            }
+            other => panic!("expected Timeout, got {:?}", other),
+        }
+        assert!(prompts.lock().unwrap().is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn timeout_after_tool_call_returns_partial_answer() {
+        let backend = MockBackend::new([format!(
+            "Waiting on the tool.\n{}",
+            tool_call("slow", json!({}))
+        )]);
+        let slow = FnTool::new("slow", "Takes a while", json!({"type": "object"}), |_| {
+            std::thread::sleep(Duration::from_millis(50));
+            Ok("done".to_string())
+        });
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(slow)
+            .with_max_duration(Duration::from_millis(10))
+            .build()
+            .unwrap();
+
+        match agent.chat("Go") {
+            Err(SuprascalarError::Timeout { partial, .. }) => {
+                assert_eq!(partial, "Waiting on the tool.");
+            }
+            other => panic!("expected Timeout, got {:?}", other),
+        }
+    }
+
+    /// `path` 인자를 읽은 척하는 도구와 실행 횟수
+    fn read_tool(cacheable: bool) -> (FnTool, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let tool = FnTool::new(
+            "read",
+            "Reads a file",
+            json!({"type": "object"}),
+            move |args| {
+                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(format!("{} (read #{})", args["path"], n))
+            },
+        )
+        .with_cacheable(cacheable);
+        (tool, calls)
+    }
+
+    #[test]
+    fn cacheable_tool_runs_once_until_a_mutating_tool_succeeds() {
+        let backend = MockBackend::new([
+            [
+                tool_call("read", json!({"path": "a"})),
+                tool_call("read", json!({"path": "a"})),
+                tool_call("echo", json!({"text": "write"})),
+                tool_call("read", json!({"path": "a"})),
+            ]
+            .join("\n"),
+            "Done.".to_string(),
+        ]);
+        let (read, reads) = read_tool(true);
+        let (echo, echoes) = echo_tool();
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(read)
+            .with_tool(echo)
+            .with_tool_cache(Duration::from_secs(60))
+            .build()
+            .unwrap();
+
+        let result = agent.chat_with_steps("Read a twice").unwrap();
+        let outputs: Vec<&str> = result.steps.iter().map(|s| s.output.as_str()).collect();
+        // 두 번째 read는 캐시, echo(캐시 불가) 성공 뒤의 read는 다시 실행
+        assert_eq!(
+            outputs,
+            [
+                "\"a\" (read #1)",
+                "\"a\" (read #1)",
+                "echo: write",
+                "\"a\" (read #2)"
+            ]
+        );
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+        assert_eq!(echoes.load(Ordering::SeqCst), 1);
+    }
+
+    /// `text`가 비어 있으면 실패하는 종료 도구
+    fn submit_tool() -> FnTool {
+        FnTool::new(
+            "submit",
+            "Submits the final text",
+            json!({
+                "type": "object",
+                "properties": {"text": {"type": "string"}},
+                "required": ["text"]
+            }),
+            |args| match args["text"].as_str().unwrap_or_default() {
+                "" => Err(SuprascalarError::InvalidToolInput("text is empty".into())),
+                text => Ok(format!("Submitted: {}", text)),
+            },
+        )
+        .with_terminal(true)
+    }
+
+    #[test]
+    fn terminal_tool_output_is_the_final_answer() {
+        let backend = MockBackend::new([[
+            tool_call("submit", json!({"text": "report"})),
+            tool_call("echo", json!({"text": "too late"})),
+        ]
+        .join("\n")]);
+        let prompts = backend.prompt_log();
+        let (echo, echoes) = echo_tool();
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(submit_tool())
+            .with_tool(echo)
+            .build()
+            .unwrap();
+
+        let result = agent.chat_with_steps("Submit the report").unwrap();
+        assert_eq!(result.answer, "Submitted: report");
+        assert!(!result.truncated);
+        assert_eq!(result.steps.len(), 1);
+        // 같은 응답의 남은 호출은 실행하지 않고, 추가 생성도 없음
+        assert_eq!(echoes.load(Ordering::SeqCst), 0);
+        assert_eq!(prompts.lock().unwrap().len(), 1);
+        assert_eq!(
+            agent.history.last().unwrap().content_as_string(),
+            "Submitted: report"
+        );
+    }
+
+    #[test]
+    fn failed_terminal_tool_keeps_the_loop_running() {
+        let backend = MockBackend::new([
+            tool_call("submit", json!({"text": ""})),
+            tool_call("submit", json!({"text": "fixed"})),
+        ]);
+        let prompts = backend.prompt_log();
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(submit_tool())
+            .build()
+            .unwrap();
+
+        let result = agent.chat_with_steps("Submit something").unwrap();
+        assert_eq!(result.answer, "Submitted: fixed");
+        assert_eq!(result.steps.len(), 2);
+        assert!(result.steps[0].output.starts_with("Error: "));
+        // 실패 메시지를 보고 다시 생성
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[1].contains("text is empty"));
+    }
+
+    #[test]
+    fn levenshtein_counts_character_edits() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("echo", "echo"), 0);
+        assert_eq!(levenshtein("", "echo"), 4);
+        assert_eq!(levenshtein("list_file", "list_files"), 1);
+        assert_eq!(levenshtein("ehco", "echo"), 2);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        // 바이트가 아니라 문자 단위
+        assert_eq!(levenshtein("파일", "파일들"), 1);
+    }
+
+    #[test]
+    fn misspelled_tool_gets_a_suggestion_without_running() {
+        let (mut agent, calls, _) = echo_agent([
+            tool_call("ecoh", json!({"text": "hi"})),
+            "Sorry.".to_string(),
+        ]);
+
+        let result = agent.chat_with_steps("Say hi").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            result.steps[0].output,
+            "Error: Tool 'ecoh' not found. Did you mean 'echo'?"
+        );
+    }
+
+    #[test]
+    fn autocorrect_runs_the_unique_nearest_tool() {
+        let backend = MockBackend::new([
+            [
+                tool_call("Echo", json!({"text": "one"})),
+                tool_call("echo_", json!({"text": "two"})),
+            ]
+            .join("\n"),
+            "Done.".to_string(),
+        ]);
+        let (echo, calls) = echo_tool();
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(echo)
+            .with_autocorrect_tool_names()
+            .build()
+            .unwrap();
+
+        let result = agent.chat_with_steps("Echo twice").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let names: Vec<&str> = result.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["echo", "echo"]);
+        assert_eq!(result.steps[1].output, "echo: two");
+    }
+
+    #[test]
+    fn autocorrect_skips_ambiguous_names() {
+        let backend = MockBackend::new([
+            tool_call("echo1", json!({"text": "hi"})),
+            "Done.".to_string(),
+        ]);
+        let (echo, calls) = echo_tool();
+        let echo2 = FnTool::new("echo2", "Another echo", json!({}), |_| Ok("echo2".into()));
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(echo)
+            .with_tool(echo2)
+            .with_autocorrect_tool_names()
+            .build()
+            .unwrap();
+
+        // echo와 echo2가 모두 거리 1이라 고르지 않고 둘 다 제안
+        let result = agent.chat_with_steps("Echo").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(result.steps[0].name, "echo1");
+        assert!(
+            result.steps[0]
+                .output
+                .starts_with("Error: Tool 'echo1' not found.")
+        );
+        assert!(result.steps[0].output.contains("'echo'"));
+        assert!(result.steps[0].output.contains("'echo2'"));
+    }
+
+    #[test]
+    fn on_tool_result_rewrites_output_everywhere() {
+        let backend = MockBackend::new([
+            tool_call("echo", json!({"text": "token=hunter2"})),
+            "Done.".to_string(),
+        ]);
+        let prompts = backend.prompt_log();
+        let (echo, _) = echo_tool();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (seen_in_hook, events_in_hook) = (seen.clone(), events.clone());
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(echo)
+            .with_on_tool_result(move |name, output| {
+                seen_in_hook.lock().unwrap().push(name.to_string());
+                output.replace("hunter2", "[REDACTED]")
+            })
+            .with_on_event(move |event| {
+                if let AgentEvent::ToolResult { output, .. } = event {
+                    events_in_hook.lock().unwrap().push(output);
+                }
+            })
+            .build()
+            .unwrap();
+
+        let result = agent.chat_with_steps("Echo the token").unwrap();
+        let redacted = "echo: token=[REDACTED]";
+        assert_eq!(*seen.lock().unwrap(), ["echo"]);
+        assert_eq!(result.steps[0].output, redacted);
+        assert_eq!(*events.lock().unwrap(), [redacted]);
+        assert!(
+            agent
+                .history
+                .iter()
+                .any(|m| m.role == Role::Function && m.content_as_string() == redacted)
+        );
+        // 관찰 결과에는 원래 값이 남지 않음 (호출 인자는 모델 자신의 출력)
+        let prompts = prompts.lock().unwrap();
+        assert!(prompts[1].contains(&format!("<tool_response>\n{}\n</tool_response>", redacted)));
+        assert!(!prompts[1].contains("echo: token=hunter2"));
+    }
+
+    #[test]
+    fn final_answer_text_accepts_common_argument_shapes() {
+        assert_eq!(final_answer_text(r#"{"answer": "42"}"#), "42");
+        assert_eq!(final_answer_text(r#"{"content": "42"}"#), "42");
+        assert_eq!(final_answer_text(r#""42""#), "42");
+        assert_eq!(final_answer_text("42 is the answer"), "42 is the answer");
+        assert_eq!(final_answer_text(r#"{"value": 42}"#), r#"{"value":42}"#);
+    }
+
+    #[test]
+    fn final_answer_call_ends_the_loop() {
+        let backend = MockBackend::new([[
+            tool_call("echo", json!({"text": "skipped"})),
+            tool_call(FINAL_ANSWER_TOOL, json!({"answer": "All done."})),
+        ]
+        .join("\n")]);
+        let prompts = backend.prompt_log();
+        let (echo, calls) = echo_tool();
+        let mut agent = Agent::builder("test", Box::new(backend), "You are a test.")
+            .with_prompt_format(QwenFnCallFormat::new(false))
+            .with_tool(echo)
+            .with_final_answer_tool()
+            .build()
+            .unwrap();
+
+        let result = agent.chat_with_steps("Finish up").unwrap();
+        assert_eq!(result.answer, "All done.");
+        assert!(result.steps.is_empty());
+        // 같은 응답의 다른 호출은 실행하지 않음
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            agent.history.last().unwrap().content_as_string(),
+            "All done."
+        );
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].contains(FINAL_ANSWER_TOOL));
+    }
+
+    #[test]
+    fn planning_writes_a_plan_before_acting() {
+        let (mut agent, calls, prompts) = echo_agent([
+            "<think>Simple.</think>\n1. Echo hi\n2. Report".to_string(),
+            tool_call("echo", json!({"text": "hi"})),
+            "The tool said hi.".to_string(),
+            "No plan needed.".to_string(),
+        ]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_in_hook = events.clone();
+        agent
+            .set_planning(true)
+            .set_on_event(move |event| events_in_hook.lock().unwrap().push(event));
+
+        let result = agent.chat_with_steps("Say hi").unwrap();
+        let plan = "1. Echo hi\n2. Report";
+        assert_eq!(result.answer, "The tool said hi.");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(agent.last_plan(), Some(plan));
+        assert_eq!(
+            events.lock().unwrap()[0],
+            AgentEvent::Plan(plan.to_string())
+        );
+
+        // 계획 요청은 계획 생성에만 쓰이고, 히스토리에는 원래 요청 뒤에 계획만 남음
+        assert_eq!(agent.history[1].content_as_string(), "Say hi");
+        assert_eq!(agent.history[2].role, Role::Assistant);
+        assert_eq!(
+            agent.history[2].content_as_string(),
+            format!("{}\n\n{}", plan, PLAN_HANDOFF)
+        );
+        {
+            let prompts = prompts.lock().unwrap();
+            assert_eq!(prompts.len(), 3);
+            assert!(prompts[0].contains(PLANNING_PROMPT));
+            assert!(!prompts[1].contains(PLANNING_PROMPT));
+            assert!(prompts[1].contains(PLAN_HANDOFF));
+        }
+
+        // 계획 모드를 끄면 다음 요청에는 계획이 없음
+        agent.set_planning(false);
+        assert_eq!(agent.chat("Anything else?").unwrap(), "No plan needed.");
+        assert_eq!(agent.last_plan(), None);
+        assert_eq!(prompts.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,112 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// 캐시 가능한 도구(`Tool::is_cacheable`)의 결과를 (도구 이름, 정규화한 인자) 기준으로 보관하는 대화 단위 캐시
+/// 항목은 `ttl`이 지나면 무효가 되고, 캐시 불가능한 도구가 성공하면 (파일 쓰기, 셸 명령 등으로
+/// 상태가 바뀌었을 수 있으므로) 전부 비웁니다.
+pub(crate) struct ToolCache {
+    ttl: Duration,
+    // 키 → (저장 시각, 결과)
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl ToolCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 캐시 내용은 lock이 poison되어도 유효하므로 그대로 사용
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, (Instant, String)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 도구 이름과 인자로 만든 키. 객체 키 순서가 달라도 같은 호출이면 같은 키가 됩니다.
+    pub(crate) fn key(name: &str, args: &Value) -> String {
+        format!("{}\0{}", name, canonical_json(args))
+    }
+
+    /// TTL 안에 저장된 결과가 있으면 반환합니다 (만료된 항목은 지움).
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.lock();
+        match entries.get(key) {
+            Some((stored, output)) if stored.elapsed() < self.ttl => Some(output.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: String, output: String) {
+        self.lock().insert(key, (Instant::now(), output));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().clear();
+    }
+}
+
+/// 객체 키를 정렬해 직렬화한 JSON (serde_json의 `preserve_order` 여부와 무관하게 같은 결과)
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let fields = keys
+                .into_iter()
+                .map(|k| format!("{}:{}", Value::String(k.clone()), canonical_json(&map[k])))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items = items.iter().map(canonical_json).collect::<Vec<_>>();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn key_ignores_object_key_order() {
+        assert_eq!(
+            ToolCache::key(
+                "read",
+                &json!({"path": "a", "range": {"end": 2, "start": 1}})
+            ),
+            ToolCache::key(
+                "read",
+                &json!({"range": {"start": 1, "end": 2}, "path": "a"})
+            )
+        );
+        assert_ne!(
+            ToolCache::key("read", &json!({"path": "a"})),
+            ToolCache::key("outline", &json!({"path": "a"}))
+        );
+    }
+
+    #[test]
+    fn serves_entries_until_ttl_or_clear() {
+        let cache = ToolCache::new(Duration::from_secs(60));
+        cache.insert("k".into(), "v".into());
+        assert_eq!(cache.get("k").as_deref(), Some("v"));
+        cache.clear();
+        assert_eq!(cache.get("k"), None);
+
+        // TTL이 지난 항목은 돌려주지 않고 지움
+        let expired = ToolCache::new(Duration::ZERO);
+        expired.insert("k".into(), "v".into());
+        assert_eq!(expired.get("k"), None);
+        assert!(expired.lock().is_empty());
+    }
+}
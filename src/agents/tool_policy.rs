@@ -0,0 +1,97 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// 도구별 실행 제한 (외부 API를 호출하는 도구가 upstream rate limit에 걸리지 않도록)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ToolPolicy {
+    /// 동시에 실행될 수 있는 최대 호출 수 (None이면 제한 없음)
+    pub max_concurrency: Option<usize>,
+    /// 연속된 호출의 시작 사이 최소 간격 (None이면 제한 없음)
+    pub min_interval: Option<Duration>,
+}
+
+impl ToolPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_concurrency(mut self, max: usize) -> Self {
+        self.max_concurrency = Some(max.max(1));
+        self
+    }
+
+    pub fn with_min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = Some(interval);
+        self
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    in_flight: usize,
+    // 다음 호출이 시작할 수 있는 가장 이른 시각
+    next_start: Option<Instant>,
+}
+
+/// `ToolPolicy`를 실제로 적용하는 상태. `&self`로 동작하므로 병렬 디스패치에서도 공유할 수 있습니다.
+pub(crate) struct ToolLimiter {
+    policy: ToolPolicy,
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+/// 실행 슬롯. drop되면 슬롯을 반납합니다.
+pub(crate) struct ToolPermit<'a> {
+    limiter: &'a ToolLimiter,
+}
+
+impl ToolLimiter {
+    pub(crate) fn new(policy: ToolPolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(LimiterState::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    // 도구 실행 중 panic으로 lock이 poison되어도 카운터 자체는 유효하므로 그대로 사용
+    fn lock(&self) -> MutexGuard<'_, LimiterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 동시 실행 슬롯과 최소 간격을 모두 만족할 때까지 기다린 뒤 슬롯을 돌려줍니다.
+    pub(crate) fn acquire(&self) -> ToolPermit<'_> {
+        let mut state = self.lock();
+        if let Some(max) = self.policy.max_concurrency {
+            while state.in_flight >= max {
+                state = self.released.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        state.in_flight += 1;
+
+        // 시작 시각을 먼저 예약해 두고 lock 밖에서 기다림 (동시에 기다리는 호출끼리도 간격 유지)
+        let wait = self.policy.min_interval.map(|interval| {
+            let now = Instant::now();
+            let start = state.next_start.map_or(now, |next| next.max(now));
+            state.next_start = Some(start + interval);
+            start - now
+        });
+        drop(state);
+
+        if let Some(wait) = wait
+            && !wait.is_zero()
+        {
+            std::thread::sleep(wait);
+        }
+        ToolPermit { limiter: self }
+    }
+}
+
+impl Drop for ToolPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.lock();
+        state.in_flight -= 1;
+        drop(state);
+        self.limiter.released.notify_one();
+    }
+}
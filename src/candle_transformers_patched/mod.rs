@@ -1 +1,2 @@
 pub mod quantized_qwen3;
+pub mod quantized_qwen3_moe;
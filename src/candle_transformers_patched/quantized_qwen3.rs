@@ -14,32 +14,32 @@ use candle_transformers::{quantized_nn::RmsNorm, utils::repeat_kv};
 use std::io::{Read, Seek};
 use std::sync::Arc;
 
-struct Gguf<R: Read + Seek> {
+pub(crate) struct Gguf<R: Read + Seek> {
     ct: gguf_file::Content,
     reader: R,
     device: Device,
 }
 
 impl<R: Read + Seek> Gguf<R> {
-    fn new(ct: gguf_file::Content, reader: R, device: Device) -> Self {
+    pub(crate) fn new(ct: gguf_file::Content, reader: R, device: Device) -> Self {
         Self { ct, reader, device }
     }
 
-    fn qmatmul(&mut self, name: &str) -> Result<QMatMul> {
+    pub(crate) fn qmatmul(&mut self, name: &str) -> Result<QMatMul> {
         let ws = self.ct.tensor(&mut self.reader, name, &self.device)?;
         QMatMul::from_weights(ws.into())
     }
 
-    fn rms_norm(&mut self, name: &str, eps: f64) -> Result<RmsNorm> {
+    pub(crate) fn rms_norm(&mut self, name: &str, eps: f64) -> Result<RmsNorm> {
         let ws = self.ct.tensor(&mut self.reader, name, &self.device)?;
         RmsNorm::from_qtensor(ws, eps)
     }
 
-    fn metadata(&self) -> &std::collections::HashMap<String, gguf_file::Value> {
+    pub(crate) fn metadata(&self) -> &std::collections::HashMap<String, gguf_file::Value> {
         &self.ct.metadata
     }
 
-    fn tensor(&mut self, name: &str) -> Result<QTensor> {
+    pub(crate) fn tensor(&mut self, name: &str) -> Result<QTensor> {
         self.ct.tensor(&mut self.reader, name, &self.device)
     }
 }
@@ -81,13 +81,13 @@ impl Module for MlpWeights {
 }
 
 #[derive(Debug, Clone)]
-struct RotaryEmbedding {
+pub(crate) struct RotaryEmbedding {
     sin: Tensor,
     cos: Tensor,
 }
 
 impl RotaryEmbedding {
-    fn new(
+    pub(crate) fn new(
         dtype: DType,
         head_dim: usize,
         max_position_embeddings: usize,
@@ -113,7 +113,7 @@ impl RotaryEmbedding {
     }
 
     /// Apply RoPE (q, k shape: B x H x L x D)
-    fn apply(&self, q: &Tensor, k: &Tensor, offset: usize) -> Result<(Tensor, Tensor)> {
+    pub(crate) fn apply(&self, q: &Tensor, k: &Tensor, offset: usize) -> Result<(Tensor, Tensor)> {
         let (_, _, seq_len, _) = q.dims4()?;
         let cos = self.cos.narrow(0, offset, seq_len)?.to_dtype(q.dtype())?;
         let sin = self.sin.narrow(0, offset, seq_len)?.to_dtype(q.dtype())?;
@@ -239,6 +239,23 @@ impl AttentionWeights {
     }
 }
 
+/// `ConcatKvCache`를 앞의 `len`개 위치만 남기고 잘라냅니다.
+/// 캐시는 덧붙이기만 하므로, 같은 offset으로 다시 forward 하기 전에 반드시 잘라야 합니다.
+pub(crate) fn truncate_kv_cache(cache: &mut ConcatKvCache, len: usize) -> Result<()> {
+    if len == 0 {
+        cache.reset();
+        return Ok(());
+    }
+    let dim = cache.dim();
+    if let Some(k) = cache.k_mut() {
+        *k = k.narrow(dim, 0, len)?;
+    }
+    if let Some(v) = cache.v_mut() {
+        *v = v.narrow(dim, 0, len)?;
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 struct LayerWeights {
     self_attn: AttentionWeights,
@@ -291,6 +308,14 @@ impl LayerWeights {
     fn clear_kv_cache(&mut self) {
         self.self_attn.clear_kv_cache();
     }
+
+    fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        truncate_kv_cache(&mut self.self_attn.kv_cache, len)
+    }
+
+    fn kv_cache_len(&self) -> usize {
+        self.self_attn.kv_cache.current_seq_len()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -421,9 +446,67 @@ impl ModelWeights {
         self.lm_head.forward(&last_hidden)?.squeeze(1)
     }
 
+    /// 좌측 패딩된 배치용 forward.
+    /// `pad_lens[i]`는 i번째 행 앞쪽 패딩 길이이며, 패딩 위치는 KV 캐시를 포함해
+    /// 모든 스텝에서 attention 대상에서 제외됩니다. (RoPE는 상대 위치만 반영하므로
+    /// 행마다 시작 위치가 밀려도 결과는 동일합니다.)
+    pub fn forward_padded(
+        &mut self,
+        input: &Tensor,
+        offset: usize,
+        pad_lens: &[usize],
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let (b, l) = input.dims2()?;
+        if pad_lens.len() != b {
+            candle_core::bail!("pad_lens has {} entries for batch {b}", pad_lens.len());
+        }
+        let mut h = self.embed_tokens.forward(input)?;
+        let mask = self.padded_causal_mask(l, offset, pad_lens)?;
+        for layer in &mut self.layers {
+            h = layer.forward(&h, Some(&mask), offset)?;
+        }
+        let h = self.norm.forward(&h)?;
+        let _enter = self.span_output.enter();
+        let last_hidden = h.narrow(1, l - 1, 1)?;
+        self.lm_head.forward(&last_hidden)?.squeeze(1)
+    }
+
+    fn padded_causal_mask(&self, tgt: usize, offset: usize, pad_lens: &[usize]) -> Result<Tensor> {
+        let minf = f32::NEG_INFINITY;
+        let mask: Vec<_> = pad_lens
+            .iter()
+            .flat_map(|&pad| {
+                (0..tgt).flat_map(move |i| {
+                    (0..(tgt + offset)).map(move |j| {
+                        let past_ok = j <= i + offset && j >= pad;
+                        // 패딩 위치의 query는 자기 자신만 보게 하여 softmax NaN을 방지
+                        let self_ok = j == i + offset;
+                        if past_ok || self_ok { 0. } else { minf }
+                    })
+                })
+            })
+            .collect();
+        Tensor::from_slice(&mask, (pad_lens.len(), 1, tgt, tgt + offset), &self.device)?
+            .to_dtype(self.dtype)
+    }
+
     pub fn clear_kv_cache(&mut self) {
         for layer in &mut self.layers {
             layer.clear_kv_cache();
         }
     }
+
+    /// 모든 레이어의 KV 캐시를 앞의 `len`개 위치만 남기고 잘라냅니다 (speculative decoding의 되감기용).
+    pub fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        for layer in &mut self.layers {
+            layer.truncate_kv_cache(len)?;
+        }
+        Ok(())
+    }
+
+    /// KV 캐시에 들어 있는 위치 수
+    pub fn kv_cache_len(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.kv_cache_len())
+    }
 }
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use thiserror::Error;
 
 /// Suprascalar crate-specific Result type alias
@@ -21,6 +22,12 @@ pub enum SuprascalarError {
     #[error("Model not found: {name}")]
     ModelNotFound { name: String },
 
+    #[error(
+        "'{file}' from '{repo}' is not in the local HF cache (offline mode). \
+         Download it once with network access or point cache_dir at an existing cache."
+    )]
+    NotCached { repo: String, file: String },
+
     #[error("Unsupported architecture: {0}")]
     UnsupportedArchitecture(String),
 
@@ -30,6 +37,30 @@ pub enum SuprascalarError {
     #[error("Invalid tool input: {0}")]
     InvalidToolInput(String),
 
+    // 도구 실행 중 발생한 런타임 실패 (`source`라는 필드명은 thiserror가 Error 소스로 취급하므로 `message` 사용)
+    #[error("Tool '{tool}' failed: {message}")]
+    ToolExecution { tool: String, message: String },
+
+    // `partial`: 루프가 멈추기 전까지 모델이 남긴 가장 최근의 답변/관찰 결과
+    #[error("Max agent turns ({turns}) exceeded before a final answer")]
+    MaxTurnsExceeded { turns: usize, partial: String },
+
+    #[error("Model emitted an unparseable tool call (gave up after {retries} retries)")]
+    MalformedToolCall { retries: usize },
+
+    #[error("Command timed out after {seconds} seconds")]
+    CommandTimeout { seconds: u64 },
+
+    // `Agent::max_duration` 초과. `partial`은 `MaxTurnsExceeded`와 같은 부분 답변
+    #[error("Agent run exceeded its time limit of {limit:?}")]
+    Timeout { limit: Duration, partial: String },
+
+    #[error("Cannot undo: {0}")]
+    Undo(String),
+
+    #[error("Docker error: {0}")]
+    Docker(String),
+
     #[error("Terminal state error: {0}")]
     TerminalState(String),
 
@@ -43,6 +74,36 @@ pub enum SuprascalarError {
     #[error("Context length exceeded: limit {limit}, current {current}")]
     ContextLimitExceeded { limit: usize, current: usize },
 
+    #[error("Not supported by this backend: {0}")]
+    Unsupported(String),
+
+    #[error("Operation cancelled")]
+    Cancelled,
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // terminal.rs가 쓰는 변형들의 필드 구성과 메시지가 바뀌면 여기서 먼저 깨지도록
+    #[test]
+    fn terminal_error_variants_display() {
+        let blocked = SuprascalarError::CommandBlocked {
+            command: "rm -rf /".to_string(),
+            reason: "destructive".to_string(),
+        };
+        assert_eq!(
+            blocked.to_string(),
+            "Command blocked by safety policy: rm -rf /. Reason: destructive"
+        );
+
+        let state = SuprascalarError::TerminalState("lock poisoned".to_string());
+        assert_eq!(state.to_string(), "Terminal state error: lock poisoned");
+
+        let missing = SuprascalarError::MissingEnvVar("HOME".to_string());
+        assert_eq!(missing.to_string(), "Missing environment variable: HOME");
+    }
+}
@@ -3,9 +3,19 @@ pub mod candle_transformers_patched;
 pub mod error;
 pub mod models;
 pub mod tools; // 추가됨
+pub mod util;
 
-pub use agents::qwen_agent::{Agent, AgentBuilder};
+pub use agents::chat_template::ChatTemplate;
+pub use agents::event::AgentEvent;
+pub use agents::prompt_format::{JsonActionFormat, PromptFormat, QwenFnCallFormat};
+pub use agents::qwen_agent::{
+    Agent, AgentBuilder, ChatResult, ContextReport, Message, OverflowPolicy, Role, Step,
+};
+pub use agents::tool_policy::ToolPolicy;
 pub use error::{Result, SuprascalarError};
-pub use models::LLMBackend;
 pub use models::qqwen3::CandleQwen;
-pub use tools::Tool; // 추가됨
+pub use models::{
+    GenerationConfig, HubConfig, LLMBackend, MockBackend, ModelRegistry, ModelSpec, Usage,
+};
+pub use tools::{FnTool, Tool}; // 추가됨
+pub use util::CancellationToken;
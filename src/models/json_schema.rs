@@ -0,0 +1,711 @@
+use serde_json::Value;
+use std::sync::Arc;
+
+/// 구조 토큰 사이에 허용하는 연속 공백 문자 수
+/// 제한이 없으면 모델이 공백만 계속 생성하는 루프에 빠질 수 있습니다.
+const MAX_WHITESPACE_RUN: usize = 1;
+
+/// 제약 디코딩에서 지원하는 JSON Schema 부분집합
+///
+/// - `type`: object / array / string / number / integer / boolean / null
+/// - object: `properties`, `required` (properties가 있으면 나열된 키만 허용)
+/// - array: `items`
+/// - string: `enum` (문자열 값만)
+///
+/// 그 외 키워드(`anyOf`, `pattern`, `minimum` 등)나 `type`이 없는 스키마는 임의의 JSON 값으로 취급합니다.
+#[derive(Debug)]
+enum Schema {
+    Any,
+    Object {
+        properties: Vec<(String, Arc<Schema>)>,
+        required: Vec<String>,
+    },
+    Array(Arc<Schema>),
+    String(Option<Vec<String>>),
+    Number {
+        integer: bool,
+    },
+    Boolean,
+    Null,
+}
+
+impl Schema {
+    fn from_value(value: &Value) -> Self {
+        if let Some(options) = value.get("enum").and_then(Value::as_array) {
+            let strings: Option<Vec<String>> = options
+                .iter()
+                .map(|o| o.as_str().map(str::to_string))
+                .collect();
+            return match strings {
+                Some(strings) => Schema::String(Some(strings)),
+                None => Schema::Any,
+            };
+        }
+
+        let ty = value.get("type").and_then(Value::as_str);
+        match ty {
+            Some("object") => Self::object(value),
+            None if value.get("properties").is_some() => Self::object(value),
+            Some("array") => Schema::Array(Arc::new(
+                value.get("items").map_or(Schema::Any, Schema::from_value),
+            )),
+            Some("string") => Schema::String(None),
+            Some("number") => Schema::Number { integer: false },
+            Some("integer") => Schema::Number { integer: true },
+            Some("boolean") => Schema::Boolean,
+            Some("null") => Schema::Null,
+            _ => Schema::Any,
+        }
+    }
+
+    fn object(value: &Value) -> Self {
+        let properties = value
+            .get("properties")
+            .and_then(Value::as_object)
+            .map(|props| {
+                props
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Arc::new(Schema::from_value(v))))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let required = value
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|req| {
+                req.iter()
+                    .filter_map(|r| r.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Schema::Object {
+            properties,
+            required,
+        }
+    }
+
+    fn property(&self, key: &str) -> Arc<Schema> {
+        match self {
+            Schema::Object { properties, .. } => properties
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, s)| s.clone())
+                .unwrap_or_else(|| Arc::new(Schema::Any)),
+            _ => Arc::new(Schema::Any),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ObjectState {
+    // '{' 직후: 키 또는 '}'
+    KeyOrEnd,
+    // ',' 직후: 키만
+    Key,
+    Colon,
+    Value,
+    CommaOrEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArrayState {
+    ValueOrEnd,
+    Value,
+    CommaOrEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    Unicode(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NumState {
+    Start,
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Frac,
+    Exp,
+    ExpSign,
+    ExpInt,
+}
+
+impl NumState {
+    fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            NumState::Zero | NumState::Int | NumState::Frac | NumState::ExpInt
+        )
+    }
+}
+
+/// 파싱 스택의 한 단계
+#[derive(Clone, Debug)]
+enum Frame {
+    /// 값이 시작되기를 기다리는 중
+    Value(Arc<Schema>),
+    Object {
+        schema: Arc<Schema>,
+        state: ObjectState,
+        seen: Vec<String>,
+        pending: Option<Arc<Schema>>,
+    },
+    Array {
+        items: Arc<Schema>,
+        state: ArrayState,
+    },
+    /// `options`가 있으면 (enum 또는 닫힌 객체의 키) 후보 중 하나의 접두사만 허용
+    Str {
+        buf: String,
+        options: Option<Vec<String>>,
+        escape: Escape,
+    },
+    Number {
+        state: NumState,
+        integer: bool,
+    },
+    Literal {
+        text: &'static str,
+        pos: usize,
+    },
+}
+
+/// 한 문자를 처리한 결과
+enum Action {
+    Reject,
+    Consume,
+    Whitespace,
+    Push(Frame),
+    PushRefeed(Frame),
+    Replace(Frame),
+    ReplaceRefeed(Frame),
+    /// 현재 프레임 완료 (문자 소비)
+    Done,
+    /// 현재 프레임 완료 (문자는 부모가 다시 처리)
+    DoneRefeed,
+}
+
+fn is_ws(c: char) -> bool {
+    matches!(c, ' ' | '\n' | '\t' | '\r')
+}
+
+impl Frame {
+    fn step(&mut self, c: char) -> Action {
+        match self {
+            Frame::Value(schema) => Self::start_value(schema, c),
+            Frame::Object {
+                schema,
+                state,
+                seen,
+                pending,
+            } => match *state {
+                ObjectState::KeyOrEnd | ObjectState::Key => {
+                    if is_ws(c) {
+                        return Action::Whitespace;
+                    }
+                    if c == '}' && *state == ObjectState::KeyOrEnd {
+                        return Self::close_object(schema, seen);
+                    }
+                    if c != '"' {
+                        return Action::Reject;
+                    }
+                    let options = match schema.as_ref() {
+                        Schema::Object { properties, .. } if !properties.is_empty() => {
+                            let left: Vec<String> = properties
+                                .iter()
+                                .map(|(k, _)| k.clone())
+                                .filter(|k| !seen.contains(k))
+                                .collect();
+                            if left.is_empty() {
+                                return Action::Reject;
+                            }
+                            Some(left)
+                        }
+                        _ => None,
+                    };
+                    Action::Push(Frame::Str {
+                        buf: String::new(),
+                        options,
+                        escape: Escape::None,
+                    })
+                }
+                ObjectState::Colon => {
+                    if is_ws(c) {
+                        return Action::Whitespace;
+                    }
+                    if c != ':' {
+                        return Action::Reject;
+                    }
+                    *state = ObjectState::Value;
+                    let value_schema = pending.take().unwrap_or_else(|| Arc::new(Schema::Any));
+                    Action::Push(Frame::Value(value_schema))
+                }
+                // 값 프레임이 스택 위에 있으므로 여기로 오지 않음
+                ObjectState::Value => Action::Reject,
+                ObjectState::CommaOrEnd => {
+                    if is_ws(c) {
+                        return Action::Whitespace;
+                    }
+                    match c {
+                        '}' => Self::close_object(schema, seen),
+                        ',' => {
+                            let has_more = match schema.as_ref() {
+                                Schema::Object { properties, .. } if !properties.is_empty() => {
+                                    properties.iter().any(|(k, _)| !seen.contains(k))
+                                }
+                                _ => true,
+                            };
+                            if !has_more {
+                                return Action::Reject;
+                            }
+                            *state = ObjectState::Key;
+                            Action::Consume
+                        }
+                        _ => Action::Reject,
+                    }
+                }
+            },
+            Frame::Array { items, state } => match *state {
+                ArrayState::ValueOrEnd => {
+                    if is_ws(c) {
+                        return Action::Whitespace;
+                    }
+                    if c == ']' {
+                        return Action::Done;
+                    }
+                    *state = ArrayState::Value;
+                    Action::PushRefeed(Frame::Value(items.clone()))
+                }
+                ArrayState::Value => Action::Reject,
+                ArrayState::CommaOrEnd => {
+                    if is_ws(c) {
+                        return Action::Whitespace;
+                    }
+                    match c {
+                        ']' => Action::Done,
+                        ',' => {
+                            *state = ArrayState::Value;
+                            Action::Push(Frame::Value(items.clone()))
+                        }
+                        _ => Action::Reject,
+                    }
+                }
+            },
+            Frame::Str {
+                buf,
+                options,
+                escape,
+            } => match *escape {
+                Escape::None => match c {
+                    '"' => match options {
+                        Some(options) if !options.iter().any(|o| o == buf) => Action::Reject,
+                        _ => Action::Done,
+                    },
+                    // enum/키 비교를 단순하게 유지하기 위해 후보가 있을 때는 이스케이프 금지
+                    '\\' if options.is_some() => Action::Reject,
+                    '\\' => {
+                        *escape = Escape::Backslash;
+                        Action::Consume
+                    }
+                    c if (c as u32) < 0x20 => Action::Reject,
+                    c => {
+                        if let Some(options) = options {
+                            buf.push(c);
+                            if !options.iter().any(|o| o.starts_with(buf.as_str())) {
+                                return Action::Reject;
+                            }
+                        }
+                        Action::Consume
+                    }
+                },
+                Escape::Backslash => match c {
+                    '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't' => {
+                        *escape = Escape::None;
+                        Action::Consume
+                    }
+                    'u' => {
+                        *escape = Escape::Unicode(0);
+                        Action::Consume
+                    }
+                    _ => Action::Reject,
+                },
+                Escape::Unicode(n) => {
+                    if !c.is_ascii_hexdigit() {
+                        return Action::Reject;
+                    }
+                    *escape = if n == 3 {
+                        Escape::None
+                    } else {
+                        Escape::Unicode(n + 1)
+                    };
+                    Action::Consume
+                }
+            },
+            Frame::Number { state, integer } => {
+                let next = match (*state, c) {
+                    (NumState::Start, '-') => Some(NumState::Minus),
+                    (NumState::Start | NumState::Minus, '0') => Some(NumState::Zero),
+                    (NumState::Start | NumState::Minus, '1'..='9') => Some(NumState::Int),
+                    (NumState::Int, '0'..='9') => Some(NumState::Int),
+                    (NumState::Zero | NumState::Int, '.') if !*integer => Some(NumState::Dot),
+                    (NumState::Dot | NumState::Frac, '0'..='9') => Some(NumState::Frac),
+                    (NumState::Zero | NumState::Int | NumState::Frac, 'e' | 'E') if !*integer => {
+                        Some(NumState::Exp)
+                    }
+                    (NumState::Exp, '+' | '-') => Some(NumState::ExpSign),
+                    (NumState::Exp | NumState::ExpSign | NumState::ExpInt, '0'..='9') => {
+                        Some(NumState::ExpInt)
+                    }
+                    _ => None,
+                };
+                match next {
+                    Some(next) => {
+                        *state = next;
+                        Action::Consume
+                    }
+                    None if state.is_terminal() => Action::DoneRefeed,
+                    None => Action::Reject,
+                }
+            }
+            Frame::Literal { text, pos } => {
+                if text[*pos..].starts_with(c) {
+                    *pos += c.len_utf8();
+                    if *pos == text.len() {
+                        Action::Done
+                    } else {
+                        Action::Consume
+                    }
+                } else {
+                    Action::Reject
+                }
+            }
+        }
+    }
+
+    fn start_value(schema: &Arc<Schema>, c: char) -> Action {
+        if is_ws(c) {
+            return Action::Whitespace;
+        }
+        let any = matches!(schema.as_ref(), Schema::Any);
+        match c {
+            '{' if any || matches!(schema.as_ref(), Schema::Object { .. }) => {
+                Action::Replace(Frame::Object {
+                    schema: schema.clone(),
+                    state: ObjectState::KeyOrEnd,
+                    seen: Vec::new(),
+                    pending: None,
+                })
+            }
+            '[' if any || matches!(schema.as_ref(), Schema::Array(_)) => {
+                let items = match schema.as_ref() {
+                    Schema::Array(items) => items.clone(),
+                    _ => Arc::new(Schema::Any),
+                };
+                Action::Replace(Frame::Array {
+                    items,
+                    state: ArrayState::ValueOrEnd,
+                })
+            }
+            '"' if any || matches!(schema.as_ref(), Schema::String(_)) => {
+                let options = match schema.as_ref() {
+                    Schema::String(options) => options.clone(),
+                    _ => None,
+                };
+                Action::Replace(Frame::Str {
+                    buf: String::new(),
+                    options,
+                    escape: Escape::None,
+                })
+            }
+            '-' | '0'..='9' if any || matches!(schema.as_ref(), Schema::Number { .. }) => {
+                let integer = matches!(schema.as_ref(), Schema::Number { integer: true });
+                Action::ReplaceRefeed(Frame::Number {
+                    state: NumState::Start,
+                    integer,
+                })
+            }
+            't' | 'f' if any || matches!(schema.as_ref(), Schema::Boolean) => {
+                let text = if c == 't' { "true" } else { "false" };
+                Action::ReplaceRefeed(Frame::Literal { text, pos: 0 })
+            }
+            'n' if any || matches!(schema.as_ref(), Schema::Null) => {
+                Action::ReplaceRefeed(Frame::Literal {
+                    text: "null",
+                    pos: 0,
+                })
+            }
+            _ => Action::Reject,
+        }
+    }
+
+    fn close_object(schema: &Schema, seen: &[String]) -> Action {
+        if let Schema::Object { required, .. } = schema
+            && required.iter().any(|r| !seen.contains(r))
+        {
+            return Action::Reject;
+        }
+        Action::Done
+    }
+
+    /// 자식 프레임(키 또는 값)이 끝났을 때 부모 상태를 갱신합니다.
+    fn child_done(&mut self, child: Frame) {
+        match self {
+            Frame::Object {
+                schema,
+                state,
+                seen,
+                pending,
+            } => match *state {
+                ObjectState::KeyOrEnd | ObjectState::Key => {
+                    if let Frame::Str { buf, .. } = child {
+                        *pending = Some(schema.property(&buf));
+                        seen.push(buf);
+                    }
+                    *state = ObjectState::Colon;
+                }
+                _ => *state = ObjectState::CommaOrEnd,
+            },
+            Frame::Array { state, .. } => *state = ArrayState::CommaOrEnd,
+            _ => {}
+        }
+    }
+}
+
+/// JSON Schema를 만족하는 출력만 허용하는 문자 단위 매처
+///
+/// 생성 중인 텍스트를 한 글자씩 받아 "아직 스키마를 만족하는 JSON의 접두사인가"를 판단합니다.
+/// 토큰 후보를 시험할 때는 `accepts`로 복제본에 먹여 보고, 선택된 토큰만 `feed_str`로 반영합니다.
+#[derive(Clone, Debug)]
+pub(crate) struct JsonMatcher {
+    stack: Vec<Frame>,
+    ws_run: usize,
+}
+
+impl JsonMatcher {
+    pub(crate) fn new(schema: &Value) -> Self {
+        Self {
+            stack: vec![Frame::Value(Arc::new(Schema::from_value(schema)))],
+            ws_run: 0,
+        }
+    }
+
+    fn feed(&mut self, c: char) -> bool {
+        loop {
+            let Some(top) = self.stack.last_mut() else {
+                // 값이 이미 끝났으면 더 이상 아무것도 받지 않음
+                return false;
+            };
+            match top.step(c) {
+                Action::Reject => return false,
+                Action::Consume => {
+                    self.ws_run = 0;
+                    return true;
+                }
+                Action::Whitespace => {
+                    if self.ws_run >= MAX_WHITESPACE_RUN {
+                        return false;
+                    }
+                    self.ws_run += 1;
+                    return true;
+                }
+                Action::Push(frame) => {
+                    self.stack.push(frame);
+                    self.ws_run = 0;
+                    return true;
+                }
+                Action::PushRefeed(frame) => self.stack.push(frame),
+                Action::Replace(frame) => {
+                    *top = frame;
+                    self.ws_run = 0;
+                    return true;
+                }
+                Action::ReplaceRefeed(frame) => *top = frame,
+                Action::Done => {
+                    self.pop_done();
+                    self.ws_run = 0;
+                    return true;
+                }
+                Action::DoneRefeed => self.pop_done(),
+            }
+        }
+    }
+
+    fn pop_done(&mut self) {
+        if let Some(child) = self.stack.pop()
+            && let Some(parent) = self.stack.last_mut()
+        {
+            parent.child_done(child);
+        }
+    }
+
+    /// 문자열 전체를 반영합니다. 실패하면 false (상태는 일부만 반영될 수 있음).
+    pub(crate) fn feed_str(&mut self, text: &str) -> bool {
+        text.chars().all(|c| self.feed(c))
+    }
+
+    /// 상태를 바꾸지 않고 `text`를 이어 붙일 수 있는지 확인합니다.
+    pub(crate) fn accepts(&self, text: &str) -> bool {
+        !text.is_empty() && self.clone().feed_str(text)
+    }
+
+    /// 최상위 값이 완전히 끝나 더 받을 문자가 없는 상태
+    pub(crate) fn is_complete(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// 여기서 생성을 끝내도 유효한 JSON인지 (최상위 숫자는 끝을 알 수 없으므로 별도 처리)
+    pub(crate) fn can_finish(&self) -> bool {
+        match self.stack.as_slice() {
+            [] => true,
+            [Frame::Number { state, .. }] => state.is_terminal(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// `text` 전체가 스키마를 만족하는 완성된 JSON인지
+    fn matches(schema: &Value, text: &str) -> bool {
+        let mut matcher = JsonMatcher::new(schema);
+        matcher.feed_str(text) && matcher.can_finish()
+    }
+
+    /// `text`가 스키마를 만족하는 JSON의 접두사인지
+    fn prefix(schema: &Value, text: &str) -> bool {
+        JsonMatcher::new(schema).feed_str(text)
+    }
+
+    #[test]
+    fn string_enum_allows_only_listed_values() {
+        let schema = json!({"type": "string", "enum": ["red", "green"]});
+        assert!(matches(&schema, r#""red""#));
+        assert!(matches(&schema, r#""green""#));
+        assert!(prefix(&schema, r#""gr"#));
+        assert!(!prefix(&schema, r#""blue"#));
+        assert!(!prefix(&schema, r#""re""#));
+        // 후보가 있을 때는 이스케이프 금지
+        assert!(!prefix(&schema, r#""r\u0065d""#));
+    }
+
+    #[test]
+    fn object_requires_required_keys_and_rejects_unknown_ones() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"a": {"type": "integer"}, "b": {"type": "string"}},
+            "required": ["a"]
+        });
+        assert!(matches(&schema, r#"{"a":1}"#));
+        assert!(matches(&schema, r#"{"b":"x","a":2}"#));
+        assert!(!prefix(&schema, r#"{"b":"x"}"#));
+        assert!(!prefix(&schema, r#"{}"#));
+        assert!(!prefix(&schema, r#"{"c""#));
+        assert!(!prefix(&schema, r#"{"a":1,"a""#));
+        // 모든 키를 썼으면 ',' 금지
+        assert!(!prefix(&schema, r#"{"a":1,"b":"x","#));
+        assert!(!prefix(&schema, r#"{"a":"1"}"#));
+    }
+
+    #[test]
+    fn nested_objects_and_arrays() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "items": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {"n": {"type": "number"}, "tags": {"type": "array", "items": {"type": "string"}}},
+                        "required": ["n"]
+                    }
+                }
+            },
+            "required": ["items"]
+        });
+        assert!(matches(&schema, r#"{"items":[]}"#));
+        assert!(matches(
+            &schema,
+            r#"{"items":[{"n":1.5},{"n":-2e3,"tags":["a","b"]}]}"#
+        ));
+        assert!(!prefix(&schema, r#"{"items":[{"n":"x""#));
+        assert!(!prefix(&schema, r#"{"items":[{"tags":[]}"#));
+        assert!(!prefix(&schema, r#"{"items":[{"n":1,"tags":[1"#));
+        assert!(!prefix(&schema, r#"{"items":[{"n":1},]"#));
+    }
+
+    #[test]
+    fn numbers_follow_json_grammar() {
+        let number = json!({"type": "number"});
+        for ok in ["0", "-0.5", "12", "3.25e+10", "1E-2"] {
+            assert!(matches(&number, ok), "{}", ok);
+        }
+        for bad in ["01", "1.", "-", ".5", "1e", "+1"] {
+            assert!(!matches(&number, bad), "{}", bad);
+        }
+
+        let integer = json!({"type": "integer"});
+        assert!(matches(&integer, "-42"));
+        assert!(!prefix(&integer, "1."));
+        assert!(!prefix(&integer, "1e5"));
+
+        // 최상위 숫자는 끝을 알 수 없으므로 완료 상태가 아님
+        let mut matcher = JsonMatcher::new(&integer);
+        assert!(matcher.feed_str("7"));
+        assert!(!matcher.is_complete());
+        assert!(matcher.can_finish());
+
+        // 객체 안의 숫자는 뒤따르는 ',' / '}'에서 끝남
+        let object = json!({"properties": {"x": {"type": "number"}, "y": {"type": "number"}}});
+        assert!(matches(&object, r#"{"x":10,"y":0.5}"#));
+    }
+
+    #[test]
+    fn string_escapes() {
+        let schema = json!({"type": "string"});
+        assert!(matches(&schema, r#""a\n\"b\" \\ é""#));
+        assert!(!prefix(&schema, r#""\x"#));
+        assert!(!prefix(&schema, r#""\u12g"#));
+        // 제어 문자는 이스케이프해야 함
+        assert!(!prefix(&schema, "\"a\nb\""));
+    }
+
+    #[test]
+    fn limits_consecutive_whitespace() {
+        let schema = json!({"type": "object", "properties": {"a": {"type": "boolean"}}});
+        assert!(matches(&schema, "{ \"a\": true }"));
+        assert!(matches(&schema, "{\n\"a\":\nfalse}"));
+        assert!(!prefix(&schema, "{  \"a\""));
+        assert!(!prefix(&schema, "{\"a\":\n\n"));
+    }
+
+    #[test]
+    fn literals_and_any_schema() {
+        assert!(matches(&json!({"type": "boolean"}), "false"));
+        assert!(!prefix(&json!({"type": "boolean"}), "null"));
+        assert!(matches(&json!({"type": "null"}), "null"));
+        assert!(!prefix(&json!({"type": "null"}), "nul1"));
+
+        let any = json!({});
+        assert!(matches(&any, r#"[1,"x",{"k":null},true,[]]"#));
+        assert!(!prefix(&any, r#"[1,}"#));
+    }
+
+    #[test]
+    fn accepts_does_not_change_state_and_nothing_follows_a_complete_value() {
+        let schema = json!({"type": "array", "items": {"type": "integer"}});
+        let mut matcher = JsonMatcher::new(&schema);
+        assert!(matcher.accepts("[1"));
+        assert!(!matcher.accepts("{"));
+        assert!(matcher.feed_str("[1,2]"));
+        assert!(matcher.is_complete());
+        assert!(!matcher.accepts(" "));
+        assert!(!matcher.accepts("3"));
+    }
+}
@@ -0,0 +1,86 @@
+use super::{LLMBackend, TokenFn};
+use crate::error::{Result, SuprascalarError};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// 미리 정해 둔 응답을 순서대로 돌려주는 테스트용 백엔드
+///
+/// 모델을 로드하지 않고 `Agent::chat`의 ReAct 루프(도구 호출 파싱, 실행, 최대 턴 등)를
+/// CPU만으로 검증할 때 사용합니다. 백엔드가 `Agent`로 이동한 뒤에도
+/// `prompt_log()`로 받은 핸들을 통해 모델에 전달된 프롬프트를 확인할 수 있습니다.
+pub struct MockBackend {
+    responses: VecDeque<String>,
+    prompts: Arc<Mutex<Vec<String>>>,
+    on_token: Option<TokenFn>,
+    stop_sequences: Vec<String>,
+    context_limit: Option<usize>,
+}
+
+impl MockBackend {
+    pub fn new<S: Into<String>>(responses: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            responses: responses.into_iter().map(Into::into).collect(),
+            prompts: Arc::new(Mutex::new(Vec::new())),
+            on_token: None,
+            stop_sequences: Vec::new(),
+            context_limit: None,
+        }
+    }
+
+    /// 프롬프트 길이 한도 (토큰 수는 바이트 수로 셈). 컨텍스트 정리(`OverflowPolicy`) 검증용
+    pub fn with_context_limit(mut self, limit: usize) -> Self {
+        self.context_limit = Some(limit);
+        self
+    }
+
+    /// `generate`에 전달된 프롬프트 기록 (호출 순서대로)
+    pub fn prompt_log(&self) -> Arc<Mutex<Vec<String>>> {
+        Arc::clone(&self.prompts)
+    }
+}
+
+impl LLMBackend for MockBackend {
+    fn generate(&mut self, prompt: &str) -> Result<String> {
+        self.prompts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(prompt.to_string());
+
+        let mut response = self.responses.pop_front().ok_or_else(|| {
+            SuprascalarError::Unknown("MockBackend: no scripted responses left".to_string())
+        })?;
+        // 실제 백엔드처럼 가장 먼저 나오는 stop 문자열까지만 남김
+        if let Some(end) = self
+            .stop_sequences
+            .iter()
+            .filter_map(|stop| response.find(stop.as_str()).map(|i| i + stop.len()))
+            .min()
+        {
+            response.truncate(end);
+        }
+        // 스트리밍 경로 검증용: 응답 전체를 한 조각으로 전달
+        if let Some(on_token) = self.on_token.as_mut() {
+            on_token(&response);
+        }
+        Ok(response)
+    }
+
+    fn set_token_callback(&mut self, callback: Option<TokenFn>) {
+        self.on_token = callback;
+    }
+
+    fn set_stop_sequences(&mut self, stops: Vec<String>) {
+        self.stop_sequences = stops;
+    }
+
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        match self.context_limit {
+            Some(_) => Ok(text.len()),
+            None => Err(SuprascalarError::Unsupported("count_tokens".to_string())),
+        }
+    }
+
+    fn context_limit(&self) -> Option<usize> {
+        self.context_limit
+    }
+}
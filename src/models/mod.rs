@@ -1,8 +1,148 @@
-use crate::error::Result;
+use crate::error::{Result, SuprascalarError};
+use crate::util::CancellationToken;
+use candle_transformers::generation::Sampling;
+use serde_json::Value;
+pub(crate) mod json_schema;
+pub mod mock;
 pub mod qqwen3;
+pub mod quantized;
+pub mod registry;
+pub mod token_stream;
+
+pub use mock::MockBackend;
+pub use qqwen3::{BenchResult, HubConfig, RepetitionGuard};
+pub use quantized::QuantizedModel;
+pub use registry::{ModelRegistry, ModelSpec};
+pub use token_stream::TokenStreamDecoder;
+
+/// Sampling parameters used by a backend's generation loop.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenerationConfig {
+    pub seed: u64,
+    /// `None` (or ~0) selects greedy argmax decoding.
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    /// Keep only tokens with `prob >= min_p * max_prob`.
+    pub min_p: Option<f64>,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 299792458,
+            temperature: Some(0.7),
+            top_p: Some(0.95),
+            top_k: None,
+            min_p: None,
+        }
+    }
+}
+
+impl GenerationConfig {
+    /// Deterministic argmax decoding (temperature 0), e.g. for tool calls and evals.
+    pub fn greedy() -> Self {
+        Self {
+            temperature: None,
+            ..Self::default()
+        }
+    }
+
+    /// `true` when decoding is plain argmax, so backends can skip sampling entirely.
+    pub fn is_greedy(&self) -> bool {
+        self.temperature.is_none_or(|t| t < 1e-7)
+    }
+
+    /// Map the config onto candle's `Sampling` strategy.
+    pub fn sampling(&self) -> Sampling {
+        let temperature = self.temperature.filter(|_| !self.is_greedy());
+        match temperature {
+            None => Sampling::ArgMax,
+            Some(temperature) => match (self.top_k, self.top_p) {
+                (None, None) => Sampling::All { temperature },
+                (Some(k), None) => Sampling::TopK { k, temperature },
+                (None, Some(p)) => Sampling::TopP { p, temperature },
+                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            },
+        }
+    }
+}
+
+/// Token usage reported by a single `generate` call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Callback receiving decoded text pieces while a backend generates.
+pub type TokenFn = Box<dyn FnMut(&str)>;
 
 /// The core trait that any Model backend must implement.
 pub trait LLMBackend {
     /// Generate a response based on the provided prompt string.
     fn generate(&mut self, prompt: &str) -> Result<String>;
+
+    /// Generate responses for several prompts. Backends without batching
+    /// support fall back to sequential `generate` calls.
+    fn generate_batch(&mut self, prompts: &[String]) -> Result<Vec<String>> {
+        prompts.iter().map(|prompt| self.generate(prompt)).collect()
+    }
+
+    /// Generate output constrained to match a JSON Schema, so the result always parses.
+    fn generate_json(&mut self, _prompt: &str, _schema: &Value) -> Result<String> {
+        Err(SuprascalarError::Unsupported("generate_json".to_string()))
+    }
+
+    /// Number of tokens `text` encodes to with this backend's tokenizer.
+    fn count_tokens(&self, _text: &str) -> Result<usize> {
+        Err(SuprascalarError::Unsupported("count_tokens".to_string()))
+    }
+
+    /// Maximum prompt length in tokens, if the backend enforces one.
+    fn context_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Token usage of the most recent `generate` call, if the backend tracks it.
+    fn last_usage(&self) -> Option<Usage> {
+        None
+    }
+
+    /// Use separate sampling parameters inside `<think>...</think>` blocks.
+    /// `None` restores a single config for the whole response.
+    fn set_thinking_config(&mut self, _config: Option<GenerationConfig>) {}
+
+    /// Install a token that aborts generation between decode steps.
+    fn set_cancellation(&mut self, _token: Option<CancellationToken>) {}
+
+    /// Receive text pieces from `generate` as they are decoded (`None` stops streaming).
+    /// Backends that cannot stream ignore the callback.
+    fn set_token_callback(&mut self, _callback: Option<TokenFn>) {}
+
+    /// Stop `generate` as soon as the output contains one of these strings.
+    /// The matched stop string is kept at the end of the result; an empty list
+    /// disables stopping. Backends without support ignore it.
+    fn set_stop_sequences(&mut self, _stops: Vec<String>) {}
+}
+
+impl dyn LLMBackend {
+    /// `ModelRegistry` 기본값에 등록된 이름으로 백엔드를 만듭니다.
+    /// 등록되지 않은 이름이면 `ModelNotFound`를 반환합니다.
+    pub fn from_name(name: &str) -> Result<Box<dyn LLMBackend>> {
+        ModelRegistry::default().load(name)
+    }
 }
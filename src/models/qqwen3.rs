@@ -1,98 +1,911 @@
-use super::LLMBackend;
+use super::json_schema::JsonMatcher;
+use super::quantized::QuantizedModel;
+use super::registry::{ModelRegistry, ModelSpec};
+use super::{GenerationConfig, LLMBackend, TokenFn, TokenStreamDecoder, Usage};
 use crate::error::{Result, SuprascalarError};
+use crate::util::{CancellationToken, select_device, sync_device};
 
-use crate::candle_transformers_patched::quantized_qwen3::ModelWeights as Qwen3;
-use candle_core::{Device, Tensor};
+use candle_core::{D, DType, Device, Tensor};
 use candle_transformers::generation::LogitsProcessor;
-use hf_hub::api::sync::Api;
+use hf_hub::Cache;
+use hf_hub::api::sync::{Api, ApiBuilder, ApiError};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokenizers::Tokenizer;
 
+// 예제 등 기존 경로(`qqwen3::gguf_architecture`) 호환
+pub use super::quantized::gguf_architecture;
+
+/// 프롬프트 최대 길이 (토큰)
+const MAX_CONTEXT: usize = 32000;
+
+/// `generate`가 한 번에 생성하는 최대 토큰 수
+const MAX_NEW_TOKENS: usize = 1000;
+
+/// JSON 제약 디코딩에서 매 스텝 샘플링 후보로 남길 (스키마를 만족하는) 토큰 수
+const JSON_CANDIDATES: usize = 64;
+
+/// 재시도 간 대기 시간의 상한
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// 반복 감지로 생성을 멈췄을 때 `generate` 결과 끝에 붙는 안내
+const REPETITION_NOTE: &str = "\n[generation stopped: the model kept repeating the same output]";
+
+/// 같은 토큰/구절만 끝없이 내는 생성(양자화 모델에서 가끔 발생)을 일찍 멈추는 감시 설정
+/// 반복 페널티와 달리 매 스텝 로짓을 건드리지 않고, 출력 끝이 반복으로 채워졌을 때만 생성을 끊습니다.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RepetitionGuard {
+    /// 마지막 N개 토큰이 모두 같으면 멈춥니다 (2 미만이면 검사하지 않음).
+    pub max_repeated_tokens: usize,
+    /// 길이 2..=`max_ngram`인 토큰열이 출력 끝에서 `ngram_repeats`번 연속 반복되면 멈춥니다.
+    pub max_ngram: usize,
+    pub ngram_repeats: usize,
+}
+
+impl Default for RepetitionGuard {
+    fn default() -> Self {
+        Self {
+            max_repeated_tokens: 48,
+            max_ngram: 8,
+            ngram_repeats: 10,
+        }
+    }
+}
+
+impl RepetitionGuard {
+    /// 지금까지 생성한 토큰의 끝부분이 반복으로 채워졌는지 확인합니다.
+    pub fn is_degenerate(&self, tokens: &[u32]) -> bool {
+        repeats_at_end(tokens, 1, self.max_repeated_tokens)
+            || (2..=self.max_ngram).any(|n| repeats_at_end(tokens, n, self.ngram_repeats))
+    }
+}
+
+/// `tokens`의 마지막 `n * times`개가 길이 `n`인 같은 토큰열의 반복인지 확인합니다.
+/// 한 토큰만 반복되는 경우는 `max_repeated_tokens`가 맡으므로 n >= 2에서는 제외합니다.
+fn repeats_at_end(tokens: &[u32], n: usize, times: usize) -> bool {
+    let span = n * times;
+    if n == 0 || times < 2 || tokens.len() < span {
+        return false;
+    }
+    let tail = &tokens[tokens.len() - span..];
+    let unit = &tail[..n];
+    if n > 1 && unit.iter().all(|&t| t == unit[0]) {
+        return false;
+    }
+    tail.chunks(n).all(|chunk| chunk == unit)
+}
+
+/// `CandleQwen::benchmark` 측정 결과
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchResult {
+    pub prompt_tokens: usize,
+    pub generated_tokens: usize,
+    /// 프롬프트 전체를 한 번에 forward 한 시간
+    pub prefill: Duration,
+    /// 첫 토큰 이후 `generated_tokens`개를 한 토큰씩 디코딩한 시간
+    pub decode: Duration,
+}
+
+impl BenchResult {
+    /// 디코딩 속도 (생성 토큰/초)
+    pub fn tokens_per_sec(&self) -> f64 {
+        per_sec(self.generated_tokens, self.decode)
+    }
+
+    /// 프리필 속도 (프롬프트 토큰/초)
+    pub fn prefill_tokens_per_sec(&self) -> f64 {
+        per_sec(self.prompt_tokens, self.prefill)
+    }
+}
+
+fn per_sec(tokens: usize, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    tokens as f64 / elapsed.as_secs_f64()
+}
+
+/// HF Hub에서 모델/토크나이저를 가져오는 방식
+#[derive(Clone, Debug)]
+pub struct HubConfig {
+    /// HF 캐시 디렉토리 (None이면 `HF_HOME` 등 기본 위치)
+    pub cache_dir: Option<PathBuf>,
+    /// true면 네트워크 없이 캐시에 있는 파일만 사용합니다.
+    pub offline: bool,
+    /// 다운로드 실패 시 추가로 시도할 횟수 (0이면 재시도 없음)
+    pub max_retries: usize,
+    /// 첫 재시도 전 대기 시간. 이후 시도마다 두 배로 늘어납니다 (최대 60초).
+    pub retry_backoff: Duration,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        Self {
+            cache_dir: None,
+            offline: false,
+            max_retries: 3,
+            retry_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl HubConfig {
+    /// 환경 변수 `HF_HUB_OFFLINE=1`이면 오프라인 모드로 시작합니다.
+    pub fn from_env() -> Self {
+        Self {
+            offline: std::env::var("HF_HUB_OFFLINE").is_ok_and(|v| v == "1"),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// 일시적인 네트워크 오류에 대비한 재시도 설정 (기본: 3회, 1초부터 지수 백오프)
+    /// 받다 만 파일은 hf-hub가 이어 받으므로 큰 GGUF도 처음부터 다시 받지 않습니다.
+    pub fn with_retries(mut self, max_retries: usize, backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    fn cache(&self) -> Cache {
+        match &self.cache_dir {
+            Some(dir) => Cache::new(dir.clone()),
+            None => Cache::from_env(),
+        }
+    }
+
+    /// 파일 경로를 얻습니다. 오프라인이면 캐시만 확인하고 없으면 바로 실패합니다.
+    fn fetch(&self, repo: &str, file: &str) -> Result<PathBuf> {
+        if self.offline {
+            return self
+                .cache()
+                .model(repo.to_string())
+                .get(file)
+                .ok_or_else(|| SuprascalarError::NotCached {
+                    repo: repo.to_string(),
+                    file: file.to_string(),
+                });
+        }
+        let repo = self.api()?.model(repo.to_string());
+        self.retry(|| repo.get(file))
+    }
+
+    /// repo에 있는 파일 경로 목록 (정렬됨). 네트워크가 필요하므로 오프라인 모드에서는 실패합니다.
+    pub fn list_files(&self, repo: &str) -> Result<Vec<String>> {
+        if self.offline {
+            return Err(SuprascalarError::Unsupported(format!(
+                "listing files of '{}' needs network access (offline mode)",
+                repo
+            )));
+        }
+        let api_repo = self.api()?.model(repo.to_string());
+        let info = self.retry(|| api_repo.info())?;
+        let mut files: Vec<String> = info.siblings.into_iter().map(|s| s.rfilename).collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn api(&self) -> Result<Api> {
+        let builder = match &self.cache_dir {
+            Some(dir) => ApiBuilder::new().with_cache_dir(dir.clone()),
+            None => ApiBuilder::from_env(),
+        };
+        Ok(builder.build()?)
+    }
+
+    /// 실패하면 지수 백오프로 `max_retries`번까지 다시 시도합니다.
+    fn retry<T>(&self, mut request: impl FnMut() -> std::result::Result<T, ApiError>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match request() {
+                Ok(value) => return Ok(value),
+                // 재시도를 모두 쓰면 마지막 오류를 그대로 HfHub로 반환
+                Err(e) if attempt >= self.max_retries => return Err(e.into()),
+                Err(_) => {
+                    let backoff = self
+                        .retry_backoff
+                        .saturating_mul(1 << attempt.min(16))
+                        .min(MAX_RETRY_BACKOFF);
+                    std::thread::sleep(backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// GGUF Qwen 모델 백엔드
+///
+/// drop하면 가중치와 KV 캐시 텐서가 해제되지만, 비동기 디바이스(CUDA/Metal)에서는
+/// 아직 실행 중인 커널이 버퍼를 참조하고 있을 수 있어 실제 반납 시점이 늦어질 수 있습니다.
+/// 모델을 교체하는 서버처럼 다음 모델을 로드하기 전에 VRAM이 비어 있어야 한다면 `unload`를 사용하세요.
 pub struct CandleQwen {
-    model: Qwen3,
+    model: QuantizedModel,
     tokenizer: Tokenizer,
     logits_processor: LogitsProcessor,
+    config: GenerationConfig,
     device: Device,
+    last_usage: Option<Usage>,
+    cancel: Option<CancellationToken>,
+    // 설정되어 있으면 생성 중 디코딩된 텍스트 조각을 바로 전달
+    on_token: Option<TokenFn>,
+    // 출력에 이 문자열 중 하나가 나오면 생성 종료
+    stop_sequences: Vec<String>,
+    thinking: Option<ThinkingSampler>,
+    repetition_guard: Option<RepetitionGuard>,
+    // 마지막 생성이 반복 감지로 중단되었는지
+    stopped_on_repetition: bool,
+    // 토큰 id별 디코딩 텍스트 (JSON 제약 디코딩용, 처음 사용할 때 계산)
+    token_texts: Option<Arc<Vec<Option<String>>>>,
+}
+
+/// `<think>` 구간에서만 사용하는 샘플러 (Qwen3는 추론/답변 구간에 서로 다른 샘플링을 권장)
+struct ThinkingSampler {
+    config: GenerationConfig,
+    logits_processor: LogitsProcessor,
 }
 
 impl CandleQwen {
+    /// 사용 가능한 디바이스(CUDA → Metal → CPU)를 자동으로 선택해 로드합니다.
     pub fn new(repo: &str, model_file: &str, tokenizer_repo: &str) -> Result<Self> {
-        let device = Device::new_cuda(0)?;
-        //huggingface api
-        let api = Api::new()?;
+        Self::new_on_device(repo, model_file, tokenizer_repo, select_device()?)
+    }
+
+    /// `ModelRegistry` 기본값에 등록된 이름(예: "qwen3-14b")으로 로드합니다.
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::from_spec(ModelRegistry::default().get(name)?)
+    }
+
+    /// `ModelSpec`의 repo/파일로 로드합니다. 디바이스는 자동으로 선택합니다.
+    pub fn from_spec(spec: &ModelSpec) -> Result<Self> {
+        Self::new(&spec.repo, &spec.file, &spec.tokenizer_repo)
+    }
+
+    /// HF repo의 파일 목록을 가져옵니다 (다운로드 전에 사용할 수 있는 양자화 파일 확인용).
+    /// 예: `CandleQwen::list_files("unsloth/Qwen3-14B-GGUF")?` 중 `.gguf`로 끝나는 항목
+    /// (`Qwen3-14B-Q4_K_M.gguf`, `Qwen3-14B-Q6_K.gguf`, ...). 캐시/오프라인 설정은 `HubConfig::from_env()`를 따릅니다.
+    pub fn list_files(repo: &str) -> Result<Vec<String>> {
+        HubConfig::from_env().list_files(repo)
+    }
+
+    /// 지정한 디바이스에 모델을 로드합니다.
+    pub fn new_on_device(
+        repo: &str,
+        model_file: &str,
+        tokenizer_repo: &str,
+        device: Device,
+    ) -> Result<Self> {
+        Self::from_hub(
+            repo,
+            model_file,
+            tokenizer_repo,
+            device,
+            &HubConfig::from_env(),
+        )
+    }
 
+    /// 캐시 위치/오프라인 여부를 지정해 HF Hub에서 로드합니다.
+    pub fn from_hub(
+        repo: &str,
+        model_file: &str,
+        tokenizer_repo: &str,
+        device: Device,
+        hub: &HubConfig,
+    ) -> Result<Self> {
         //tokenizer
-        let tokenizer_path = api
-            .model(tokenizer_repo.to_string())
-            .get("tokenizer.json")?;
+        let tokenizer_path = hub.fetch(tokenizer_repo, "tokenizer.json")?;
         let tokenizer = Tokenizer::from_file(tokenizer_path)
             .map_err(|e| SuprascalarError::Tokenizer(e.to_string()))?;
 
         //model
-        let model_path = api.model(repo.to_string()).get(model_file)?;
-        let mut file = std::fs::File::open(&model_path)?;
-        let content = candle_core::quantized::gguf_file::Content::read(&mut file)?;
-        let model = Qwen3::from_gguf(content, &mut file, &device)?;
+        let model_path = hub.fetch(repo, model_file)?;
+        let model = QuantizedModel::load(&model_path, &device)?;
 
-        let logits_processor = LogitsProcessor::new(299792458, Some(0.7), Some(0.95));
+        let config = GenerationConfig::default();
+        let logits_processor = LogitsProcessor::from_sampling(config.seed, config.sampling());
 
         Ok(Self {
             model,
             tokenizer,
             logits_processor,
+            config,
             device,
+            last_usage: None,
+            cancel: None,
+            on_token: None,
+            stop_sequences: Vec::new(),
+            thinking: None,
+            repetition_guard: None,
+            stopped_on_repetition: false,
+            token_texts: None,
         })
     }
-}
 
-impl LLMBackend for CandleQwen {
-    fn generate(&mut self, prompt: &str) -> Result<String> {
+    /// 샘플링 설정을 교체합니다 (LogitsProcessor 재생성).
+    pub fn set_generation_config(&mut self, config: GenerationConfig) {
+        self.logits_processor = LogitsProcessor::from_sampling(config.seed, config.sampling());
+        self.config = config;
+    }
+
+    pub fn generation_config(&self) -> &GenerationConfig {
+        &self.config
+    }
+
+    /// 반복 감지를 켜거나(`Some`) 끕니다(`None`, 기본). 켜면 출력이 반복에 빠졌을 때
+    /// 최대 토큰 수까지 기다리지 않고 멈추며, `generate`는 그때까지의 결과 끝에 안내 문구를 붙입니다.
+    pub fn set_repetition_guard(&mut self, guard: Option<RepetitionGuard>) {
+        self.repetition_guard = guard;
+    }
+
+    /// 마지막 `generate`/`generate_tokens`가 반복 감지로 중단되었는지
+    pub fn stopped_on_repetition(&self) -> bool {
+        self.stopped_on_repetition
+    }
+
+    /// 모델을 내리고 디바이스 메모리를 반납합니다.
+    /// 큐잉된 커널이 끝나길 기다린 뒤 KV 캐시 → 가중치 순으로 해제하고, 해제까지 끝난 뒤 반환합니다.
+    /// (Metal은 candle의 버퍼 풀이 해제된 버퍼를 다음 할당 때 재사용/정리하므로 같은 프로세스의 다음 로드에 쓰입니다.)
+    pub fn unload(mut self) -> Result<()> {
+        sync_device(&self.device)?;
+        self.model.clear_kv_cache();
+        let Self {
+            model,
+            device,
+            token_texts,
+            ..
+        } = self;
+        drop(token_texts);
+        drop(model);
+        sync_device(&device)
+    }
+
+    /// 짧은 forward를 한 번 실행해 커널 로딩/메모리 할당을 미리 끝냅니다.
+    /// 서비스 시작 시 호출하면 첫 요청이 느려지지 않습니다. 걸린 시간을 반환합니다.
+    pub fn warmup(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        let tokens = self.encode("<|im_start|>user\nHello<|im_end|>\n")?;
+        let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+        self.model.forward(&input, 0)?;
+        // 비동기 디바이스(CUDA/Metal)는 실제 실행이 끝날 때까지 기다려야 정확한 시간이 나옴
+        sync_device(&self.device)?;
         self.model.clear_kv_cache();
+        Ok(start.elapsed())
+    }
 
-        // Tokenizer errors need manual mapping to SuprascalarError::Tokenizer
+    /// 같은 방식으로 프리필/디코딩 속도를 측정합니다 (양자화 파일, 디바이스 비교용).
+    /// `warmup` 후 프롬프트를 한 번 forward 하고, EOS와 상관없이 greedy로 `n_tokens`개를 디코딩합니다.
+    /// 구간마다 디바이스 동기화 후 시간을 재므로 비동기 디바이스(CUDA/Metal)에서도 실제 실행 시간입니다.
+    pub fn benchmark(&mut self, prompt: &str, n_tokens: usize) -> Result<BenchResult> {
+        let tokens = self.encode(prompt)?;
+        if tokens.is_empty() {
+            return Err(SuprascalarError::Tokenizer(
+                "empty input: at least one token is required".to_string(),
+            ));
+        }
+        if tokens.len() + n_tokens > MAX_CONTEXT {
+            return Err(SuprascalarError::ContextLimitExceeded {
+                limit: MAX_CONTEXT,
+                current: tokens.len() + n_tokens,
+            });
+        }
+        self.warmup()?;
+        self.model.clear_kv_cache();
+
+        let start = Instant::now();
+        let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+        let logits = self.model.forward(&input, 0)?;
+        let mut next_token = logits.squeeze(0)?.argmax(D::Minus1)?.to_scalar::<u32>()?;
+        sync_device(&self.device)?;
+        let prefill = start.elapsed();
+
+        let start = Instant::now();
+        for pos in tokens.len()..tokens.len() + n_tokens {
+            if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(SuprascalarError::Cancelled);
+            }
+            let input = Tensor::new(&[next_token], &self.device)?.unsqueeze(0)?;
+            let logits = self.model.forward(&input, pos)?;
+            // argmax 결과를 읽으면서 매 스텝 동기화되므로 실제 생성과 같은 조건
+            next_token = logits.squeeze(0)?.argmax(D::Minus1)?.to_scalar::<u32>()?;
+        }
+        sync_device(&self.device)?;
+        let decode = start.elapsed();
+        self.model.clear_kv_cache();
+
+        Ok(BenchResult {
+            prompt_tokens: tokens.len(),
+            generated_tokens: n_tokens,
+            prefill,
+            decode,
+        })
+    }
+
+    /// 토큰 id로 바로 생성합니다 (이미 인코딩된 프롬프트, 이어쓰기, 프리픽스 캐싱 등).
+    /// 새로 생성한 토큰만 반환하며, EOS로 끝났다면 마지막 토큰이 EOS입니다.
+    /// 문자열 `generate`도 인코딩한 뒤 이 메서드를 사용합니다.
+    pub fn generate_tokens(&mut self, tokens: &[u32], max_new: usize) -> Result<Vec<u32>> {
+        self.model.clear_kv_cache();
+        self.last_usage = None;
+        self.stopped_on_repetition = false;
+
+        if tokens.is_empty() {
+            return Err(SuprascalarError::Tokenizer(
+                "empty input: at least one token is required".to_string(),
+            ));
+        }
+        if tokens.len() > MAX_CONTEXT {
+            return Err(SuprascalarError::ContextLimitExceeded {
+                limit: MAX_CONTEXT,
+                current: tokens.len(),
+            });
+        }
+
+        let _span = tracing::debug_span!("generate", prompt_tokens = tokens.len()).entered();
+        let start = Instant::now();
+        let mut input = Tensor::new(tokens, &self.device)?.unsqueeze(0)?;
+        let mut pos = 0;
+        let mut in_think = self.starts_in_think_tokens(tokens);
+        let mut generated = Vec::new();
+        let mut stream = (self.on_token.is_some() || !self.stop_sequences.is_empty())
+            .then(TokenStreamDecoder::new);
+        // stop 문자열 검사용으로 지금까지 디코딩한 텍스트
+        let mut text = String::new();
+
+        for _ in 0..max_new {
+            if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(SuprascalarError::Cancelled);
+            }
+
+            let logits = {
+                let _forward = tracing::trace_span!("forward", pos).entered();
+                self.model.forward(&input, pos)?
+            };
+            let logits = logits.squeeze(0)?;
+            let next_token = self.sample(&logits, in_think)?;
+            self.update_think_state(next_token, &mut in_think);
+            generated.push(next_token);
+            let mut stopped = false;
+            if let Some(decoder) = stream.as_mut()
+                && let Some(delta) = decoder.push(&self.tokenizer, next_token)?
+            {
+                if let Some(on_token) = self.on_token.as_mut() {
+                    on_token(&delta);
+                }
+                if !self.stop_sequences.is_empty() {
+                    text.push_str(&delta);
+                    stopped = self
+                        .stop_sequences
+                        .iter()
+                        .any(|s| text.contains(s.as_str()));
+                }
+            }
+
+            if stopped || self.is_eos(next_token) {
+                break;
+            }
+            if self
+                .repetition_guard
+                .is_some_and(|guard| guard.is_degenerate(&generated))
+            {
+                tracing::warn!(
+                    generated = generated.len(),
+                    "stopping generation: output is repeating"
+                );
+                self.stopped_on_repetition = true;
+                break;
+            }
+            let (_b, seq_len) = input.dims2()?;
+            pos += seq_len;
+            input = Tensor::new(&[next_token], &self.device)?.unsqueeze(0)?;
+        }
+        if let (Some(decoder), Some(on_token)) = (stream.as_ref(), self.on_token.as_mut())
+            && let Some(rest) = decoder.flush(&self.tokenizer)?
+        {
+            on_token(&rest);
+        }
+
+        tracing::debug!(
+            completion_tokens = generated.len(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "generation finished"
+        );
+        self.last_usage = Some(Usage {
+            prompt_tokens: tokens.len(),
+            completion_tokens: generated.len(),
+        });
+        Ok(generated)
+    }
+
+    /// `starts_in_think`의 토큰 버전: 마지막 `<|im_start|>` 이후에 닫히지 않은 `<think>`가 있는지 확인합니다.
+    fn starts_in_think_tokens(&self, tokens: &[u32]) -> bool {
+        let id = |t: &str| self.tokenizer.token_to_id(t);
+        let (Some(im_start), Some(think), Some(think_end)) =
+            (id("<|im_start|>"), id("<think>"), id("</think>"))
+        else {
+            return false;
+        };
+        let Some(last) = tokens.iter().rposition(|&t| t == im_start) else {
+            return false;
+        };
+        let tail = &tokens[last..];
+        tail.contains(&think) && !tail.contains(&think_end)
+    }
+
+    fn is_eos(&self, token: u32) -> bool {
+        token == self.tokenizer.token_to_id("<|endoftext|>").unwrap_or(0)
+            || token == self.tokenizer.token_to_id("<|im_end|>").unwrap_or(0)
+    }
+
+    fn encode(&self, prompt: &str) -> Result<Vec<u32>> {
         let tokens = self
             .tokenizer
             .encode(prompt, true)
             .map_err(|e| SuprascalarError::Tokenizer(e.to_string()))?;
+        Ok(tokens.get_ids().to_vec())
+    }
+
+    /// 현재 구간(추론/답변)에 맞는 샘플러로 다음 토큰을 뽑습니다.
+    fn sample(&mut self, logits: &Tensor, in_think: bool) -> Result<u32> {
+        let (config, logits_processor) = match self.thinking.as_mut() {
+            Some(thinking) if in_think => (&thinking.config, &mut thinking.logits_processor),
+            _ => (&self.config, &mut self.logits_processor),
+        };
+        // greedy는 샘플러를 거치지 않고 디바이스에서 argmax (vocab 전체를 CPU로 복사하지 않음, seed 무관)
+        if config.is_greedy() {
+            return Ok(logits.argmax(D::Minus1)?.to_scalar::<u32>()?);
+        }
+        let next_token = match config.min_p.filter(|p| *p > 0.0) {
+            Some(min_p) => {
+                logits_processor.sample(&apply_min_p(logits, config.temperature, min_p)?)?
+            }
+            None => logits_processor.sample(logits)?,
+        };
+        Ok(next_token)
+    }
+
+    /// 토큰 id별로 단독 디코딩한 텍스트. 특수/추가 토큰과 불완전한 UTF-8 조각은 None입니다.
+    fn token_texts(&mut self, vocab_size: usize) -> Arc<Vec<Option<String>>> {
+        if let Some(texts) = &self.token_texts
+            && texts.len() == vocab_size
+        {
+            return texts.clone();
+        }
+        let added = self.tokenizer.get_added_tokens_decoder();
+        let texts: Vec<Option<String>> = (0..vocab_size as u32)
+            .map(|id| {
+                if added.contains_key(&id) {
+                    return None;
+                }
+                self.tokenizer
+                    .decode(&[id], false)
+                    .ok()
+                    .filter(|t| !t.is_empty() && !t.contains('\u{FFFD}'))
+            })
+            .collect();
+        let texts = Arc::new(texts);
+        self.token_texts = Some(texts.clone());
+        texts
+    }
+
+    /// 샘플링한 토큰이 `<think>` / `</think>`이면 추론 구간 상태를 갱신합니다.
+    fn update_think_state(&self, token: u32, in_think: &mut bool) {
+        if self.tokenizer.token_to_id("<think>") == Some(token) {
+            *in_think = true;
+        } else if self.tokenizer.token_to_id("</think>") == Some(token) {
+            *in_think = false;
+        }
+    }
+}
+
+/// 프롬프트가 열린 `<think>` 블록으로 끝나면 (assistant 프리필) 추론 구간에서 시작합니다.
+fn starts_in_think(prompt: &str) -> bool {
+    prompt
+        .rsplit_once("<|im_start|>assistant")
+        .is_some_and(|(_, tail)| tail.contains("<think>") && !tail.contains("</think>"))
+}
+
+/// min-p 필터링: 최대 확률 대비 `min_p` 비율 미만인 토큰의 logit을 -inf로 마스킹합니다.
+/// prob_i / prob_max = exp((l_i - l_max) / T) 이므로 logit 공간에서 바로 비교할 수 있습니다.
+fn apply_min_p(logits: &Tensor, temperature: Option<f64>, min_p: f64) -> Result<Tensor> {
+    let temperature = temperature.filter(|t| *t >= 1e-7).unwrap_or(1.0);
+    let mut values = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let threshold = max + (temperature * min_p.ln()) as f32;
+    for v in values.iter_mut() {
+        if *v < threshold {
+            *v = f32::NEG_INFINITY;
+        }
+    }
+    Ok(Tensor::new(values, logits.device())?)
+}
+
+impl LLMBackend for CandleQwen {
+    fn generate(&mut self, prompt: &str) -> Result<String> {
+        // Tokenizer errors need manual mapping to SuprascalarError::Tokenizer
+        let tokens = self.encode(prompt)?;
+        let generated = self.generate_tokens(&tokens, MAX_NEW_TOKENS)?;
+
+        let mut decoder = TokenStreamDecoder::new();
+        let mut result = String::new();
+        for token in generated {
+            if let Some(delta) = decoder.push(&self.tokenizer, token)? {
+                result.push_str(&delta);
+            }
+        }
+        if let Some(rest) = decoder.flush(&self.tokenizer)? {
+            result.push_str(&rest);
+        }
+        if self.stopped_on_repetition {
+            result.push_str(REPETITION_NOTE);
+        }
+
+        Ok(result)
+    }
+
+    /// 여러 프롬프트를 좌측 패딩으로 쌓아 한 번에 prefill/decode 합니다.
+    /// 각 행은 `generate`와 같은 조건(EOS, stop 문자열, 반복 감지)으로 독립적으로 종료되며,
+    /// 모든 행이 끝나면 루프를 멈춥니다.
+    /// 행들이 동시에 디코딩되므로 토큰 콜백은 조각 단위가 아니라, 끝난 뒤 행 순서대로 행 전체 텍스트를 받습니다.
+    fn generate_batch(&mut self, prompts: &[String]) -> Result<Vec<String>> {
+        if prompts.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Qwen2/MoE 모델은 패딩 마스크를 지원하지 않으므로 순차 생성으로 대체
+        if !self.model.supports_padded_batch() {
+            return prompts.iter().map(|p| self.generate(p)).collect();
+        }
+
+        self.model.clear_kv_cache();
+        self.last_usage = None;
+        self.stopped_on_repetition = false;
 
-        let tokens = tokens.get_ids().to_vec();
-        let mut generated_tokens = Vec::new();
+        let encoded = prompts
+            .iter()
+            .map(|p| self.encode(p))
+            .collect::<Result<Vec<_>>>()?;
 
-        // Check context limit (Example of using the custom error)
-        if tokens.len() > 32000 {
+        let max_len = encoded.iter().map(|t| t.len()).max().unwrap_or(0);
+        if max_len > MAX_CONTEXT {
             return Err(SuprascalarError::ContextLimitExceeded {
-                limit: 32000,
+                limit: MAX_CONTEXT,
+                current: max_len,
+            });
+        }
+
+        // 좌측 패딩 (패딩 토큰은 마스킹되므로 어떤 id든 무방)
+        let pad_id = self.tokenizer.token_to_id("<|endoftext|>").unwrap_or(0);
+        let pad_lens: Vec<usize> = encoded.iter().map(|t| max_len - t.len()).collect();
+        let mut flat = Vec::with_capacity(encoded.len() * max_len);
+        for (tokens, &pad) in encoded.iter().zip(pad_lens.iter()) {
+            flat.extend(std::iter::repeat_n(pad_id, pad));
+            flat.extend_from_slice(tokens);
+        }
+
+        let batch = encoded.len();
+        let mut input = Tensor::from_vec(flat, (batch, max_len), &self.device)?;
+        let mut pos = 0;
+        let mut generated: Vec<Vec<u32>> = vec![Vec::new(); batch];
+        let mut finished = vec![false; batch];
+        let mut in_think: Vec<bool> = prompts.iter().map(|p| starts_in_think(p)).collect();
+        // 행별 stop 문자열 검사용 디코더/텍스트와 반복 감지 여부
+        let mut decoders: Vec<TokenStreamDecoder> =
+            (0..batch).map(|_| TokenStreamDecoder::new()).collect();
+        let mut texts = vec![String::new(); batch];
+        let mut repeated = vec![false; batch];
+
+        for _ in 0..MAX_NEW_TOKENS {
+            if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(SuprascalarError::Cancelled);
+            }
+
+            let logits = self.model.forward_padded(&input, pos, &pad_lens)?;
+            let mut next_tokens = Vec::with_capacity(batch);
+
+            for (row, done) in finished.iter_mut().enumerate() {
+                if *done {
+                    // 끝난 행은 패딩 토큰으로 계속 채움 (결과에는 포함하지 않음)
+                    next_tokens.push(pad_id);
+                    continue;
+                }
+
+                let next_token = self.sample(&logits.get(row)?, in_think[row])?;
+                self.update_think_state(next_token, &mut in_think[row]);
+                generated[row].push(next_token);
+                if self.is_eos(next_token) {
+                    *done = true;
+                } else if !self.stop_sequences.is_empty()
+                    && let Some(delta) = decoders[row].push(&self.tokenizer, next_token)?
+                {
+                    texts[row].push_str(&delta);
+                    *done = self
+                        .stop_sequences
+                        .iter()
+                        .any(|s| texts[row].contains(s.as_str()));
+                }
+                if !*done
+                    && self
+                        .repetition_guard
+                        .is_some_and(|guard| guard.is_degenerate(&generated[row]))
+                {
+                    tracing::warn!(
+                        row,
+                        generated = generated[row].len(),
+                        "stopping batch row: output is repeating"
+                    );
+                    repeated[row] = true;
+                    *done = true;
+                }
+                next_tokens.push(next_token);
+            }
+
+            if finished.iter().all(|done| *done) {
+                break;
+            }
+
+            let (_b, seq_len) = input.dims2()?;
+            pos += seq_len;
+            input = Tensor::from_vec(next_tokens, (batch, 1), &self.device)?;
+        }
+
+        let results = generated
+            .iter()
+            .zip(&repeated)
+            .map(|(tokens, &repeated)| {
+                let mut text = self
+                    .tokenizer
+                    .decode(tokens, true)
+                    .map_err(|e| SuprascalarError::Tokenizer(e.to_string()))?;
+                if repeated {
+                    text.push_str(REPETITION_NOTE);
+                }
+                Ok(text)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.stopped_on_repetition = repeated.iter().any(|r| *r);
+        if let Some(on_token) = self.on_token.as_mut() {
+            for text in &results {
+                on_token(text);
+            }
+        }
+
+        self.last_usage = Some(Usage {
+            prompt_tokens: encoded.iter().map(|t| t.len()).sum(),
+            completion_tokens: generated.iter().map(|t| t.len()).sum(),
+        });
+
+        Ok(results)
+    }
+
+    /// 매 스텝 logit이 높은 순서로 토큰을 시험해 스키마를 만족하는 후보만 남기고 샘플링합니다.
+    /// 최상위 값이 끝나면 (EOS를 기다리지 않고) 바로 종료합니다.
+    /// `MAX_NEW_TOKENS` 안에 값을 끝내지 못하면 잘린 JSON을 돌려주지 않고 에러를 반환합니다.
+    /// Qwen3는 `<think>` 블록을 만들 수 없으므로 프롬프트에서 추론을 끈 상태로 사용하세요.
+    fn generate_json(&mut self, prompt: &str, schema: &Value) -> Result<String> {
+        self.model.clear_kv_cache();
+        self.last_usage = None;
+
+        let tokens = self.encode(prompt)?;
+        if tokens.len() > MAX_CONTEXT {
+            return Err(SuprascalarError::ContextLimitExceeded {
+                limit: MAX_CONTEXT,
                 current: tokens.len(),
             });
         }
 
+        let mut matcher = JsonMatcher::new(schema);
+        let mut result = String::new();
+        let mut completion_tokens = 0;
         let mut input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
         let mut pos = 0;
 
-        // Simplified loop
-        for _ in 0..1000 {
-            let logits = self.model.forward(&input, pos)?;
-            let logits = logits.squeeze(0)?;
-            let next_token = self.logits_processor.sample(&logits)?;
+        for _ in 0..MAX_NEW_TOKENS {
+            if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(SuprascalarError::Cancelled);
+            }
 
-            // tokens.push(next_token);
-            generated_tokens.push(next_token);
+            let logits = self.model.forward(&input, pos)?.squeeze(0)?;
+            let values = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+            let texts = self.token_texts(values.len());
 
-            // Break on EOS (Simplified)
-            if next_token == self.tokenizer.token_to_id("<|endoftext|>").unwrap_or(0)
-                || next_token == self.tokenizer.token_to_id("<|im_end|>").unwrap_or(0)
-            {
+            let mut order: Vec<u32> = (0..values.len() as u32).collect();
+            order.sort_unstable_by(|a, b| values[*b as usize].total_cmp(&values[*a as usize]));
+
+            let mut masked = vec![f32::NEG_INFINITY; values.len()];
+            let mut allowed = 0;
+            for id in order {
+                let ok = if self.is_eos(id) {
+                    matcher.can_finish()
+                } else {
+                    texts[id as usize]
+                        .as_deref()
+                        .is_some_and(|t| matcher.accepts(t))
+                };
+                if ok {
+                    masked[id as usize] = values[id as usize];
+                    allowed += 1;
+                    if allowed == JSON_CANDIDATES {
+                        break;
+                    }
+                }
+            }
+            if allowed == 0 {
+                return Err(SuprascalarError::Unknown(
+                    "No token can continue output that matches the JSON schema".to_string(),
+                ));
+            }
+
+            let masked = Tensor::new(masked, logits.device())?;
+            let next_token = self.sample(&masked, false)?;
+            if self.is_eos(next_token) {
                 break;
             }
+
+            let text = texts[next_token as usize].as_deref().unwrap_or_default();
+            matcher.feed_str(text);
+            result.push_str(text);
+            completion_tokens += 1;
+            if matcher.is_complete() {
+                break;
+            }
+
             let (_b, seq_len) = input.dims2()?;
             pos += seq_len;
             input = Tensor::new(&[next_token], &self.device)?.unsqueeze(0)?;
         }
 
-        let result = self
-            .tokenizer
-            .decode(&generated_tokens, true)
-            .map_err(|e| SuprascalarError::Tokenizer(e.to_string()))?;
+        self.last_usage = Some(Usage {
+            prompt_tokens: tokens.len(),
+            completion_tokens,
+        });
+        if !matcher.can_finish() {
+            return Err(SuprascalarError::Unknown(format!(
+                "JSON output was cut off after {} tokens before the value was complete",
+                completion_tokens
+            )));
+        }
 
         Ok(result)
     }
+
+    fn count_tokens(&self, text: &str) -> Result<usize> {
+        Ok(self.encode(text)?.len())
+    }
+
+    fn context_limit(&self) -> Option<usize> {
+        Some(MAX_CONTEXT)
+    }
+
+    fn last_usage(&self) -> Option<Usage> {
+        self.last_usage
+    }
+
+    fn set_thinking_config(&mut self, config: Option<GenerationConfig>) {
+        self.thinking = config.map(|config| ThinkingSampler {
+            logits_processor: LogitsProcessor::from_sampling(config.seed, config.sampling()),
+            config,
+        });
+    }
+
+    fn set_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.cancel = token;
+    }
+
+    fn set_token_callback(&mut self, callback: Option<TokenFn>) {
+        self.on_token = callback;
+    }
+
+    fn set_stop_sequences(&mut self, stops: Vec<String>) {
+        self.stop_sequences = stops;
+    }
 }
@@ -0,0 +1,144 @@
+use crate::candle_transformers_patched::quantized_qwen3::ModelWeights as Qwen3;
+use crate::candle_transformers_patched::quantized_qwen3_moe::GGUFQWenMoE as Qwen3Moe;
+use crate::error::{Result, SuprascalarError};
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::models::quantized_qwen2::ModelWeights as Qwen2;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+/// GGUF 메타데이터의 `general.architecture` 값 (예: "qwen2", "qwen3", "qwen3moe")
+pub fn gguf_architecture(content: &gguf_file::Content) -> Result<String> {
+    content
+        .metadata
+        .get("general.architecture")
+        .and_then(|v| v.to_string().ok())
+        .cloned()
+        .ok_or_else(|| {
+            SuprascalarError::UnsupportedArchitecture(
+                "missing general.architecture in GGUF metadata".to_string(),
+            )
+        })
+}
+
+/// GGUF `general.architecture`에 따라 고른 양자화 Qwen 모델
+/// `CandleQwen` 백엔드와 speculative decoding 예제처럼 forward를 직접 다루는 코드가 함께 사용합니다.
+pub enum QuantizedModel {
+    Qwen2(Qwen2),
+    Qwen3(Qwen3),
+    Moe(Qwen3Moe),
+}
+
+impl QuantizedModel {
+    /// GGUF 파일을 열어 아키텍처에 맞는 모델을 로드합니다.
+    pub fn load(path: &Path, device: &Device) -> Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let content = gguf_file::Content::read(&mut file)?;
+        Self::from_gguf(content, &mut file, device)
+    }
+
+    /// 이미 읽은 GGUF 메타데이터(`content`)와 텐서를 읽을 `reader`로 로드합니다.
+    pub fn from_gguf<R: Read + Seek>(
+        content: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        // Qwen2 파일을 Qwen3로 읽으면 에러 없이 엉뚱한 출력이 나오므로 메타데이터로만 결정
+        match gguf_architecture(&content)?.as_str() {
+            "qwen2" => Ok(Self::Qwen2(Qwen2::from_gguf(content, reader, device)?)),
+            "qwen3" => Ok(Self::Qwen3(Qwen3::from_gguf(content, reader, device)?)),
+            "qwen3moe" => {
+                // CPU에서는 BF16 matmul이 느리므로 F32로 attention 계산
+                let dtype = if device.is_cpu() {
+                    DType::F32
+                } else {
+                    DType::BF16
+                };
+                Ok(Self::Moe(Qwen3Moe::from_gguf(
+                    content, reader, device, dtype,
+                )?))
+            }
+            other => Err(SuprascalarError::UnsupportedArchitecture(format!(
+                "{} (expected qwen2, qwen3 or qwen3moe)",
+                other
+            ))),
+        }
+    }
+
+    /// GGUF `general.architecture` 이름
+    pub fn architecture(&self) -> &'static str {
+        match self {
+            Self::Qwen2(_) => "qwen2",
+            Self::Qwen3(_) => "qwen3",
+            Self::Moe(_) => "qwen3moe",
+        }
+    }
+
+    /// `input`([batch, seq])을 KV 캐시의 `offset` 위치에 이어서 forward 하고 마지막 위치의 logits를 반환합니다.
+    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        Ok(match self {
+            Self::Qwen2(m) => m.forward(input, offset)?,
+            Self::Qwen3(m) => m.forward(input, offset)?,
+            Self::Moe(m) => m.forward(input, offset)?,
+        })
+    }
+
+    /// 좌측 패딩된 배치 forward (행마다 `pad_lens`개의 앞 토큰을 마스킹). Qwen3 dense만 지원합니다.
+    pub fn forward_padded(
+        &mut self,
+        input: &Tensor,
+        offset: usize,
+        pad_lens: &[usize],
+    ) -> Result<Tensor> {
+        match self {
+            Self::Qwen3(m) => Ok(m.forward_padded(input, offset, pad_lens)?),
+            Self::Qwen2(_) => Err(SuprascalarError::Unsupported(
+                "padded batch forward for qwen2".to_string(),
+            )),
+            Self::Moe(_) => Err(SuprascalarError::Unsupported(
+                "padded batch forward for qwen3moe".to_string(),
+            )),
+        }
+    }
+
+    /// `forward_padded`를 쓸 수 있는지
+    pub fn supports_padded_batch(&self) -> bool {
+        matches!(self, Self::Qwen3(_))
+    }
+
+    pub fn clear_kv_cache(&mut self) {
+        match self {
+            // quantized_qwen2는 offset 0으로 forward 하면 캐시를 새로 시작합니다.
+            Self::Qwen2(_) => {}
+            Self::Qwen3(m) => m.clear_kv_cache(),
+            Self::Moe(m) => m.clear_kv_cache(),
+        }
+    }
+
+    /// KV 캐시를 앞의 `len`개 위치만 남기고 잘라냅니다.
+    /// 캐시는 forward 할 때마다 덧붙기만 하므로, 같은 offset으로 다시 forward 하려면 먼저 잘라야 합니다.
+    /// qwen2는 캐시에 접근할 수 없어 지원하지 않습니다 (`supports_kv_truncate`).
+    pub fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        match self {
+            Self::Qwen2(_) => Err(SuprascalarError::Unsupported(
+                "KV cache truncation for qwen2".to_string(),
+            )),
+            Self::Qwen3(m) => Ok(m.truncate_kv_cache(len)?),
+            Self::Moe(m) => Ok(m.truncate_kv_cache(len)?),
+        }
+    }
+
+    /// `truncate_kv_cache`를 쓸 수 있는지
+    pub fn supports_kv_truncate(&self) -> bool {
+        !matches!(self, Self::Qwen2(_))
+    }
+
+    /// KV 캐시에 들어 있는 위치 수 (qwen2는 알 수 없어 `None`)
+    pub fn kv_cache_len(&self) -> Option<usize> {
+        match self {
+            Self::Qwen2(_) => None,
+            Self::Qwen3(m) => Some(m.kv_cache_len()),
+            Self::Moe(m) => Some(m.kv_cache_len()),
+        }
+    }
+}
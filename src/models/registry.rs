@@ -0,0 +1,114 @@
+use super::LLMBackend;
+use super::qqwen3::CandleQwen;
+use crate::error::{Result, SuprascalarError};
+use std::collections::BTreeMap;
+
+/// 이름 하나로 모델을 로드하는 데 필요한 정보
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelSpec {
+    /// GGUF 파일이 있는 HF repo
+    pub repo: String,
+    pub file: String,
+    /// `tokenizer.json`을 가져올 HF repo (보통 원본 모델 repo)
+    pub tokenizer_repo: String,
+    /// GGUF `general.architecture` 값 (예: "qwen2", "qwen3", "qwen3moe")
+    pub arch: String,
+}
+
+impl ModelSpec {
+    pub fn new(
+        repo: impl Into<String>,
+        file: impl Into<String>,
+        tokenizer_repo: impl Into<String>,
+        arch: impl Into<String>,
+    ) -> Self {
+        Self {
+            repo: repo.into(),
+            file: file.into(),
+            tokenizer_repo: tokenizer_repo.into(),
+            arch: arch.into(),
+        }
+    }
+}
+
+/// 짧은 이름(예: "qwen3-14b") → `ModelSpec` 매핑
+/// 기본값에는 예제에서 쓰는 모델들이 등록되어 있고, `with_model`로 추가/덮어쓸 수 있습니다.
+#[derive(Clone, Debug)]
+pub struct ModelRegistry {
+    models: BTreeMap<String, ModelSpec>,
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self::empty()
+            .with_model(
+                "qwen3-0.6b",
+                ModelSpec::new(
+                    "unsloth/Qwen3-0.6B-GGUF",
+                    "Qwen3-0.6B-Q4_K_M.gguf",
+                    "Qwen/Qwen3-0.6B",
+                    "qwen3",
+                ),
+            )
+            .with_model(
+                "qwen3-14b",
+                ModelSpec::new(
+                    "unsloth/Qwen3-14B-GGUF",
+                    "Qwen3-14B-Q4_K_M.gguf",
+                    "Qwen/Qwen3-14B",
+                    "qwen3",
+                ),
+            )
+            .with_model(
+                "qwen3-30b-a3b",
+                ModelSpec::new(
+                    "unsloth/Qwen3-30B-A3B-Instruct-2507-GGUF",
+                    "Qwen3-30B-A3B-Instruct-2507-Q4_K_M.gguf",
+                    "Qwen/Qwen3-30B-A3B-Instruct-2507",
+                    "qwen3moe",
+                ),
+            )
+    }
+}
+
+impl ModelRegistry {
+    /// 기본 모델들이 등록된 레지스트리
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 아무것도 등록되지 않은 레지스트리
+    pub fn empty() -> Self {
+        Self {
+            models: BTreeMap::new(),
+        }
+    }
+
+    /// 모델을 등록합니다. 같은 이름이 있으면 덮어씁니다.
+    pub fn with_model(mut self, name: impl Into<String>, spec: ModelSpec) -> Self {
+        self.models.insert(name.into(), spec);
+        self
+    }
+
+    /// 등록된 이름 목록 (정렬됨)
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.models.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&ModelSpec> {
+        self.models
+            .get(name)
+            .ok_or_else(|| SuprascalarError::ModelNotFound {
+                name: name.to_string(),
+            })
+    }
+
+    /// 이름으로 모델을 찾아 아키텍처에 맞는 백엔드를 로드합니다.
+    pub fn load(&self, name: &str) -> Result<Box<dyn LLMBackend>> {
+        let spec = self.get(name)?;
+        match spec.arch.as_str() {
+            "qwen2" | "qwen3" | "qwen3moe" => Ok(Box::new(CandleQwen::from_spec(spec)?)),
+            other => Err(SuprascalarError::UnsupportedArchitecture(other.to_string())),
+        }
+    }
+}
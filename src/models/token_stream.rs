@@ -0,0 +1,70 @@
+use crate::error::{Result, SuprascalarError};
+use tokenizers::Tokenizer;
+
+/// 토큰을 하나씩 받아 새로 확정된 텍스트(delta)만 돌려주는 증분 디코더
+///
+/// 매 스텝 전체 시퀀스를 다시 디코딩하지 않고, 마지막으로 내보낸 구간부터만 디코딩합니다.
+/// 여러 토큰에 걸친 문자(한글, 이모지 등)는 완성될 때까지 내보내지 않습니다.
+#[derive(Clone, Debug, Default)]
+pub struct TokenStreamDecoder {
+    tokens: Vec<u32>,
+    // 직전에 내보낸 구간의 시작/끝 (tokens 인덱스)
+    prev_index: usize,
+    current_index: usize,
+}
+
+impl TokenStreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 토큰을 추가하고, 새로 확정된 텍스트가 있으면 반환합니다.
+    pub fn push(&mut self, tokenizer: &Tokenizer, token: u32) -> Result<Option<String>> {
+        let prev_text = decode(tokenizer, &self.tokens[self.prev_index..self.current_index])?;
+        self.tokens.push(token);
+        let text = decode(tokenizer, &self.tokens[self.prev_index..])?;
+
+        // U+FFFD로 끝나면 아직 UTF-8 바이트가 덜 모인 상태
+        if text.len() <= prev_text.len() || text.ends_with('\u{FFFD}') {
+            return Ok(None);
+        }
+        let Some(delta) = text.get(prev_text.len()..) else {
+            return Ok(None);
+        };
+
+        let delta = delta.to_string();
+        self.prev_index = self.current_index;
+        self.current_index = self.tokens.len();
+        Ok(Some(delta))
+    }
+
+    /// 아직 내보내지 않은 나머지 텍스트를 반환합니다 (생성 종료 시 호출).
+    pub fn flush(&self, tokenizer: &Tokenizer) -> Result<Option<String>> {
+        let prev_text = decode(tokenizer, &self.tokens[self.prev_index..self.current_index])?;
+        let text = decode(tokenizer, &self.tokens[self.prev_index..])?;
+        Ok(text
+            .get(prev_text.len()..)
+            .filter(|rest| !rest.is_empty())
+            .map(str::to_string))
+    }
+
+    /// 지금까지 받은 모든 토큰
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+        self.prev_index = 0;
+        self.current_index = 0;
+    }
+}
+
+fn decode(tokenizer: &Tokenizer, tokens: &[u32]) -> Result<String> {
+    if tokens.is_empty() {
+        return Ok(String::new());
+    }
+    tokenizer
+        .decode(tokens, true)
+        .map_err(|e| SuprascalarError::Tokenizer(e.to_string()))
+}
@@ -0,0 +1,324 @@
+use super::sandbox::Sandbox;
+use super::{OutputBudget, Tool, parse_args};
+use crate::error::{Result, SuprascalarError};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// 기본으로 돌려주는 진단 개수 상한
+const DEFAULT_MAX_DIAGNOSTICS: usize = 20;
+
+/// cargo를 `--message-format=json`으로 실행하고 컴파일러 진단을 요약해서 돌려주는 도구
+/// 원본 빌드 로그 대신 `file:line:col: message` 형태의 짧은 목록만 모델에게 전달합니다.
+pub struct CargoTool {
+    sandbox: Sandbox,
+    max_diagnostics: usize,
+    // `test` 실행 결과(테스트 바이너리 출력)에 적용할 출력 제한
+    output_budget: OutputBudget,
+}
+
+#[derive(Deserialize)]
+struct CargoArgs {
+    #[serde(default = "default_command")]
+    command: String,
+    // Cargo.toml이 있는 디렉토리 (샌드박스 루트 기준)
+    #[serde(default = "default_path")]
+    path: String,
+}
+
+fn default_command() -> String {
+    "check".to_string()
+}
+
+fn default_path() -> String {
+    ".".to_string()
+}
+
+/// 컴파일러 진단 하나 (primary span 기준)
+#[derive(Debug)]
+struct Diagnostic {
+    level: String,
+    code: Option<String>,
+    location: Option<String>,
+    message: String,
+}
+
+impl Diagnostic {
+    /// `compiler-message` JSON 한 줄에서 진단을 추출합니다.
+    /// "aborting due to ..." 같은 위치 없는 요약 메시지는 건너뜁니다.
+    fn from_message(msg: &Value) -> Option<Self> {
+        let level = msg.get("level")?.as_str()?;
+        if level != "error" && level != "warning" {
+            return None;
+        }
+        let spans = msg.get("spans")?.as_array()?;
+        if spans.is_empty() {
+            return None;
+        }
+
+        let location = spans
+            .iter()
+            .find(|s| s.get("is_primary").and_then(Value::as_bool) == Some(true))
+            .or_else(|| spans.first())
+            .map(|s| {
+                format!(
+                    "{}:{}:{}",
+                    s.get("file_name").and_then(Value::as_str).unwrap_or("?"),
+                    s.get("line_start").and_then(Value::as_u64).unwrap_or(0),
+                    s.get("column_start").and_then(Value::as_u64).unwrap_or(0)
+                )
+            });
+
+        Some(Self {
+            level: level.to_string(),
+            code: msg
+                .get("code")
+                .and_then(|c| c.get("code"))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            location,
+            message: msg.get("message")?.as_str()?.to_string(),
+        })
+    }
+
+    fn render(&self) -> String {
+        let head = match &self.code {
+            Some(code) => format!("{}[{}]", self.level, code),
+            None => self.level.clone(),
+        };
+        match &self.location {
+            Some(location) => format!("{} {}: {}", head, location, self.message),
+            None => format!("{}: {}", head, self.message),
+        }
+    }
+}
+
+/// `--message-format=json` 출력에서 (중복을 제거한 진단, JSON이 아닌 줄)을 분리합니다.
+/// JSON이 아닌 줄은 테스트 바이너리의 출력입니다.
+fn parse_cargo_output(stdout: &str) -> (Vec<Diagnostic>, String) {
+    let mut diagnostics = Vec::new();
+    // lib/test 타깃에서 같은 진단이 반복되므로 중복 제거
+    let mut seen = HashSet::new();
+    let mut test_output = String::new();
+
+    for line in stdout.lines() {
+        let Ok(msg) = serde_json::from_str::<Value>(line) else {
+            test_output.push_str(line);
+            test_output.push('\n');
+            continue;
+        };
+        if msg.get("reason").and_then(Value::as_str) != Some("compiler-message") {
+            continue;
+        }
+        if let Some(d) = msg.get("message").and_then(Diagnostic::from_message)
+            && seen.insert(d.render())
+        {
+            diagnostics.push(d);
+        }
+    }
+    (diagnostics, test_output)
+}
+
+impl Default for CargoTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CargoTool {
+    pub fn new() -> Self {
+        Self {
+            sandbox: Sandbox::new(),
+            max_diagnostics: DEFAULT_MAX_DIAGNOSTICS,
+            output_budget: OutputBudget::default(),
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다. `path` 인자는 이 루트 안쪽이어야 합니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `FileIO`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// 돌려줄 진단의 최대 개수 (에러가 경고보다 먼저 채워짐)
+    pub fn with_max_diagnostics(mut self, max: usize) -> Self {
+        self.max_diagnostics = max;
+        self
+    }
+
+    /// `test` 실행 결과에 적용할 출력 제한 설정
+    pub fn with_output_budget(mut self, budget: OutputBudget) -> Self {
+        self.output_budget = budget;
+        self
+    }
+
+    /// 진단 목록을 요약 텍스트로 만듭니다.
+    fn summarize(&self, command: &str, success: bool, diagnostics: Vec<Diagnostic>) -> String {
+        let (errors, warnings): (Vec<_>, Vec<_>) =
+            diagnostics.into_iter().partition(|d| d.level == "error");
+
+        let mut out = format!(
+            "cargo {}: {} ({} errors, {} warnings)\n",
+            command,
+            if success { "OK" } else { "FAILED" },
+            errors.len(),
+            warnings.len()
+        );
+
+        let total = errors.len() + warnings.len();
+        for d in errors
+            .iter()
+            .chain(warnings.iter())
+            .take(self.max_diagnostics)
+        {
+            out.push_str(&d.render());
+            out.push('\n');
+        }
+        if total > self.max_diagnostics {
+            out.push_str(&format!(
+                "... and {} more diagnostics\n",
+                total - self.max_diagnostics
+            ));
+        }
+        out
+    }
+}
+
+impl Tool for CargoTool {
+    fn name(&self) -> &str {
+        "cargo"
+    }
+
+    fn description(&self) -> &str {
+        "Runs cargo (check, build, test or clippy) on a Rust project and returns a compact \
+        list of compiler errors and warnings with file:line:column. \
+        Prefer this over running cargo in the shell."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "enum": ["check", "build", "test", "clippy"],
+                    "description": "The cargo subcommand to run (default: check)"
+                },
+                "path": {
+                    "type": "string",
+                    "description": "Directory containing Cargo.toml (default: '.')"
+                }
+            },
+            "required": ["command"]
+        })
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        let args: CargoArgs = parse_args(args)?;
+        let command = args.command.as_str();
+        if !matches!(command, "check" | "build" | "test" | "clippy") {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "Unsupported cargo command '{}'. Use check, build, test or clippy.",
+                command
+            )));
+        }
+
+        // [Security] 작업 디렉토리는 샌드박스 안쪽으로 제한
+        let dir = self.sandbox.validate_path(self.name(), &args.path)?;
+        if !dir.join("Cargo.toml").exists() {
+            return Ok(format!("Error: No Cargo.toml found in '{}'.", args.path));
+        }
+
+        let output = Command::new("cargo")
+            .arg(command)
+            .arg("--message-format=json")
+            .current_dir(&dir)
+            .output()
+            .map_err(|e| SuprascalarError::ToolExecution {
+                tool: self.name().to_string(),
+                message: format!("Failed to run cargo: {}", e),
+            })?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (diagnostics, test_output) = parse_cargo_output(&stdout);
+
+        let no_diagnostics = diagnostics.is_empty();
+        let mut result = self.summarize(command, output.status.success(), diagnostics);
+
+        if command == "test" && !test_output.trim().is_empty() {
+            result.push_str("\nTest output:\n");
+            result.push_str(&self.output_budget.truncate(test_output));
+        } else if !output.status.success() && no_diagnostics {
+            // 진단 없이 실패한 경우 (예: 매니페스트 오류) stderr를 그대로 보여줌
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            result.push_str(&self.output_budget.truncate(stderr));
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `cargo check --message-format=json`에서 기록한 줄 (rendered 등 일부 필드 생략)
+    const UNUSED_VARIABLE: &str = r#"{"reason":"compiler-message","package_id":"demo 0.1.0","target":{"kind":["lib"],"name":"demo"},"message":{"message":"unused variable: `x`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"src/lib.rs","line_start":2,"line_end":2,"column_start":9,"column_end":10,"is_primary":true,"label":"help: if this is intentional, prefix it with an underscore: `_x`"}],"children":[]}}"#;
+    const MISMATCHED_TYPES: &str = r#"{"reason":"compiler-message","package_id":"demo 0.1.0","target":{"kind":["lib"],"name":"demo"},"message":{"message":"mismatched types","code":{"code":"E0308","explanation":"..."},"level":"error","spans":[{"file_name":"src/lib.rs","line_start":1,"line_end":1,"column_start":20,"column_end":23,"is_primary":false,"label":"expected `u32` because of return type"},{"file_name":"src/lib.rs","line_start":3,"line_end":3,"column_start":5,"column_end":12,"is_primary":true,"label":"expected `u32`, found `&str`"}],"children":[]}}"#;
+    const ABORTING: &str = r#"{"reason":"compiler-message","package_id":"demo 0.1.0","target":{"kind":["lib"],"name":"demo"},"message":{"message":"aborting due to 1 previous error","code":null,"level":"error","spans":[],"children":[]}}"#;
+    const ARTIFACT: &str = r#"{"reason":"build-finished","success":false}"#;
+
+    #[test]
+    fn extracts_diagnostics_at_primary_span() {
+        let stdout = [UNUSED_VARIABLE, MISMATCHED_TYPES, ABORTING, ARTIFACT].join("\n");
+        let (diagnostics, test_output) = parse_cargo_output(&stdout);
+        let rendered: Vec<String> = diagnostics.iter().map(Diagnostic::render).collect();
+        assert_eq!(
+            rendered,
+            vec![
+                "warning[unused_variables] src/lib.rs:2:9: unused variable: `x`",
+                "error[E0308] src/lib.rs:3:5: mismatched types",
+            ]
+        );
+        assert!(test_output.is_empty());
+    }
+
+    #[test]
+    fn removes_duplicates_and_keeps_test_output() {
+        // lib와 test 타깃에서 같은 경고가 두 번 나옴
+        let stdout = [
+            UNUSED_VARIABLE,
+            UNUSED_VARIABLE,
+            "running 1 test",
+            "test it_works ... ok",
+        ]
+        .join("\n");
+        let (diagnostics, test_output) = parse_cargo_output(&stdout);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(test_output, "running 1 test\ntest it_works ... ok\n");
+    }
+
+    #[test]
+    fn summary_lists_errors_first_and_caps_the_rest() {
+        let stdout = [UNUSED_VARIABLE, MISMATCHED_TYPES].join("\n");
+        let (diagnostics, _) = parse_cargo_output(&stdout);
+        let summary =
+            CargoTool::new()
+                .with_max_diagnostics(1)
+                .summarize("check", false, diagnostics);
+        assert_eq!(
+            summary,
+            "cargo check: FAILED (1 errors, 1 warnings)\n\
+             error[E0308] src/lib.rs:3:5: mismatched types\n\
+             ... and 1 more diagnostics\n"
+        );
+    }
+}
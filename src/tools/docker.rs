@@ -1,22 +1,39 @@
-use super::Tool;
+use super::snapshot::GitSnapshot;
+use super::terminal::TerminalSession;
+use super::{OutputBudget, Tool, ToolOutputSink, parse_args};
 use crate::error::{Result, SuprascalarError};
+use crate::util::CancellationToken;
 use bollard::Docker;
 use bollard::exec::{CreateExecOptions, StartExecOptions, StartExecResults};
 use bollard::models::ContainerCreateBody;
 use bollard::query_parameters::{
-    CreateContainerOptions, CreateImageOptions, KillContainerOptions, StartContainerOptions,
-    StopContainerOptionsBuilder,
+    CreateContainerOptions, CreateImageOptions, InspectContainerOptions, KillContainerOptions,
+    StartContainerOptions, StopContainerOptionsBuilder,
 };
 use bollard::service::HostConfig;
 use futures_util::StreamExt;
 use regex::Regex;
+use serde::Deserialize;
 use serde_json::{Value, json};
 use std::env;
 use std::path::PathBuf;
-use std::process::Command;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::runtime::Runtime;
-use tokio::time::{Duration, timeout};
+use tokio::time::{Duration, sleep, timeout};
+
+/// Docker 상태 메시지(이미지 pull 진행, 준비/종료 알림, 실시간 명령 출력)를 내보내는 방식
+/// 모든 도구가 공유하는 `ToolOutputSink`의 이전 이름입니다.
+pub type DockerLog = ToolOutputSink;
+
+/// 사용자 명령 하나의 실행 시간 제한
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 준비 명령 하나의 실행 시간 제한 (패키지 설치는 오래 걸릴 수 있음)
+const SETUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// 준비 명령의 종료 코드를 출력에서 찾기 위한 마커
+const EXIT_MARKER: &str = "___SUPRA_EXIT";
 
 /// Docker 샌드박스 도구 (Optimized)
 pub struct DockerShell {
@@ -24,17 +41,48 @@ pub struct DockerShell {
     docker: Docker,
     container_id: String,
     cwd: Mutex<PathBuf>,
+    // reset_cwd()가 돌아갈 시작 디렉토리
+    initial_cwd: PathBuf,
     // 안전 장치 활성화 플래그
     safety_enabled: bool,
+    // 직접 생성한 컨테이너인지 여부 (attach한 컨테이너는 Drop 시 정리하지 않음)
+    owns_container: bool,
+    output_budget: OutputBudget,
+    log: ToolOutputSink,
+    // 취소되면 실행 중인 exec 프로세스를 종료하고 `Cancelled`를 반환
+    cancel: Option<CancellationToken>,
+    // exec마다 고유한 pid 파일 이름을 만들기 위한 카운터
+    exec_seq: AtomicU64,
+    snapshot: GitSnapshot,
+    // 첫 명령 전에 한 번 실행할 준비 명령 (패키지 설치 등)
+    setup_commands: Vec<String>,
+    // 준비 명령을 모두 성공적으로 실행했는지 여부 (실행 중에는 lock을 잡아 다른 호출이 기다림)
+    setup_done: Mutex<bool>,
+}
+
+/// exec가 완료되기 전에 중단된 이유
+enum ExecAbort {
+    Timeout,
+    Cancelled,
+}
+
+#[derive(Deserialize)]
+struct ShellArgs {
+    command: String,
 }
 
 impl DockerShell {
     pub fn new() -> Result<Self> {
+        Self::new_with_log(ToolOutputSink::default())
+    }
+
+    /// 상태 메시지 출력 방식을 지정해 새 샌드박스 컨테이너를 만듭니다.
+    pub fn new_with_log(log: ToolOutputSink) -> Result<Self> {
         let runtime = Runtime::new().map_err(|e| SuprascalarError::Unknown(e.to_string()))?;
 
         // 1. Docker 데몬 연결
         let docker = Docker::connect_with_local_defaults()
-            .map_err(|e| SuprascalarError::Unknown(format!("Docker connect failed: {}", e)))?;
+            .map_err(|e| SuprascalarError::Docker(format!("Docker connect failed: {}", e)))?;
 
         // 2. 호스트 경로 바인딩 준비
         let host_cwd = env::current_dir().map_err(SuprascalarError::Io)?;
@@ -66,10 +114,10 @@ impl DockerShell {
             .block_on(async {
                 // 이미지 존재 여부 체크 후 필요 시 pull
                 if let Err(_) = docker.inspect_image(image_name).await {
-                    eprintln!(
+                    log.line(&format!(
                         ">> [Docker] Image '{}' not found locally. Pulling...",
                         image_name
-                    );
+                    ));
                     let mut stream = docker.create_image(
                         Some(CreateImageOptions {
                             from_image: Some(String::from(image_name)),
@@ -83,7 +131,7 @@ impl DockerShell {
                         match progress {
                             Ok(status) => {
                                 if let Some(detail) = status.status {
-                                    println!(">> [Docker] {}", detail);
+                                    log.line(&format!(">> [Docker] {}", detail));
                                 }
                             }
                             Err(e) => {
@@ -109,23 +157,353 @@ impl DockerShell {
                 Ok::<String, bollard::errors::Error>(id)
             })
             .map_err(|e| {
-                SuprascalarError::Unknown(format!(
+                SuprascalarError::Docker(format!(
                     "Failed to start Docker sandbox: {}. (Try 'docker pull {}')",
                     e, image_name
                 ))
             })?;
 
-        println!(
+        log.line(&format!(
             ">> [Docker] Sandbox Ready (Limit: 512MB). ID: {:.8}",
             container_id
-        );
+        ));
 
         Ok(Self {
             runtime,
             docker,
             container_id,
             cwd: Mutex::new(PathBuf::from("/workspace")),
+            initial_cwd: PathBuf::from("/workspace"),
             safety_enabled: true,
+            owns_container: true,
+            output_budget: OutputBudget::default(),
+            log,
+            cancel: None,
+            exec_seq: AtomicU64::new(0),
+            snapshot: GitSnapshot::default(),
+            setup_commands: Vec::new(),
+            setup_done: Mutex::new(false),
+        })
+    }
+
+    /// Docker 데몬에 연결 가능한지 확인합니다 (ping, 최대 3초).
+    /// 에이전트 구성 시점에 Docker/호스트 셸 중 하나를 고르는 용도입니다.
+    pub fn is_available() -> bool {
+        let Ok(runtime) = Runtime::new() else {
+            return false;
+        };
+        let Ok(docker) = Docker::connect_with_local_defaults() else {
+            return false;
+        };
+        runtime.block_on(async {
+            matches!(
+                timeout(Duration::from_secs(3), docker.ping()).await,
+                Ok(Ok(_))
+            )
+        })
+    }
+
+    /// Docker 샌드박스를 생성하되, 데몬이 없으면 호스트 `TerminalSession`으로 대체합니다.
+    /// 대체된 셸은 격리 없이 호스트에서 명령을 실행하므로 `tracing` 경고를 남깁니다.
+    /// (데몬은 있지만 컨테이너 생성에 실패한 경우에는 에러를 그대로 반환)
+    pub fn new_or_host() -> Result<Box<dyn Tool>> {
+        if Self::is_available() {
+            return Ok(Box::new(Self::new()?));
+        }
+
+        tracing::warn!(
+            "Docker daemon is not available; falling back to the HOST shell (TerminalSession). \
+             Commands will run WITHOUT container isolation!"
+        );
+        Ok(Box::new(TerminalSession::new()))
+    }
+
+    /// 이미 실행 중인 컨테이너에 연결합니다.
+    /// 컨테이너를 새로 만들지 않으며, Drop 시에도 컨테이너를 중지하지 않으므로
+    /// 설치한 패키지와 상태가 프로세스 재시작 이후에도 유지됩니다.
+    pub fn attach(container_name: &str) -> Result<Self> {
+        Self::attach_with_log(container_name, ToolOutputSink::default())
+    }
+
+    /// 상태 메시지 출력 방식을 지정해 기존 컨테이너에 연결합니다.
+    pub fn attach_with_log(container_name: &str, log: ToolOutputSink) -> Result<Self> {
+        let runtime = Runtime::new().map_err(|e| SuprascalarError::Unknown(e.to_string()))?;
+
+        let docker = Docker::connect_with_local_defaults()
+            .map_err(|e| SuprascalarError::Docker(format!("Docker connect failed: {}", e)))?;
+
+        let info = runtime
+            .block_on(docker.inspect_container(container_name, None::<InspectContainerOptions>))
+            .map_err(|e| {
+                SuprascalarError::Docker(format!(
+                    "Failed to inspect container '{}': {}",
+                    container_name, e
+                ))
+            })?;
+
+        let running = info
+            .state
+            .as_ref()
+            .and_then(|state| state.running)
+            .unwrap_or(false);
+        if !running {
+            return Err(SuprascalarError::Docker(format!(
+                "Container '{}' is not running. (Try 'docker start {}')",
+                container_name, container_name
+            )));
+        }
+
+        let container_id = info.id.unwrap_or_else(|| container_name.to_string());
+
+        // 컨테이너에 설정된 작업 디렉토리를 시작 위치로 사용 (없으면 /workspace)
+        let working_dir = info
+            .config
+            .and_then(|config| config.working_dir)
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| String::from("/workspace"));
+
+        log.line(&format!(
+            ">> [Docker] Attached to existing container '{}'. ID: {:.8}",
+            container_name, container_id
+        ));
+
+        Ok(Self {
+            runtime,
+            docker,
+            container_id,
+            cwd: Mutex::new(PathBuf::from(&working_dir)),
+            initial_cwd: PathBuf::from(working_dir),
+            safety_enabled: true,
+            owns_container: false,
+            output_budget: OutputBudget::default(),
+            log,
+            cancel: None,
+            exec_seq: AtomicU64::new(0),
+            snapshot: GitSnapshot::default(),
+            setup_commands: Vec::new(),
+            setup_done: Mutex::new(false),
+        })
+    }
+
+    /// LLM 컨텍스트 보호를 위한 출력 제한 설정
+    pub fn with_output_budget(mut self, budget: OutputBudget) -> Self {
+        self.output_budget = budget;
+        self
+    }
+
+    /// 실시간 명령 출력과 상태 메시지를 내보낼 곳 (생성 이후의 메시지부터 적용)
+    /// 생성 중 메시지(이미지 pull 등)까지 바꾸려면 `new_with_log`/`attach_with_log`를 사용하세요.
+    pub fn with_output_sink(mut self, sink: ToolOutputSink) -> Self {
+        self.log = sink;
+        self
+    }
+
+    /// 명령 실행 전 호스트 Git 스냅샷 설정 (기본: 켜짐, `GitSnapshot::disabled()`로 끔)
+    pub fn with_git_snapshot(mut self, snapshot: GitSnapshot) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// 컨테이너에서 첫 명령을 실행하기 전에 한 번만 실행할 준비 명령을 지정합니다.
+    /// 예: `["apt-get update", "apt-get install -y python3"]`
+    /// 설치 결과는 컨테이너가 살아 있는 동안 유지되므로 모델이 매번 설치할 필요가 없습니다.
+    /// 준비 명령은 안전 검사와 Git 스냅샷 없이 시작 디렉토리에서 순서대로 실행되며,
+    /// 하나라도 실패하면 에러를 반환하고 다음 호출에서 처음부터 다시 시도합니다.
+    pub fn with_setup_commands<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.setup_commands = commands.into_iter().map(Into::into).collect();
+        *self.setup_done.get_mut().unwrap_or_else(|e| e.into_inner()) = false;
+        self
+    }
+
+    /// 아직 실행하지 않은 준비 명령을 실행합니다.
+    /// 첫 도구 호출 때 자동으로 실행되지만, 에이전트 시작 전에 미리 호출해 설치 시간을 옮길 수 있습니다.
+    pub fn run_setup(&self) -> Result<()> {
+        let mut done = self.setup_done.lock().unwrap_or_else(|e| e.into_inner());
+        if *done || self.setup_commands.is_empty() {
+            return Ok(());
+        }
+
+        let working_dir = self.initial_cwd.to_string_lossy().to_string();
+        for command in &self.setup_commands {
+            self.log.line(&format!(">> [Docker] Setup: {}", command));
+            let script = format!("{}; echo \"{}:$?\"", command, EXIT_MARKER);
+            let output = self.exec(&script, &working_dir, SETUP_TIMEOUT)?;
+
+            let (body, status) = output
+                .trim_end()
+                .rsplit_once(&format!("{}:", EXIT_MARKER))
+                .unwrap_or((output.as_str(), ""));
+            let status = status.trim();
+            if status != "0" {
+                return Err(SuprascalarError::Docker(format!(
+                    "Setup command '{}' failed (exit {}): {}",
+                    command,
+                    if status.is_empty() { "unknown" } else { status },
+                    self.output_budget.truncate(body.trim_end().to_string())
+                )));
+            }
+        }
+
+        *done = true;
+        self.log.line(">> [Docker] Setup complete.");
+        Ok(())
+    }
+
+    /// 취소 토큰을 설정합니다. `Agent`와 같은 토큰을 넘기면 chat이 취소될 때
+    /// 컨테이너 안에서 실행 중인 명령도 함께 종료됩니다.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// 컨테이너 안의 현재 작업 디렉토리
+    pub fn current_dir(&self) -> PathBuf {
+        self.lock_cwd().clone()
+    }
+
+    /// 작업 디렉토리를 시작 위치(`/workspace`, attach한 경우 컨테이너의 작업 디렉토리)로 되돌립니다.
+    /// 새 작업을 시작하기 전에 호출하면 이전 작업의 `cd`에 영향을 받지 않습니다.
+    pub fn reset_cwd(&self) {
+        *self.lock_cwd() = self.initial_cwd.clone();
+    }
+
+    // 명령 실행 중 panic으로 lock이 poison되어도 경로 값 자체는 유효하므로 그대로 사용
+    fn lock_cwd(&self) -> std::sync::MutexGuard<'_, PathBuf> {
+        self.cwd.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 시간 초과/취소된 exec의 프로세스를 컨테이너 안에서 종료합니다.
+    /// Docker API에는 exec 단위 kill이 없으므로, 실행 시 기록한 셸 PID를 읽어
+    /// 프로세스 그룹 리더면 그룹 전체를, 아니면 셸과 직계 자식을 SIGKILL 합니다.
+    /// (셸을 먼저 죽여야 자식 종료 후 다음 명령으로 넘어가지 않으므로 한 번에 kill)
+    async fn kill_exec(&self, pid_file: &str) {
+        let script = format!(
+            "P=$(cat {f} 2>/dev/null) || exit 0; rm -f {f}; \
+             G=$(cut -d' ' -f5 /proc/$P/stat 2>/dev/null); \
+             if [ \"$G\" = \"$P\" ]; then kill -KILL -$P; else K=; \
+             for s in /proc/[0-9]*/stat; do read -r c _ _ pp _ < $s; \
+             [ \"$pp\" = \"$P\" ] && K=\"$K $c\"; done; kill -KILL $P $K; fi",
+            f = pid_file
+        );
+        let kill = async {
+            let exec_id = self
+                .docker
+                .create_exec(
+                    &self.container_id,
+                    CreateExecOptions {
+                        attach_stdout: Some(true),
+                        attach_stderr: Some(true),
+                        cmd: Some(vec!["/bin/sh", "-c", script.as_str()]),
+                        ..Default::default()
+                    },
+                )
+                .await?
+                .id;
+            if let StartExecResults::Attached { mut output, .. } = self
+                .docker
+                .start_exec(&exec_id, None::<StartExecOptions>)
+                .await?
+            {
+                while output.next().await.is_some() {}
+            }
+            Ok::<(), bollard::errors::Error>(())
+        };
+        if !matches!(timeout(Duration::from_secs(5), kill).await, Ok(Ok(()))) {
+            self.log
+                .line(">> [Docker] Failed to kill timed-out command inside the container.");
+        }
+    }
+
+    /// 취소 토큰이 설정되어 있으면 취소될 때까지 기다립니다 (없으면 영원히 대기).
+    async fn wait_cancelled(&self) {
+        match &self.cancel {
+            Some(token) => {
+                while !token.is_cancelled() {
+                    sleep(Duration::from_millis(100)).await;
+                }
+            }
+            None => std::future::pending::<()>().await,
+        }
+    }
+
+    /// 컨테이너 안에서 `script`를 실행하고 stdout/stderr를 합친 출력을 돌려줍니다.
+    /// 출력은 실시간으로 `log`에도 내보내며, `limit`을 넘기거나 취소되면 프로세스를 종료합니다.
+    fn exec(&self, script: &str, working_dir: &str, limit: Duration) -> Result<String> {
+        // 시간 초과/취소 시 프로세스를 종료할 수 있도록 셸 PID를 기록
+        let pid_file = format!(
+            "/tmp/.supra_exec_{}_{}.pid",
+            std::process::id(),
+            self.exec_seq.fetch_add(1, Ordering::Relaxed)
+        );
+        let injected_command = format!(
+            "echo $$ > {pid}; {script}; rm -f {pid}",
+            pid = pid_file,
+            script = script
+        );
+
+        self.runtime.block_on(async {
+            let execution_future = async {
+                let exec_config = CreateExecOptions {
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    working_dir: Some(working_dir),
+                    cmd: Some(vec!["/bin/sh", "-c", &injected_command]),
+                    ..Default::default()
+                };
+
+                let exec_id = self
+                    .docker
+                    .create_exec(&self.container_id, exec_config)
+                    .await?
+                    .id;
+
+                // Stream Start
+                let mut combined_output = String::new();
+                let stream = self
+                    .docker
+                    .start_exec(&exec_id, None::<StartExecOptions>)
+                    .await?;
+
+                match stream {
+                    StartExecResults::Attached { mut output, .. } => {
+                        while let Some(msg) = output.next().await {
+                            if let Ok(log) = msg {
+                                // [UX] 실시간 터미널 출력
+                                let chunk = log.to_string();
+                                self.log.chunk(&chunk);
+                                combined_output.push_str(&chunk);
+                            }
+                        }
+                    }
+                    StartExecResults::Detached => {
+                        combined_output.push_str("Exec started in detached mode")
+                    }
+                }
+                Ok::<String, bollard::errors::Error>(combined_output)
+            };
+            // [Time Limit] 시간 초과 또는 취소 중 먼저 오는 쪽으로 중단
+            let aborted = tokio::select! {
+                // 시간 내 완료됨 (Docker API 실패는 인프라 문제로 구분)
+                result = execution_future => {
+                    return result
+                        .map_err(|e| SuprascalarError::Docker(format!("Exec failed: {}", e)));
+                }
+                _ = sleep(limit) => ExecAbort::Timeout,
+                _ = self.wait_cancelled() => ExecAbort::Cancelled,
+            };
+
+            // 스트림만 버리면 컨테이너 안의 프로세스는 계속 돌기 때문에 직접 종료
+            self.kill_exec(&pid_file).await;
+            match aborted {
+                ExecAbort::Timeout => Err(SuprascalarError::CommandTimeout {
+                    seconds: limit.as_secs(),
+                }),
+                ExecAbort::Cancelled => Err(SuprascalarError::Cancelled),
+            }
         })
     }
 
@@ -155,10 +533,10 @@ impl DockerShell {
         for (pattern, reason) in dangerous_patterns {
             if let Ok(re) = Regex::new(pattern) {
                 if re.is_match(cmd) {
-                    return Err(SuprascalarError::Unknown(format!(
-                        "SECURITY BLOCK: Command '{}' blocked. Reason: {}",
-                        cmd, reason
-                    )));
+                    return Err(SuprascalarError::CommandBlocked {
+                        command: cmd.to_string(),
+                        reason: reason.to_string(),
+                    });
                 }
             }
         }
@@ -183,40 +561,22 @@ impl DockerShell {
             return;
         }
 
-        // 변경사항 확인
-        let status = Command::new("git")
-            .args(["status", "--porcelain"])
-            .current_dir(&host_cwd)
-            .output();
-
-        if let Ok(output) = status {
-            if !output.status.success() || output.stdout.is_empty() {
-                return; // 변경 없음
-            }
-
-            // Auto-Commit
-            let _ = Command::new("git")
-                .args(["add", "."])
-                .current_dir(&host_cwd)
-                .output();
-
-            let msg = format!(
-                "Suprascalar Auto-save: Before running '{}' in Docker",
-                cmd_context
-            );
-            let _ = Command::new("git")
-                .args(["commit", "-m", &msg])
-                .current_dir(&host_cwd)
-                .output();
-        }
+        self.snapshot
+            .take(&host_cwd, &format!("running '{}' in Docker", cmd_context));
     }
 }
 
 // 프로그램 종료 시 컨테이너 정리 (Cleanup)
 impl Drop for DockerShell {
     fn drop(&mut self) {
+        // attach로 연결한 컨테이너는 사용자 소유이므로 그대로 둔다
+        if !self.owns_container {
+            return;
+        }
+
         let container_id = self.container_id.clone();
-        println!(">> [Docker] Graceful shutdown initiated (Timeout: 3s)...");
+        self.log
+            .line(">> [Docker] Graceful shutdown initiated (Timeout: 3s)...");
         let _ = self.runtime.block_on(async {
             let options = StopContainerOptionsBuilder::new().t(3).build();
             match self
@@ -224,9 +584,10 @@ impl Drop for DockerShell {
                 .stop_container(&container_id, Some(options))
                 .await
             {
-                Ok(_) => println!(">> [Docker] Container stopped gracefully."),
+                Ok(_) => self.log.line(">> [Docker] Container stopped gracefully."),
                 Err(e) => {
-                    eprintln!(">> [Docker] Stop failed ({}). Forcing kill...", e);
+                    self.log
+                        .line(&format!(">> [Docker] Stop failed ({}). Forcing kill...", e));
                     let _ = self
                         .docker
                         .kill_container(&container_id, None::<KillContainerOptions>)
@@ -262,114 +623,52 @@ impl Tool for DockerShell {
     }
 
     fn execute(&self, args: Value) -> Result<String> {
-        let command_str = args["command"]
-            .as_str()
-            .ok_or_else(|| SuprascalarError::Unknown("Missing 'command' parameter".to_string()))?;
+        let ShellArgs { command } = parse_args(args)?;
+        let command_str = command.as_str();
 
         // 1. [Safety] 금지어 검사
         self.check_safety(command_str)?;
 
+        // 준비 명령(패키지 설치 등)은 컨테이너마다 한 번만
+        self.run_setup()?;
+
         // 2. [Safety] Git 스냅샷 (호스트에서 실행)
         // 파일 수정, 이동, 삭제 등이 포함될 수 있으므로 일단 모든 명령 전에 체크
         self.create_git_snapshot(command_str);
 
         // 3. 현재 Docker 내부 경로 가져오기
-        let current_cwd = self.cwd.lock().unwrap().to_string_lossy().to_string();
+        let current_cwd = self.lock_cwd().to_string_lossy().to_string();
 
         // 4. 명령어 주입 (Marker 전략)
         let marker = "___SUPRA_CWD";
-        let injected_command = format!("{}; echo \"{}:$(pwd)\"", command_str, marker);
-
-        let timeout_duration = Duration::from_secs(60);
+        let script = format!("{}; echo \"{}:$(pwd)\"", command_str, marker);
 
         // 5. Docker Exec 실행
-        let output_result = self.runtime.block_on(async {
-            let execution_future = async {
-                let exec_config = CreateExecOptions {
-                    attach_stdout: Some(true),
-                    attach_stderr: Some(true),
-                    working_dir: Some(current_cwd.as_str()),
-                    cmd: Some(vec!["/bin/sh", "-c", &injected_command]),
-                    ..Default::default()
-                };
-
-                let exec_id = self
-                    .docker
-                    .create_exec(&self.container_id, exec_config)
-                    .await?
-                    .id;
-
-                // Stream Start
-                let mut combined_output = String::new();
-                let stream = self
-                    .docker
-                    .start_exec(&exec_id, None::<StartExecOptions>)
-                    .await?;
-
-                match stream {
-                    StartExecResults::Attached { mut output, .. } => {
-                        while let Some(msg) = output.next().await {
-                            if let Ok(log) = msg {
-                                // [UX] 실시간 터미널 출력
-                                print!("{}", log);
-                                combined_output.push_str(&log.to_string());
-                            }
-                        }
-                    }
-                    StartExecResults::Detached => {
-                        combined_output.push_str("Exec started in detached mode")
-                    }
-                }
-                Ok::<String, bollard::errors::Error>(combined_output)
-            };
-            // [Time Limit] 비동기 작업에 타임아웃 걸기
-            match timeout(timeout_duration, execution_future).await {
-                Ok(result) => result, // 시간 내 완료됨
-                Err(_) => {
-                    // 시간 초과 발생!
-                    // 여기서 컨테이너 전체를 죽일 필요는 없고, 그냥 에러 메시지만 반환하면
-                    // 다음 턴에서 에이전트가 "아, 너무 오래 걸려서 실패했구나"라고 인지함.
-                    // (필요하다면 여기서 exec process를 kill 하는 로직을 추가할 수도 있음)
-                    Ok(format!(
-                        "Error: Command timed out after {} seconds.",
-                        timeout_duration.as_secs()
-                    ))
-                }
-            }
-        });
-
-        match output_result {
-            Ok(full_output) => {
-                // 6. 결과 파싱 및 상태 업데이트
-                let mut lines: Vec<&str> = full_output.lines().collect();
-                let mut final_output = full_output.clone();
-                let mut new_cwd_found = false;
-
-                if let Some(last_line) = lines.last() {
-                    if last_line.contains(marker) {
-                        if let Some(path_str) = last_line.strip_prefix(&format!("{}:", marker)) {
-                            let new_path = PathBuf::from(path_str.trim());
-                            *self.cwd.lock().unwrap() = new_path;
-                            new_cwd_found = true;
-                        }
-                    }
-                }
+        let full_output = self.exec(&script, &current_cwd, COMMAND_TIMEOUT)?;
+
+        // 6. 결과 파싱 및 상태 업데이트
+        let mut lines: Vec<&str> = full_output.lines().collect();
+        let mut final_output = full_output.clone();
+        let mut new_cwd_found = false;
+
+        if let Some(last_line) = lines.last()
+            && let Some(path_str) = last_line.strip_prefix(&format!("{}:", marker))
+        {
+            let new_path = PathBuf::from(path_str.trim());
+            *self.lock_cwd() = new_path;
+            new_cwd_found = true;
+        }
 
-                if new_cwd_found {
-                    lines.pop(); // 마커 라인 제거
-                    final_output = lines.join("\n");
-                }
+        if new_cwd_found {
+            lines.pop(); // 마커 라인 제거
+            final_output = lines.join("\n");
+        }
 
-                // 7. 출력 제한
-                if final_output.len() > 2000 {
-                    Ok(format!("{}\n... [Truncated] ...", &final_output[..2000]))
-                } else if final_output.trim().is_empty() {
-                    Ok("(Command executed successfully)".to_string())
-                } else {
-                    Ok(final_output)
-                }
-            }
-            Err(e) => Ok(format!("Docker Execution Failed: {}", e)),
+        // 7. 출력 제한
+        if final_output.trim().is_empty() {
+            Ok("(Command executed successfully)".to_string())
+        } else {
+            Ok(self.output_budget.truncate(final_output))
         }
     }
 }
@@ -0,0 +1,215 @@
+use super::Tool;
+use super::sandbox::Sandbox;
+use crate::error::Result;
+use serde_json::{Value, json};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// 버전까지 확인하는 툴체인 (`<name> --version`의 첫 줄)
+const TOOLCHAINS: &[&str] = &["cargo", "rustc", "python3", "pip3", "node", "npm", "gcc"];
+
+/// 있는지만 확인하는 패키지 매니저
+const PACKAGE_MANAGERS: &[&str] = &["apt-get", "dnf", "yum", "apk", "pacman", "zypper", "brew"];
+
+/// 있는지만 확인하는 기타 명령
+const OTHER_COMMANDS: &[&str] = &[
+    "git", "docker", "make", "cmake", "go", "java", "curl", "wget",
+];
+
+/// 버전 문자열에서 보여줄 최대 글자 수
+const MAX_VERSION_CHARS: usize = 100;
+
+/// 작업 환경(OS/아키텍처, 작업 디렉토리, Git 저장소 여부, 툴체인, 디스크 여유 공간)을 요약하는 읽기 전용 도구
+/// 모델이 행동하기 전에 환경을 파악해 없는 명령(예: apk 배포판에서 `apt-get`)을 쓰지 않게 합니다.
+/// 임의의 명령은 실행하지 않으며, 정해진 툴체인의 `--version`과 `df`만 호출합니다.
+pub struct EnvInfo {
+    sandbox: Sandbox,
+}
+
+impl Default for EnvInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnvInfo {
+    pub fn new() -> Self {
+        Self {
+            sandbox: Sandbox::new(),
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다. 작업 디렉토리로 보고됩니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `FileIO`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+}
+
+/// PATH에서 실행 파일을 찾습니다.
+fn which(name: &str) -> Option<PathBuf> {
+    let file = if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    };
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// `<path> --version` 출력의 첫 줄 (stdout이 비어 있으면 stderr, 예: 오래된 python)
+fn version(path: &Path) -> Option<String> {
+    let output = Command::new(path).arg("--version").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line = stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|l| !l.is_empty())?;
+    Some(line.chars().take(MAX_VERSION_CHARS).collect())
+}
+
+/// 배포판 이름 (`/etc/os-release`의 PRETTY_NAME, Linux 전용)
+fn distribution() -> Option<String> {
+    let content = fs::read_to_string("/etc/os-release").ok()?;
+    content.lines().find_map(|line| {
+        line.strip_prefix("PRETTY_NAME=")
+            .map(|v| v.trim_matches('"').to_string())
+    })
+}
+
+/// `dir`이 속한 Git 저장소의 루트와 현재 브랜치 (명령을 실행하지 않고 `.git`을 직접 읽음)
+fn git_repository(dir: &Path) -> Option<(PathBuf, Option<String>)> {
+    let root = dir.ancestors().find(|d| d.join(".git").exists())?;
+    // worktree/서브모듈은 `.git`이 파일이므로 브랜치는 생략
+    let branch = fs::read_to_string(root.join(".git").join("HEAD"))
+        .ok()
+        .and_then(|head| {
+            head.trim()
+                .strip_prefix("ref: refs/heads/")
+                .map(str::to_string)
+        });
+    Some((root.to_path_buf(), branch))
+}
+
+/// `df -Pk`로 (사용 가능, 전체) 바이트 수를 구합니다.
+#[cfg(unix)]
+fn disk_space(dir: &Path) -> Option<(u64, u64)> {
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // "Filesystem 1024-blocks Used Available Capacity Mounted on" 다음 줄
+    let fields: Vec<&str> = stdout.lines().nth(1)?.split_whitespace().collect();
+    let total: u64 = fields.get(1)?.parse().ok()?;
+    let available: u64 = fields.get(3)?.parse().ok()?;
+    Some((available * 1024, total * 1024))
+}
+
+#[cfg(not(unix))]
+fn disk_space(_dir: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+fn format_gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / 1e9)
+}
+
+impl Tool for EnvInfo {
+    fn name(&self) -> &str {
+        "env_info"
+    }
+
+    fn description(&self) -> &str {
+        "Describes the environment: OS and architecture, working directory, whether it is a git \
+        repository, installed toolchains with versions (cargo, rustc, python, node, ...), \
+        available package managers and free disk space. Call this before running commands that \
+        depend on the platform."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {}
+        })
+    }
+
+    // 인자가 없으므로 `{}`/null 등 무엇이 와도 무시
+    fn execute(&self, _args: Value) -> Result<String> {
+        let dir = self.sandbox.root()?;
+
+        let mut out = format!("OS: {} ({})", env::consts::OS, env::consts::ARCH);
+        if let Some(distro) = distribution() {
+            out.push_str(&format!(", {}", distro));
+        }
+        out.push('\n');
+
+        out.push_str(&format!("Working directory: {}\n", dir.display()));
+        match git_repository(&dir) {
+            Some((root, branch)) => {
+                out.push_str(&format!("Git repository: yes (root: {}", root.display()));
+                if let Some(branch) = branch {
+                    out.push_str(&format!(", branch: {}", branch));
+                }
+                out.push_str(")\n");
+            }
+            None => out.push_str("Git repository: no\n"),
+        }
+        if let Some((available, total)) = disk_space(&dir) {
+            out.push_str(&format!(
+                "Disk: {} available of {}\n",
+                format_gb(available),
+                format_gb(total)
+            ));
+        }
+
+        out.push_str("Toolchains:\n");
+        for name in TOOLCHAINS {
+            let status = match which(name) {
+                Some(path) => version(&path).unwrap_or_else(|| "installed".to_string()),
+                None => "not found".to_string(),
+            };
+            out.push_str(&format!("  {}: {}\n", name, status));
+        }
+
+        let found = |names: &[&str]| {
+            let found: Vec<&str> = names
+                .iter()
+                .copied()
+                .filter(|n| which(n).is_some())
+                .collect();
+            if found.is_empty() {
+                "none".to_string()
+            } else {
+                found.join(", ")
+            }
+        };
+        out.push_str(&format!("Package managers: {}\n", found(PACKAGE_MANAGERS)));
+        out.push_str(&format!("Other commands: {}\n", found(OTHER_COMMANDS)));
+        Ok(out)
+    }
+
+    // 세션 중에는 거의 바뀌지 않으므로 결과를 재사용해도 됨
+    fn is_cacheable(&self) -> bool {
+        true
+    }
+}
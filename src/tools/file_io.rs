@@ -1,106 +1,316 @@
-use super::Tool;
+use super::sandbox::Sandbox;
+use super::snapshot::GitSnapshot;
+use super::{OutputBudget, Tool, TruncateMode, parse_args};
 use crate::error::{Result, SuprascalarError};
+use serde::Deserialize;
 use serde_json::{Value, json};
-use std::env;
 use std::fs;
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// `tail` 읽기에서 파일 끝부터 한 번에 읽는 크기
+const TAIL_BLOCK_BYTES: u64 = 64 * 1024;
+
+/// 한 번의 read에서 `paths`로 읽을 수 있는 최대 파일 수
+const MAX_READ_PATHS: usize = 10;
+
+/// 바이너리 여부를 판단할 때 확인하는 앞부분 크기 (git과 같은 방식: NUL 바이트 검사)
+const BINARY_SNIFF_BYTES: usize = 8000;
 
 /// 파일 읽기/쓰기 도구 (Host-side I/O)
 /// 보안 기능: Path Traversal 방지 (프로젝트 폴더 탈출 금지)
-pub struct FileIO;
+pub struct FileIO {
+    output_budget: OutputBudget,
+    // true면 read만 허용 (분석 전용 에이전트용)
+    read_only: bool,
+    sandbox: Sandbox,
+    // true면 모든 write 후 파일을 다시 읽어 검증 (인자 `verify`와 무관하게)
+    verify_writes: bool,
+}
+
+#[derive(Deserialize)]
+struct FileIOArgs {
+    action: String,
+    #[serde(default)]
+    path: Option<String>,
+    // read 전용: 여러 파일을 한 번에 읽기 (`path` 대신)
+    #[serde(default)]
+    paths: Option<Vec<String>>,
+    content: Option<String>,
+    line_start: Option<u64>,
+    line_end: Option<u64>,
+    // 마지막 N줄만 읽기 (로그 파일 등)
+    tail: Option<u64>,
+    // write 후 다시 읽어 내용이 그대로 들어갔는지 확인
+    #[serde(default)]
+    verify: bool,
+}
 
 impl FileIO {
     pub fn new() -> Self {
-        Self
+        Self {
+            // 파일은 line range로 이어서 읽을 수 있으므로 앞부분을 남김
+            output_budget: OutputBudget::default().with_mode(TruncateMode::Head),
+            read_only: false,
+            sandbox: Sandbox::new(),
+            verify_writes: false,
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다. 모든 경로는 이 루트 기준으로 해석/검증됩니다.
+    /// 루트는 생성 시점에 canonicalize되므로 존재하는 디렉토리여야 합니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `ListDirectory`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// 쓰기 전 Git 스냅샷 설정 (기본: 켜짐, `GitSnapshot::disabled()`로 끔)
+    pub fn with_git_snapshot(mut self, snapshot: GitSnapshot) -> Self {
+        self.sandbox = self.sandbox.with_git_snapshot(snapshot);
+        self
+    }
+
+    /// 읽기 전용 FileIO. write 등 파일을 변경하는 action은 모두 거부됩니다.
+    pub fn read_only() -> Self {
+        Self {
+            read_only: true,
+            ..Self::new()
+        }
+    }
+
+    /// 읽기 결과에 적용할 출력 제한 설정
+    pub fn with_output_budget(mut self, budget: OutputBudget) -> Self {
+        self.output_budget = budget;
+        self
+    }
+
+    /// 모든 write를 검증 모드로 실행합니다 (다시 읽어 줄 수/체크섬을 결과에 포함).
+    pub fn with_verify_writes(mut self, enabled: bool) -> Self {
+        self.verify_writes = enabled;
+        self
     }
 
-    /// [Security Patch] Symlink 공격 방지를 위한 물리적 경로 검증
-    /// 1. 프로젝트 루트의 진짜 경로(Real Path)를 구합니다.
-    /// 2. 요청한 경로의 진짜 경로를 구합니다.
-    /// 3. 요청한 경로가 프로젝트 루트 안쪽에 있는지 확인합니다.
-    fn validate_path(&self, path_str: &str) -> Result<PathBuf> {
-        // 1. 프로젝트 루트의 물리적 경로 (Symlink 해제됨)
-        let cwd = env::current_dir().map_err(SuprascalarError::Io)?;
-        let canonical_root = cwd.canonicalize().map_err(SuprascalarError::Io)?;
-
-        // 2. 타겟 경로 구성
-        let target_path = cwd.join(path_str);
-
-        // 3. 물리적 경로 확인 (Symlink Resolution)
-        // 케이스 A: 파일/폴더가 이미 존재하는 경우
-        if target_path.exists() {
-            let real_path = target_path.canonicalize().map_err(|e| {
-                SuprascalarError::Unknown(format!("Failed to resolve path '{}': {}", path_str, e))
-            })?;
-
-            if !real_path.starts_with(&canonical_root) {
-                return Err(SuprascalarError::Unknown(format!(
-                    "SECURITY BLOCK: Symlink detected! '{}' resolves to '{}', which is outside the project root.",
-                    path_str,
-                    real_path.display()
-                )));
+    /// 파일 하나를 읽습니다 (`line_start`/`line_end`, `tail` 적용).
+    fn read(&self, path_str: &str, args: &FileIOArgs) -> Result<String> {
+        // [Security] 여기서 Symlink까지 확인하는 강력한 검증 수행
+        let path = self.sandbox.validate_path(self.name(), path_str)?;
+
+        if !path.exists() {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "File '{}' does not exist.",
+                path_str
+            )));
+        }
+        if path.is_dir() {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "'{}' is a directory, not a file.",
+                path_str
+            )));
+        }
+        if looks_binary(&path).map_err(SuprascalarError::Io)? {
+            return Ok(not_shown(path_str, &path, "binary"));
+        }
+
+        if let Some(tail) = args.tail {
+            if args.line_start.is_some() || args.line_end.is_some() {
+                return Err(SuprascalarError::InvalidToolInput(
+                    "Use either 'tail' or 'line_start'/'line_end', not both".to_string(),
+                ));
+            }
+            if tail == 0 {
+                return Err(SuprascalarError::InvalidToolInput(
+                    "'tail' must be at least 1".to_string(),
+                ));
             }
-            return Ok(real_path);
+            let text = match read_tail(&path, tail as usize) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    return Ok(not_shown(path_str, &path, "non-UTF-8 text"));
+                }
+                Err(e) => return Err(SuprascalarError::Io(e)),
+            };
+            // 파일이 더 짧으면 실제로 읽은 줄 수를 표시
+            let shown = text.lines().count();
+            // 끝부분을 보려는 요청이므로 넘치면 앞쪽을 자름
+            let text = self
+                .output_budget
+                .with_mode(TruncateMode::Tail)
+                .truncate(text);
+            return Ok(format!(
+                "File '{}' (last {} lines):\n```\n{}\n```",
+                path_str, shown, text
+            ));
         }
 
-        // 케이스 B: 파일이 존재하지 않는 경우 (새로 쓰기)
-        // 존재하지 않는 파일은 canonicalize가 불가능하므로, "존재하는 가장 깊은 부모 디렉토리"를 검사해야 함.
-        let mut current_check = target_path.parent();
+        let bytes = fs::read(&path).map_err(SuprascalarError::Io)?;
+        let Ok(content) = String::from_utf8(bytes) else {
+            return Ok(not_shown(path_str, &path, "non-UTF-8 text"));
+        };
 
-        while let Some(p) = current_check {
-            if p.exists() {
-                // 존재하는 부모를 찾았다! 이 부모가 혹시 외부로 연결된 심볼릭 링크인지 확인
-                let real_parent = p.canonicalize().map_err(SuprascalarError::Io)?;
+        let start = args.line_start;
+        let end = args.line_end;
 
-                if !real_parent.starts_with(&canonical_root) {
-                    return Err(SuprascalarError::Unknown(format!(
-                        "SECURITY BLOCK: Parent directory symlink escape detected! '{}' resolves to outside.",
-                        p.display()
-                    )));
+        let sliced = if start.is_some() || end.is_some() {
+            let lines: Vec<&str> = content.lines().collect();
+            if lines.is_empty() {
+                String::new()
+            } else {
+                let start_idx = start.unwrap_or(1);
+                let end_idx = end.unwrap_or(lines.len() as u64);
+
+                if start_idx == 0 || end_idx == 0 || start_idx > end_idx {
+                    return Err(SuprascalarError::InvalidToolInput(
+                        "Invalid line range: ensure 1-based start <= end".to_string(),
+                    ));
                 }
 
-                // 부모가 안전하다면, 루프 종료 (안전함)
-                break;
+                let start_pos = (start_idx.saturating_sub(1) as usize).min(lines.len());
+                let end_pos = (end_idx as usize).min(lines.len());
+
+                if start_pos >= end_pos {
+                    String::new()
+                } else {
+                    lines[start_pos..end_pos].join("\n")
+                }
             }
-            // 더 상위 부모로 이동
-            current_check = p.parent();
+        } else {
+            content
+        };
+
+        let sliced = self.output_budget.truncate(sliced);
+        Ok(format!("File '{}':\n```\n{}\n```", path_str, sliced))
+    }
+
+    /// 여러 파일을 한 번에 읽습니다. 파일마다 `File '...'` 헤더가 붙고, 각 파일에 출력 제한과
+    /// 줄 범위/`tail`이 따로 적용됩니다. 한 파일의 오류는 그 자리에 표시하고 나머지는 계속 읽습니다.
+    fn read_many(&self, paths: &[String], args: &FileIOArgs) -> Result<String> {
+        if paths.is_empty() {
+            return Err(SuprascalarError::InvalidToolInput(
+                "'paths' must not be empty".to_string(),
+            ));
+        }
+        if paths.len() > MAX_READ_PATHS {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "At most {} paths can be read at once (got {})",
+                MAX_READ_PATHS,
+                paths.len()
+            )));
         }
 
-        // 여기까지 오면 안전함 (부모들이 모두 Safe Zone 안에 있음)
-        // 단, 리턴값은 canonicalize된 경로가 아니라 논리적 경로여야 함 (파일이 아직 없으므로)
-        // 하지만 편의상 절대경로(target_path)를 반환
-        Ok(target_path)
+        let files = paths
+            .iter()
+            .map(|path_str| match self.read(path_str, args) {
+                Ok(text) => text,
+                Err(e) => format!("File '{}': Error: {}", path_str, e),
+            })
+            .collect::<Vec<_>>();
+        Ok(files.join("\n\n"))
     }
+}
 
-    /// Git snapshot before mutating files for basic auditing/safety
-    fn create_git_snapshot(&self, context: &str) {
-        let Ok(cwd) = env::current_dir() else {
-            return;
-        };
+/// 쓴 파일을 다시 읽어 요청한 내용과 비교합니다.
+/// 일치하면 줄 수/바이트 수/체크섬을, 다르면 처음 달라지는 줄을 알려줍니다.
+fn verify_written(
+    tool: &str,
+    path_str: &str,
+    path: &std::path::Path,
+    expected: &str,
+) -> Result<String> {
+    let actual = fs::read(path).map_err(SuprascalarError::Io)?;
+    if actual == expected.as_bytes() {
+        return Ok(format!(
+            "Successfully wrote to '{}' (verified: {} lines, {} bytes, checksum {:08x}).",
+            path_str,
+            expected.lines().count(),
+            actual.len(),
+            fnv1a(&actual)
+        ));
+    }
 
-        let status = Command::new("git")
-            .args(["status", "--porcelain"])
-            .current_dir(&cwd)
-            .output();
+    let actual = String::from_utf8_lossy(&actual);
+    let line = expected
+        .lines()
+        .zip(actual.lines())
+        .position(|(e, a)| e != a)
+        .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()))
+        + 1;
+    Err(SuprascalarError::ToolExecution {
+        tool: tool.to_string(),
+        message: format!(
+            "Verification failed for '{}': wrote {} bytes but read back {} bytes; \
+             content first differs at line {}.",
+            path_str,
+            expected.len(),
+            actual.len(),
+            line
+        ),
+    })
+}
 
-        if let Ok(output) = status {
-            if !output.status.success() || output.stdout.is_empty() {
-                return;
-            }
+/// 앞부분에 NUL 바이트가 있으면 바이너리 파일로 봅니다.
+fn looks_binary(path: &std::path::Path) -> std::io::Result<bool> {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_BYTES);
+    fs::File::open(path)?
+        .take(BINARY_SNIFF_BYTES as u64)
+        .read_to_end(&mut head)?;
+    Ok(head.contains(&0))
+}
 
-            let _ = Command::new("git")
-                .args(["add", "."])
-                .current_dir(&cwd)
-                .output();
+/// 텍스트로 보여줄 수 없는 파일에 대한 안내 (io 에러 대신 모델이 건너뛸 수 있도록)
+fn not_shown(path_str: &str, path: &std::path::Path, reason: &str) -> String {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    format!(
+        "File '{}' appears to be {} ({} bytes), not shown.",
+        path_str, reason, size
+    )
+}
 
-            let msg = format!("Suprascalar Auto-save: Before file_io '{}'", context);
-            let _ = Command::new("git")
-                .args(["commit", "-m", &msg])
-                .current_dir(&cwd)
-                .output();
-        }
+/// 파일 끝에서부터 블록 단위로 읽어 마지막 `n`줄만 돌려줍니다 (큰 로그 파일을 전부 읽지 않음).
+/// 파일이 `n`줄보다 짧으면 전체를 돌려줍니다.
+fn read_tail(path: &std::path::Path, n: usize) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut pos = file.metadata()?.len();
+    let mut buf: Vec<u8> = Vec::new();
+
+    // 마지막 줄의 끝(파일 끝의 줄바꿈)을 제외하고 n개의 줄바꿈이 보이면 충분
+    let enough = |buf: &[u8]| {
+        let body = buf.strip_suffix(b"\n").unwrap_or(buf);
+        body.iter().filter(|&&b| b == b'\n').count() >= n
+    };
+    while pos > 0 && !enough(&buf) {
+        let block = TAIL_BLOCK_BYTES.min(pos);
+        pos -= block;
+        file.seek(SeekFrom::Start(pos))?;
+        let mut chunk = vec![0u8; block as usize];
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
     }
+
+    let body = buf.strip_suffix(b"\n").unwrap_or(&buf);
+    let start = body
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(n.saturating_sub(1))
+        .map_or(0, |(i, _)| i + 1);
+    let text = String::from_utf8(body[start..].to_vec())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok(text.lines().collect::<Vec<_>>().join("\n"))
+}
+
+/// 32비트 FNV-1a (짧은 비교용 체크섬, 암호학적 용도 아님)
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5u32, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x01000193)
+    })
 }
 
 impl Tool for FileIO {
@@ -109,103 +319,129 @@ impl Tool for FileIO {
     }
 
     fn description(&self) -> &str {
-        "Reads or writes a file on the host system. \
+        if self.read_only {
+            return "Reads a file on the host system (read-only mode: writing is disabled). \
+            Strictly sandboxed: Cannot access files outside the current project directory.";
+        }
+        "Reads or writes a file, or creates a directory (mkdir), on the host system. \
         Strictly sandboxed: Cannot access files outside the current project directory. \
         Use this to create/edit code files."
     }
 
     fn parameters(&self) -> Value {
+        let actions = if self.read_only {
+            json!(["read"])
+        } else {
+            json!(["read", "write", "mkdir"])
+        };
         json!({
             "type": "object",
             "properties": {
                 "action": {
                     "type": "string",
-                    "enum": ["read", "write"],
+                    "enum": actions,
                     "description": "Action to perform"
                 },
                 "path": {
                     "type": "string",
-                    "description": "Relative file path (e.g., 'src/main.rs')"
+                    "description": "Relative file path (e.g., 'src/main.rs'). Required unless 'paths' is given"
+                },
+                "paths": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "For 'read': several related files to read at once, instead of 'path'"
                 },
                 "content": {
                     "type": "string",
                     "description": "Content to write (required for 'write')"
                 },
                 "line_start": { "type": "integer" },
-                "line_end": { "type": "integer" }
+                "line_end": { "type": "integer" },
+                "tail": {
+                    "type": "integer",
+                    "description": "For 'read': return only the last N lines (e.g. recent log entries)"
+                },
+                "verify": {
+                    "type": "boolean",
+                    "description": "For 'write': read the file back and report line count and checksum"
+                }
             },
-            "required": ["action", "path"]
+            "required": ["action"]
         })
     }
 
     fn execute(&self, args: Value) -> Result<String> {
-        let action = args["action"]
-            .as_str()
-            .ok_or_else(|| SuprascalarError::Unknown("Missing 'action'".to_string()))?;
-        let path_str = args["path"]
-            .as_str()
-            .ok_or_else(|| SuprascalarError::Unknown("Missing 'path'".to_string()))?;
+        let args: FileIOArgs = parse_args(args)?;
+        let action = args.action.as_str();
+
+        if self.read_only && action != "read" {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "READ-ONLY MODE: action '{}' is not allowed. Only 'read' is permitted.",
+                action
+            )));
+        }
+
+        if let Some(paths) = &args.paths {
+            if action != "read" {
+                return Err(SuprascalarError::InvalidToolInput(
+                    "'paths' is only supported for 'read'".to_string(),
+                ));
+            }
+            if args.path.is_some() {
+                return Err(SuprascalarError::InvalidToolInput(
+                    "Use either 'path' or 'paths', not both".to_string(),
+                ));
+            }
+            return self.read_many(paths, &args);
+        }
+
+        let path_str = args
+            .path
+            .as_deref()
+            .ok_or_else(|| SuprascalarError::InvalidToolInput("Missing 'path'".to_string()))?;
+        if action == "read" {
+            return self.read(path_str, &args);
+        }
 
         // [Security] 여기서 Symlink까지 확인하는 강력한 검증 수행
-        let path = self.validate_path(path_str)?;
+        let path = self.sandbox.validate_path(self.name(), path_str)?;
 
         match action {
-            "read" => {
-                if !path.exists() {
-                    return Err(SuprascalarError::Unknown(format!(
-                        "File '{}' does not exist.",
-                        path_str
-                    )));
-                }
-                let content = fs::read_to_string(&path).map_err(SuprascalarError::Io)?;
-
-                let start = args["line_start"].as_u64();
-                let end = args["line_end"].as_u64();
-
-                let sliced = if start.is_some() || end.is_some() {
-                    let lines: Vec<&str> = content.lines().collect();
-                    if lines.is_empty() {
-                        String::new()
-                    } else {
-                        let start_idx = start.unwrap_or(1);
-                        let end_idx = end.unwrap_or(lines.len() as u64);
-
-                        if start_idx == 0 || end_idx == 0 || start_idx > end_idx {
-                            return Err(SuprascalarError::Unknown(
-                                "Invalid line range: ensure 1-based start <= end".to_string(),
-                            ));
-                        }
-
-                        let start_pos = (start_idx.saturating_sub(1) as usize).min(lines.len());
-                        let end_pos = (end_idx as usize).min(lines.len());
-
-                        if start_pos >= end_pos {
-                            String::new()
-                        } else {
-                            lines[start_pos..end_pos].join("\n")
-                        }
-                    }
-                } else {
-                    content
-                };
-
-                Ok(format!("File '{}':\n```\n{}\n```", path_str, sliced))
-            }
             "write" => {
-                let content = args["content"]
-                    .as_str()
-                    .ok_or_else(|| SuprascalarError::Unknown("Missing 'content'".to_string()))?;
+                let content = args.content.as_deref().ok_or_else(|| {
+                    SuprascalarError::InvalidToolInput("Missing 'content'".to_string())
+                })?;
 
                 if let Some(parent) = path.parent() {
                     fs::create_dir_all(parent).map_err(SuprascalarError::Io)?;
                 }
 
-                self.create_git_snapshot(path_str);
+                self.sandbox.create_git_snapshot("file_io", path_str);
 
                 fs::write(&path, content).map_err(SuprascalarError::Io)?;
+                if args.verify || self.verify_writes {
+                    return verify_written(self.name(), path_str, &path, content);
+                }
                 Ok(format!("Successfully wrote to '{}'.", path_str))
             }
-            _ => Ok(format!("Unknown action: {}", action)),
+            "mkdir" => {
+                if path.is_dir() {
+                    return Ok(format!("Directory '{}' already exists.", path_str));
+                }
+                if path.exists() {
+                    return Err(SuprascalarError::InvalidToolInput(format!(
+                        "'{}' already exists and is not a directory.",
+                        path_str
+                    )));
+                }
+
+                fs::create_dir_all(&path).map_err(SuprascalarError::Io)?;
+                Ok(format!("Successfully created directory '{}'.", path_str))
+            }
+            _ => Err(SuprascalarError::InvalidToolInput(format!(
+                "Unknown action: {}",
+                action
+            ))),
         }
     }
 }
@@ -0,0 +1,81 @@
+use super::Tool;
+use crate::error::Result;
+use serde_json::Value;
+
+type ToolFn = Box<dyn Fn(Value) -> Result<String> + Send + Sync>;
+
+/// 클로저로 만드는 일회성 도구.
+/// 새 구조체를 정의하지 않고 이름/설명/JSON Schema/실행 함수만으로 `Tool`을 구성합니다.
+///
+/// ```ignore
+/// agent.register_tool(FnTool::new(
+///     "add",
+///     "Add two numbers.",
+///     json!({"type": "object", "properties": {"a": {"type": "number"}, "b": {"type": "number"}}}),
+///     |v| Ok((v["a"].as_f64().unwrap_or(0.0) + v["b"].as_f64().unwrap_or(0.0)).to_string()),
+/// ));
+/// ```
+pub struct FnTool {
+    name: String,
+    description: String,
+    parameters: Value,
+    func: ToolFn,
+    terminal: bool,
+    cacheable: bool,
+}
+
+impl FnTool {
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: Value,
+        func: impl Fn(Value) -> Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+            func: Box::new(func),
+            terminal: false,
+            cacheable: false,
+        }
+    }
+
+    /// 결과를 최종 답변으로 바로 반환하는 도구로 만듭니다 (`Tool::is_terminal`).
+    pub fn with_terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// 부작용 없는 도구로 표시해 에이전트의 도구 캐시가 결과를 재사용하게 합니다 (`Tool::is_cacheable`).
+    pub fn with_cacheable(mut self, cacheable: bool) -> Self {
+        self.cacheable = cacheable;
+        self
+    }
+}
+
+impl Tool for FnTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        (self.func)(args)
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    fn is_cacheable(&self) -> bool {
+        self.cacheable
+    }
+}
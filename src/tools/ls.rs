@@ -1,14 +1,43 @@
-use super::Tool;
+use super::sandbox::Sandbox;
+use super::{Tool, parse_args};
 use crate::error::{Result, SuprascalarError};
+use serde::Deserialize;
 use serde_json::{Value, json};
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
 
-pub struct ListDirectory;
+/// 디렉토리 목록 도구. `FileIO`와 같은 `Sandbox`로 경로를 검증합니다.
+pub struct ListDirectory {
+    sandbox: Sandbox,
+}
+
+#[derive(Deserialize)]
+struct ListArgs {
+    #[serde(default = "default_path")]
+    path: String,
+}
+
+fn default_path() -> String {
+    ".".to_string()
+}
 
 impl ListDirectory {
     pub fn new() -> Self {
-        Self
+        Self {
+            sandbox: Sandbox::new(),
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `FileIO`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
     }
 }
 
@@ -17,6 +46,11 @@ impl Tool for ListDirectory {
         "list_files"
     }
 
+    // 읽기 전용이므로 같은 경로의 결과를 재사용해도 됨
+    fn is_cacheable(&self) -> bool {
+        true
+    }
+
     fn description(&self) -> &str {
         "List files and directories in a specific path. Useful for exploring the file system."
     }
@@ -36,8 +70,11 @@ impl Tool for ListDirectory {
 
     fn execute(&self, args: Value) -> Result<String> {
         // 인자 파싱 (없으면 현재 디렉토리)
-        let path_str = args["path"].as_str().unwrap_or(".");
-        let path = Path::new(path_str);
+        let args: ListArgs = parse_args(args)?;
+        let path_str = args.path.as_str();
+
+        // [Security] FileIO와 동일한 샌드박스 검증
+        let path = self.sandbox.validate_path(self.name(), path_str)?;
 
         // 경로 존재 여부 확인
         if !path.exists() {
@@ -45,7 +82,7 @@ impl Tool for ListDirectory {
         }
 
         // 디렉토리 읽기
-        let entries = fs::read_dir(path).map_err(SuprascalarError::Io)?;
+        let entries = fs::read_dir(&path).map_err(SuprascalarError::Io)?;
 
         let mut file_list = String::new();
         file_list.push_str(&format!("Files in '{}':\n", path_str));
@@ -1,13 +1,33 @@
 // src/tools/mod.rs
 
-use crate::error::Result;
+use crate::error::{Result, SuprascalarError};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
 
 // 서브 모듈(구현체) 등록
+pub mod cargo;
 pub mod docker;
+pub mod env_info;
 pub mod file_io;
+pub mod fn_tool;
 pub mod ls;
+pub mod outline;
+pub mod patch;
+pub mod sandbox;
+pub mod snapshot;
 pub mod terminal;
+pub mod test_runner;
+
+pub use cargo::CargoTool;
+pub use docker::DockerLog;
+pub use env_info::EnvInfo;
+pub use fn_tool::FnTool;
+pub use outline::Outline;
+pub use sandbox::Sandbox;
+pub use snapshot::GitSnapshot;
+pub use test_runner::TestRunner;
 
 /// Suprascalar의 모든 도구가 구현해야 하는 인터페이스입니다.
 /// MCP(Model Context Protocol) 표준과 호환되도록 설계되었습니다.
@@ -23,4 +43,241 @@ pub trait Tool: Send + Sync {
 
     /// 도구 실행 로직
     fn execute(&self, args: Value) -> Result<String>;
+
+    /// true면 실행에 성공했을 때 결과를 그대로 최종 답변으로 반환하고 ReAct 루프를 끝냅니다.
+    /// 모델이 도구 결과를 되풀이하기 위한 추가 생성을 건너뜁니다 (예: 검색 후 바로 답하는 도구).
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    /// true면 같은 인자의 결과를 재사용해도 되는 (부작용 없는 읽기 전용) 도구입니다.
+    /// 에이전트에 도구 캐시(`Agent::set_tool_cache`)가 켜져 있을 때만 의미가 있습니다.
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// 런타임에 구현체를 고르는 경우(예: `DockerShell::new_or_host`)를 위해 박스된 도구도 `Tool`로 취급합니다.
+impl<T: Tool + ?Sized> Tool for Box<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn description(&self) -> &str {
+        (**self).description()
+    }
+
+    fn parameters(&self) -> Value {
+        (**self).parameters()
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        (**self).execute(args)
+    }
+
+    fn is_terminal(&self) -> bool {
+        (**self).is_terminal()
+    }
+
+    fn is_cacheable(&self) -> bool {
+        (**self).is_cacheable()
+    }
+}
+
+/// 도구를 에이전트에 등록한 뒤에도 설정을 바꿀 수 있도록 (예: `TerminalSession::allow_once`)
+/// `Arc`로 공유된 도구도 `Tool`로 취급합니다.
+impl<T: Tool + ?Sized> Tool for std::sync::Arc<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn description(&self) -> &str {
+        (**self).description()
+    }
+
+    fn parameters(&self) -> Value {
+        (**self).parameters()
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        (**self).execute(args)
+    }
+
+    fn is_terminal(&self) -> bool {
+        (**self).is_terminal()
+    }
+
+    fn is_cacheable(&self) -> bool {
+        (**self).is_cacheable()
+    }
+}
+
+/// 도구의 실시간 출력(명령 실행 중 출력, 상태 메시지)을 내보내는 방식
+/// 라이브러리로 사용할 때 stdout을 오염시키지 않도록 끄거나 다른 곳으로 보낼 수 있습니다.
+/// 모델에게 돌려주는 결과(`execute`의 반환값)에는 영향을 주지 않습니다.
+#[derive(Clone, Default)]
+pub enum ToolOutputSink {
+    /// stdout으로 출력 (기본값)
+    #[default]
+    Stdout,
+    /// 아무것도 출력하지 않음
+    Silent,
+    /// 채널로 전송 (받는 쪽이 닫혀 있으면 버림)
+    Channel(Sender<String>),
+    /// 콜백으로 전달 (예: 로거, UI)
+    Custom(Arc<dyn Fn(&str) + Send + Sync>),
+    /// `tracing` 이벤트로 기록 (target `suprascalar::tools`, 상태 메시지는 INFO, 명령 출력은 DEBUG)
+    Tracing,
+}
+
+impl ToolOutputSink {
+    pub fn is_silent(&self) -> bool {
+        matches!(self, ToolOutputSink::Silent)
+    }
+
+    /// 한 줄짜리 상태 메시지
+    pub(crate) fn line(&self, msg: &str) {
+        match self {
+            ToolOutputSink::Stdout => println!("{}", msg),
+            ToolOutputSink::Silent => {}
+            ToolOutputSink::Channel(tx) => {
+                let _ = tx.send(format!("{}\n", msg));
+            }
+            ToolOutputSink::Custom(f) => f(msg),
+            ToolOutputSink::Tracing => tracing::info!(target: "suprascalar::tools", "{}", msg),
+        }
+    }
+
+    /// 줄바꿈 없이 이어지는 명령 출력 조각
+    pub(crate) fn chunk(&self, chunk: &str) {
+        match self {
+            ToolOutputSink::Stdout => {
+                print!("{}", chunk);
+                let _ = std::io::Write::flush(&mut std::io::stdout());
+            }
+            ToolOutputSink::Silent => {}
+            ToolOutputSink::Channel(tx) => {
+                let _ = tx.send(chunk.to_string());
+            }
+            ToolOutputSink::Custom(f) => f(chunk),
+            ToolOutputSink::Tracing => tracing::debug!(target: "suprascalar::tools", "{}", chunk),
+        }
+    }
+}
+
+/// 출력이 `OutputBudget`을 넘을 때 남길 부분
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TruncateMode {
+    /// 앞부분만 남김 (파일 읽기처럼 이어서 읽을 수 있는 경우)
+    Head,
+    /// 뒷부분만 남김 (긴 로그의 마지막 상태만 필요한 경우)
+    Tail,
+    /// 앞/뒤를 절반씩 남김 (끝부분의 에러 메시지 보존)
+    #[default]
+    Middle,
+}
+
+/// 도구 출력이 LLM 컨텍스트에 들어가기 전 적용되는 길이 제한 (문자 단위)
+/// 큰 컨텍스트 모델은 늘리고, 작은 모델은 줄여서 사용합니다.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputBudget {
+    pub max_chars: usize,
+    pub mode: TruncateMode,
+}
+
+impl Default for OutputBudget {
+    fn default() -> Self {
+        Self {
+            max_chars: 2000,
+            mode: TruncateMode::default(),
+        }
+    }
+}
+
+impl OutputBudget {
+    pub fn new(max_chars: usize) -> Self {
+        Self {
+            max_chars,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_mode(mut self, mode: TruncateMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// 설정된 `mode`로 자릅니다. 모든 자르기는 문자 경계 기준이라 멀티바이트 출력에서도 안전합니다.
+    pub fn truncate(&self, output: String) -> String {
+        match self.mode {
+            TruncateMode::Head => self.truncate_head(output),
+            TruncateMode::Tail => self.truncate_tail(output),
+            TruncateMode::Middle => self.truncate_middle(output),
+        }
+    }
+
+    /// 앞부분만 남기고 자릅니다.
+    pub fn truncate_head(&self, output: String) -> String {
+        let total = output.chars().count();
+        if total <= self.max_chars {
+            return output;
+        }
+
+        let end = char_offset(&output, self.max_chars);
+        format!(
+            "{}\n... [Output truncated: {} of {} chars omitted] ...",
+            &output[..end],
+            total - self.max_chars,
+            total
+        )
+    }
+
+    /// 뒷부분만 남기고 자릅니다.
+    pub fn truncate_tail(&self, output: String) -> String {
+        let total = output.chars().count();
+        if total <= self.max_chars {
+            return output;
+        }
+
+        let start = char_offset(&output, total - self.max_chars);
+        format!(
+            "... [Output truncated: {} of {} chars omitted] ...\n{}",
+            total - self.max_chars,
+            total,
+            &output[start..]
+        )
+    }
+
+    /// 앞/뒤를 절반씩 남기고 가운데를 자릅니다 (끝부분의 에러 메시지 보존).
+    pub fn truncate_middle(&self, output: String) -> String {
+        let total = output.chars().count();
+        if total <= self.max_chars {
+            return output;
+        }
+
+        let half = self.max_chars / 2;
+        let start = char_offset(&output, half);
+        let end = char_offset(&output, total - (self.max_chars - half));
+        format!(
+            "{}\n... [Output truncated: {} of {} chars omitted] ...\n{}",
+            &output[..start],
+            total - self.max_chars,
+            total,
+            &output[end..]
+        )
+    }
+}
+
+/// n번째 문자의 바이트 오프셋 (멀티바이트 문자 경계에서 안전)
+fn char_offset(text: &str, n: usize) -> usize {
+    text.char_indices()
+        .nth(n)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len())
+}
+
+/// 도구 인자(JSON)를 강타입 구조체로 역직렬화합니다.
+/// 모델이 잘못된 인자를 보낸 경우 serde 에러 메시지를 담은 `InvalidToolInput`을 반환합니다.
+pub fn parse_args<T: DeserializeOwned>(args: Value) -> Result<T> {
+    serde_json::from_value(args).map_err(|e| SuprascalarError::InvalidToolInput(e.to_string()))
 }
@@ -0,0 +1,260 @@
+use super::sandbox::Sandbox;
+use super::{Tool, parse_args};
+use crate::error::{Result, SuprascalarError};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// 기본으로 돌려주는 선언 개수 상한
+const DEFAULT_MAX_ENTRIES: usize = 200;
+
+/// 한 선언 줄에서 보여줄 최대 글자 수
+const MAX_SIGNATURE_CHARS: usize = 120;
+
+/// 소스 파일의 선언 목록(fn/struct/impl, def/class 등)만 줄 번호와 함께 돌려주는 도구
+/// 큰 파일을 통째로 읽는 대신 구조를 먼저 보고 `FileIO`의 line range로 필요한 부분만 읽게 합니다.
+pub struct Outline {
+    sandbox: Sandbox,
+    max_entries: usize,
+}
+
+#[derive(Deserialize)]
+struct OutlineArgs {
+    path: String,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Outline {
+    pub fn new() -> Self {
+        Self {
+            sandbox: Sandbox::new(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `FileIO`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// 돌려줄 선언의 최대 개수
+    pub fn with_max_entries(mut self, max: usize) -> Self {
+        self.max_entries = max;
+        self
+    }
+}
+
+/// 선언 패턴 목록을 컴파일합니다 (패턴은 모두 상수이므로 실패하면 버그).
+fn compile(patterns: &[&str]) -> Vec<Regex> {
+    patterns
+        .iter()
+        .map(|p| Regex::new(p).expect("valid declaration regex"))
+        .collect()
+}
+
+// 언어별 선언 패턴 (정규식 기반의 가벼운 추출기, 파서가 아니므로 근사치). 처음 쓸 때 한 번만 컴파일
+static RUST_DECLS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    compile(&[
+        r#"^\s*(pub(\([^)]*\))?\s+)?((const|async|unsafe|extern\s+"[^"]*")\s+)*fn\s+\w+"#,
+        r"^\s*(pub(\([^)]*\))?\s+)?(unsafe\s+)?(struct|enum|union|trait|mod|type)\s+\w+",
+        r"^\s*(unsafe\s+)?impl\b",
+        r"^(pub(\([^)]*\))?\s+)?(const|static)\s+\w+",
+        r"^\s*macro_rules!\s*\w+",
+    ])
+});
+static PYTHON_DECLS: LazyLock<Vec<Regex>> =
+    LazyLock::new(|| compile(&[r"^\s*(async\s+)?def\s+\w+", r"^\s*class\s+\w+"]));
+static JS_DECLS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    compile(&[
+        r"^\s*(export\s+)?(default\s+)?(declare\s+)?(abstract\s+)?(async\s+)?(function\*?|class|interface|enum|type|namespace)\s+\w+",
+        r"^(export\s+)?(const|let|var)\s+\w+\s*=\s*(async\s+)?(\([^)]*\)|\w+)\s*=>",
+    ])
+});
+static GO_DECLS: LazyLock<Vec<Regex>> = LazyLock::new(|| compile(&[r"^func\s", r"^type\s+\w+"]));
+static JVM_DECLS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    compile(&[
+        r"^\s*((public|private|protected|internal|static|final|abstract|sealed|open|data|override|suspend)\s+)*(class|interface|enum|record|object|fun|def)\s+\w+",
+        r"^\s+((public|private|protected|static|final|abstract|synchronized)\s+)+[\w<>\[\],\s]+\s+\w+\s*\(",
+    ])
+});
+
+/// 확장자별 선언 패턴
+fn declaration_patterns(path: &Path) -> Option<&'static [Regex]> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    let patterns: &'static LazyLock<Vec<Regex>> = match ext.as_str() {
+        "rs" => &RUST_DECLS,
+        "py" => &PYTHON_DECLS,
+        "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" => &JS_DECLS,
+        "go" => &GO_DECLS,
+        "java" | "kt" | "kts" | "scala" | "cs" => &JVM_DECLS,
+        _ => return None,
+    };
+    Some(patterns.as_slice())
+}
+
+/// 선언 줄을 한 줄 요약으로 정리합니다 (본문 시작 `{`/`:` 이후는 버림).
+fn signature(line: &str) -> String {
+    let trimmed = line.trim_end();
+    let head = trimmed
+        .split_once('{')
+        .map_or(trimmed, |(head, _)| head)
+        .trim_end();
+    let head = head.strip_suffix(':').unwrap_or(head);
+    if head.chars().count() > MAX_SIGNATURE_CHARS {
+        let cut: String = head.chars().take(MAX_SIGNATURE_CHARS).collect();
+        format!("{}...", cut)
+    } else {
+        head.to_string()
+    }
+}
+
+impl Tool for Outline {
+    fn name(&self) -> &str {
+        "file_outline"
+    }
+
+    // 읽기 전용이므로 같은 경로의 결과를 재사용해도 됨
+    fn is_cacheable(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        "Shows the outline of a source file: its declarations (functions, structs, classes, impls, \
+        modules) with line numbers. Use this before reading a large file, then read only the \
+        relevant line ranges with read_write_file. Supports Rust, Python, JavaScript/TypeScript, \
+        Go and Java/Kotlin."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Relative path of the source file (e.g., 'src/main.rs')"
+                }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        let args: OutlineArgs = parse_args(args)?;
+        let path_str = args.path.as_str();
+
+        // [Security] FileIO와 동일한 샌드박스 검증
+        let path = self.sandbox.validate_path(self.name(), path_str)?;
+        if !path.is_file() {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "File '{}' does not exist.",
+                path_str
+            )));
+        }
+
+        let Some(regexes) = declaration_patterns(&path) else {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "Unsupported file type for outline: '{}'. \
+                 Supported: .rs, .py, .js/.ts, .go, .java/.kt",
+                path_str
+            )));
+        };
+        let content = fs::read_to_string(&path).map_err(SuprascalarError::Io)?;
+        let total_lines = content.lines().count();
+
+        let entries: Vec<(usize, String)> = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| regexes.iter().any(|re| re.is_match(line)))
+            .map(|(i, line)| (i + 1, signature(line)))
+            .collect();
+
+        let mut out = format!(
+            "Outline of '{}' ({} lines, {} declarations):\n",
+            path_str,
+            total_lines,
+            entries.len()
+        );
+        // 줄 번호 폭을 맞춰서 들여쓰기(중첩 구조)가 보이도록
+        let width = total_lines.to_string().len();
+        for (line_no, sig) in entries.iter().take(self.max_entries) {
+            out.push_str(&format!("{:>width$}: {}\n", line_no, sig, width = width));
+        }
+        if entries.len() > self.max_entries {
+            out.push_str(&format!(
+                "... and {} more declarations\n",
+                entries.len() - self.max_entries
+            ));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `file.{ext}`의 선언 줄 요약 목록
+    fn declarations(ext: &str, source: &str) -> Vec<String> {
+        let regexes = declaration_patterns(Path::new(&format!("file.{}", ext))).unwrap();
+        source
+            .lines()
+            .filter(|line| regexes.iter().any(|re| re.is_match(line)))
+            .map(signature)
+            .collect()
+    }
+
+    #[test]
+    fn extracts_rust_declarations() {
+        let source = "\
+use std::fmt;
+
+pub struct Point {
+    x: i32,
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, \"{}\", self.x)
+    }
+}
+
+pub(crate) async fn load() {}
+const LIMIT: usize = 3;
+";
+        assert_eq!(
+            declarations("rs", source),
+            vec![
+                "pub struct Point",
+                "impl fmt::Display for Point",
+                "    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result",
+                "pub(crate) async fn load()",
+                "const LIMIT: usize = 3;",
+            ]
+        );
+    }
+
+    #[test]
+    fn extracts_python_declarations_and_rejects_unknown_types() {
+        let source = "class Parser:\n    async def parse(self):\n        return 1\n";
+        assert_eq!(
+            declarations("py", source),
+            vec!["class Parser", "    async def parse(self)"]
+        );
+        assert!(declaration_patterns(Path::new("notes.txt")).is_none());
+    }
+}
@@ -0,0 +1,564 @@
+use super::sandbox::Sandbox;
+use super::snapshot::GitSnapshot;
+use super::{Tool, parse_args};
+use crate::error::{Result, SuprascalarError};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Unified diff 적용 도구 (Host-side)
+/// 파일 전체를 다시 쓰는 것보다 모델이 diff를 출력하는 편이 수정 범위가 작고 안정적입니다.
+/// 모든 hunk가 적용 가능한 것을 확인한 뒤에만 파일을 씁니다 (부분 적용 없음).
+#[derive(Default)]
+pub struct ApplyPatch {
+    sandbox: Sandbox,
+}
+
+#[derive(Deserialize)]
+struct PatchArgs {
+    diff: String,
+}
+
+/// diff 안의 파일 하나에 대한 변경 (`---`/`+++` 헤더 + hunk 목록)
+struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+struct Hunk {
+    header: String,
+    old_start: usize,
+    // 헤더에 적힌 원본 줄 수 (0이면 `old_start` 줄 뒤에 삽입)
+    old_count: usize,
+    lines: Vec<HunkLine>,
+}
+
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    /// 원본 파일에서 찾아야 하는 줄들 (context + 삭제 줄)
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl ApplyPatch {
+    pub fn new() -> Self {
+        Self {
+            sandbox: Sandbox::new(),
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `FileIO`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// 패치 적용 전 Git 스냅샷 설정 (기본: 켜짐)
+    pub fn with_git_snapshot(mut self, snapshot: GitSnapshot) -> Self {
+        self.sandbox = self.sandbox.with_git_snapshot(snapshot);
+        self
+    }
+}
+
+impl Tool for ApplyPatch {
+    fn name(&self) -> &str {
+        "apply_patch"
+    }
+
+    fn description(&self) -> &str {
+        "Applies a unified diff (as produced by `diff -u` or `git diff`) to files in the project. \
+        Paths are relative to the project root; use /dev/null to create or delete files. \
+        Strictly sandboxed: Cannot touch files outside the current project directory."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "diff": {
+                    "type": "string",
+                    "description": "Unified diff text with ---/+++ file headers and @@ hunks"
+                }
+            },
+            "required": ["diff"]
+        })
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        let args: PatchArgs = parse_args(args)?;
+        let patches = parse_unified_diff(&args.diff)?;
+
+        // 1. 모든 파일에 대해 메모리 상에서 먼저 적용 (하나라도 실패하면 아무것도 쓰지 않음)
+        let mut writes: Vec<(PathBuf, Option<String>)> = Vec::new();
+        let mut summary = Vec::new();
+        // 같은 파일을 두 섹션에서 고치면 두 번째 쓰기가 첫 번째를 덮어쓰므로 거부
+        let mut touched: HashSet<PathBuf> = HashSet::new();
+        let mut touch = |path: &str, resolved: &PathBuf| {
+            if touched.insert(resolved.clone()) {
+                Ok(())
+            } else {
+                Err(SuprascalarError::InvalidToolInput(format!(
+                    "'{}' appears in more than one file section of the diff. \
+                    Put all of its hunks under a single ---/+++ header.",
+                    path
+                )))
+            }
+        };
+
+        for patch in &patches {
+            let source = match &patch.old_path {
+                Some(path) => {
+                    let resolved = self.sandbox.validate_path(self.name(), path)?;
+                    if !resolved.is_file() {
+                        return Err(SuprascalarError::InvalidToolInput(format!(
+                            "File '{}' does not exist.",
+                            path
+                        )));
+                    }
+                    touch(path, &resolved)?;
+                    Some((path, resolved))
+                }
+                None => None,
+            };
+            if source.is_none()
+                && let Some(path) = &patch.new_path
+                && self.sandbox.validate_path(self.name(), path)?.exists()
+            {
+                return Err(SuprascalarError::InvalidToolInput(format!(
+                    "Cannot create '{}': file already exists. Use a diff against the existing file.",
+                    path
+                )));
+            }
+
+            let original = match &source {
+                Some((_, resolved)) => {
+                    fs::read_to_string(resolved).map_err(SuprascalarError::Io)?
+                }
+                None => String::new(),
+            };
+            let display = patch
+                .new_path
+                .as_deref()
+                .or(patch.old_path.as_deref())
+                .unwrap_or_default();
+            let updated = apply_hunks(&original, &patch.hunks, display)?;
+
+            match &patch.new_path {
+                Some(path) => {
+                    let target = self.sandbox.validate_path(self.name(), path)?;
+                    if source
+                        .as_ref()
+                        .is_none_or(|(_, resolved)| *resolved != target)
+                    {
+                        touch(path, &target)?;
+                    }
+                    // 이름 변경: 새 경로에 쓰고 기존 경로는 삭제
+                    if let Some((_, resolved)) = &source
+                        && *resolved != target
+                    {
+                        writes.push((resolved.clone(), None));
+                    }
+                    writes.push((target, Some(updated)));
+                    summary.push(format!("- {} ({} hunk(s))", path, patch.hunks.len()));
+                }
+                None => {
+                    if let Some((path, resolved)) = source {
+                        writes.push((resolved, None));
+                        summary.push(format!("- {} (deleted)", path));
+                    }
+                }
+            }
+        }
+
+        // 2. 검증이 끝난 뒤에만 스냅샷 + 실제 쓰기
+        self.sandbox
+            .create_git_snapshot("apply_patch", &format!("{} file(s)", patches.len()));
+
+        for (path, content) in writes {
+            match content {
+                Some(content) => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).map_err(SuprascalarError::Io)?;
+                    }
+                    fs::write(&path, content).map_err(SuprascalarError::Io)?;
+                }
+                None => fs::remove_file(&path).map_err(SuprascalarError::Io)?,
+            }
+        }
+
+        Ok(format!(
+            "Successfully applied patch to {} file(s):\n{}",
+            patches.len(),
+            summary.join("\n")
+        ))
+    }
+}
+
+/// Unified diff 텍스트를 파일 단위 패치로 파싱합니다.
+fn parse_unified_diff(diff: &str) -> Result<Vec<FilePatch>> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(old_header) = lines[i].strip_prefix("--- ") else {
+            // `diff --git`, `index ...` 같은 부가 헤더는 무시
+            i += 1;
+            continue;
+        };
+        let Some(new_header) = lines.get(i + 1).and_then(|l| l.strip_prefix("+++ ")) else {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "Malformed diff: '--- {}' is not followed by a '+++' line",
+                old_header
+            )));
+        };
+        i += 2;
+
+        let mut patch = FilePatch {
+            old_path: parse_diff_path(old_header),
+            new_path: parse_diff_path(new_header),
+            hunks: Vec::new(),
+        };
+
+        while i < lines.len() && lines[i].starts_with("@@") {
+            let header = lines[i].to_string();
+            let range = parse_hunk_header(&header)?;
+            let mut hunk = Hunk {
+                header,
+                old_start: range.old_start,
+                old_count: range.old_count,
+                lines: Vec::new(),
+            };
+            i += 1;
+
+            // 헤더의 줄 수만큼 정확히 읽음: `-- `로 시작하는 삭제 줄(SQL/Lua 주석 등)이
+            // 파일 헤더(`--- `)처럼 보여도 hunk 본문으로 처리
+            let (mut old_left, mut new_left) = (range.old_count, range.new_count);
+            while old_left > 0 || new_left > 0 {
+                let Some(&line) = lines.get(i) else {
+                    break;
+                };
+                if line.starts_with("@@") {
+                    break;
+                }
+                if let Some(rest) = line.strip_prefix('+') {
+                    hunk.lines.push(HunkLine::Add(rest.to_string()));
+                    new_left = new_left.saturating_sub(1);
+                } else if let Some(rest) = line.strip_prefix('-') {
+                    hunk.lines.push(HunkLine::Remove(rest.to_string()));
+                    old_left = old_left.saturating_sub(1);
+                } else if line.starts_with('\\') {
+                    // "\ No newline at end of file"
+                } else if line.is_empty() || line.starts_with(' ') {
+                    // 공백 한 칸이 빠진 빈 context 줄도 허용
+                    let rest = line.strip_prefix(' ').unwrap_or(line);
+                    hunk.lines.push(HunkLine::Context(rest.to_string()));
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                } else {
+                    // `diff --git` 같은 diff 외 줄
+                    break;
+                }
+                i += 1;
+            }
+            // 마지막 줄 뒤에 붙는 "\ No newline at end of file"
+            while lines.get(i).is_some_and(|l| l.starts_with('\\')) {
+                i += 1;
+            }
+
+            if old_left > 0 || new_left > 0 || has_extra_hunk_line(&lines, i) {
+                return Err(SuprascalarError::InvalidToolInput(format!(
+                    "Malformed diff: the body of hunk '{}' does not match the line counts \
+                    in its header. Regenerate the diff with correct @@ ranges.",
+                    hunk.header
+                )));
+            }
+
+            patch.hunks.push(hunk);
+        }
+
+        if patch.hunks.is_empty() {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "Malformed diff: no hunks for '{}'",
+                new_header
+            )));
+        }
+        if patch.old_path.is_none() && patch.new_path.is_none() {
+            return Err(SuprascalarError::InvalidToolInput(
+                "Malformed diff: both paths are /dev/null".to_string(),
+            ));
+        }
+        patches.push(patch);
+    }
+
+    if patches.is_empty() {
+        return Err(SuprascalarError::InvalidToolInput(
+            "No file changes found: expected '---'/'+++' headers followed by '@@' hunks"
+                .to_string(),
+        ));
+    }
+    Ok(patches)
+}
+
+/// `a/src/main.rs\t2024-01-01 ...` -> `src/main.rs`, `/dev/null` -> None
+fn parse_diff_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// hunk 헤더의 줄 범위
+struct HunkRange {
+    old_start: usize,
+    old_count: usize,
+    new_count: usize,
+}
+
+/// `@@ -12,7 +12,8 @@` 에서 원본 시작 줄(1-based)과 양쪽 줄 수를 읽습니다.
+/// 줄 수가 생략된 범위(`-12`)는 한 줄입니다.
+fn parse_hunk_header(header: &str) -> Result<HunkRange> {
+    let malformed =
+        || SuprascalarError::InvalidToolInput(format!("Malformed hunk header: '{}'", header));
+    let range = |prefix: char| -> Option<(usize, usize)> {
+        let range = header
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix(prefix))?;
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+
+    let (old_start, old_count) = range('-').ok_or_else(malformed)?;
+    let (_, new_count) = range('+').ok_or_else(malformed)?;
+    Ok(HunkRange {
+        old_start,
+        old_count,
+        new_count,
+    })
+}
+
+/// 줄 수를 다 읽은 hunk 뒤에 본문 줄이 더 남아 있는지 확인합니다.
+/// 다음 파일 헤더(`--- ` + `+++ `), 다음 hunk, 빈 줄, diff 외 줄은 정상적인 끝입니다.
+fn has_extra_hunk_line(lines: &[&str], i: usize) -> bool {
+    let Some(line) = lines.get(i) else {
+        return false;
+    };
+    let file_header = line.starts_with("--- ")
+        && lines
+            .get(i + 1)
+            .is_some_and(|next| next.starts_with("+++ "));
+    !file_header && line.starts_with([' ', '+', '-'])
+}
+
+/// hunk들을 순서대로 적용합니다.
+/// 각 hunk는 헤더의 줄 번호 근처부터 바깥쪽으로 탐색하며, 정확히 일치하지 않으면
+/// 줄 끝 공백을 무시한 비교(fuzz)로 한 번 더 찾습니다.
+fn apply_hunks(original: &str, hunks: &[Hunk], path: &str) -> Result<String> {
+    let trailing_newline = original.is_empty() || original.ends_with('\n');
+    let mut lines: Vec<String> = original.lines().map(str::to_string).collect();
+    // 앞선 hunk로 인해 밀린 줄 수
+    let mut shift: isize = 0;
+
+    for (idx, hunk) in hunks.iter().enumerate() {
+        // 원본 줄 수가 0인 hunk(`@@ -5,0 +6,2 @@`)는 5번째 줄 "뒤"에 삽입
+        let start = if hunk.old_count == 0 {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let hint = (start as isize + shift).max(0) as usize;
+        let old_lines = hunk.old_lines();
+        let position = find_hunk(&lines, &old_lines, hint, |a, b| a == b)
+            .or_else(|| {
+                find_hunk(&lines, &old_lines, hint, |a, b| {
+                    a.trim_end() == b.trim_end()
+                })
+            })
+            .ok_or_else(|| {
+                SuprascalarError::InvalidToolInput(format!(
+                    "Hunk #{} ({}) failed to apply to '{}': context lines not found. \
+                    Re-read the file and regenerate the diff.",
+                    idx + 1,
+                    hunk.header,
+                    path
+                ))
+            })?;
+
+        // context 줄은 (fuzz로 매칭됐더라도) 원본 줄을 그대로 유지
+        let mut original_lines = lines[position..position + old_lines.len()].iter();
+        let mut replacement = Vec::new();
+        for line in &hunk.lines {
+            match line {
+                HunkLine::Context(_) => replacement.extend(original_lines.next().cloned()),
+                HunkLine::Remove(_) => {
+                    original_lines.next();
+                }
+                HunkLine::Add(text) => replacement.push(text.clone()),
+            }
+        }
+
+        shift += replacement.len() as isize - old_lines.len() as isize;
+        lines.splice(position..position + old_lines.len(), replacement);
+    }
+
+    let mut result = lines.join("\n");
+    if trailing_newline && !result.is_empty() {
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// `needle`이 시작하는 위치를 `hint`에서 가까운 순서로 찾습니다.
+fn find_hunk(
+    lines: &[String],
+    needle: &[&str],
+    hint: usize,
+    eq: impl Fn(&str, &str) -> bool,
+) -> Option<usize> {
+    if needle.is_empty() {
+        // 순수 추가 hunk (예: 새 파일)
+        return Some(hint.min(lines.len()));
+    }
+    if needle.len() > lines.len() {
+        return None;
+    }
+
+    let last = lines.len() - needle.len();
+    let matches_at = |pos: usize| {
+        lines[pos..pos + needle.len()]
+            .iter()
+            .zip(needle)
+            .all(|(a, b)| eq(a, b))
+    };
+
+    let hint = hint.min(last);
+    (0..=last.max(hint)).find_map(|distance| {
+        [hint.checked_sub(distance), hint.checked_add(distance)]
+            .into_iter()
+            .flatten()
+            .filter(|&pos| pos <= last)
+            .find(|&pos| matches_at(pos))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(original: &str, diff: &str) -> Result<String> {
+        let patches = parse_unified_diff(diff)?;
+        apply_hunks(original, &patches[0].hunks, "test.txt")
+    }
+
+    #[test]
+    fn applies_simple_hunk() {
+        let diff = "--- a/test.txt\n+++ b/test.txt\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n";
+        assert_eq!(apply("a\nb\nc\n", diff).unwrap(), "a\nB\nc\n");
+    }
+
+    #[test]
+    fn removed_line_that_looks_like_file_header_stays_in_hunk() {
+        // "-- x" 삭제와 "++ y" 추가는 각각 "--- x", "+++ y" 줄이 됨
+        let diff = "--- a/test.md\n+++ b/test.md\n@@ -1,3 +1,3 @@\n title\n--- x\n+++ y\n end\n";
+        let patches = parse_unified_diff(diff).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(
+            apply_hunks("title\n-- x\nend\n", &patches[0].hunks, "test.md").unwrap(),
+            "title\n++ y\nend\n"
+        );
+    }
+
+    #[test]
+    fn removes_sql_comment_line() {
+        let diff =
+            "--- a/q.sql\n+++ b/q.sql\n@@ -1,3 +1,2 @@\n SELECT 1;\n--- old comment\n SELECT 2;\n";
+        assert_eq!(
+            apply("SELECT 1;\n-- old comment\nSELECT 2;\n", diff).unwrap(),
+            "SELECT 1;\nSELECT 2;\n"
+        );
+    }
+
+    #[test]
+    fn zero_length_old_range_inserts_after_line() {
+        let diff = "--- a/test.txt\n+++ b/test.txt\n@@ -2,0 +3,2 @@\n+x\n+y\n";
+        assert_eq!(apply("a\nb\nc\n", diff).unwrap(), "a\nb\nx\ny\nc\n");
+
+        let diff = "--- a/test.txt\n+++ b/test.txt\n@@ -0,0 +1 @@\n+first\n";
+        assert_eq!(apply("a\n", diff).unwrap(), "first\na\n");
+    }
+
+    #[test]
+    fn parses_multiple_files_and_hunks() {
+        let diff = "diff --git a/x b/x\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-1\n+one\n@@ -3 +3 @@\n-3\n+three\n\
+                    --- a/y\n+++ b/y\n@@ -1 +1,2 @@\n y\n+z\n";
+        let patches = parse_unified_diff(diff).unwrap();
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].hunks.len(), 2);
+        assert_eq!(
+            apply_hunks("1\n2\n3\n", &patches[0].hunks, "x").unwrap(),
+            "one\n2\nthree\n"
+        );
+    }
+
+    #[test]
+    fn rejects_hunk_with_wrong_line_counts() {
+        let short = "--- a/t\n+++ b/t\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n";
+        assert!(parse_unified_diff(short).is_err());
+        let long = "--- a/t\n+++ b/t\n@@ -1,1 +1,1 @@\n-b\n+B\n c\n";
+        assert!(parse_unified_diff(long).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_file_sections() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("t.txt"), "a\nb\n").unwrap();
+        let tool = ApplyPatch::new()
+            .with_root(dir.path())
+            .unwrap()
+            .with_git_snapshot(GitSnapshot::disabled());
+
+        let diff = "--- a/t.txt\n+++ b/t.txt\n@@ -1 +1 @@\n-a\n+A\n\
+                    --- a/t.txt\n+++ b/t.txt\n@@ -2 +2 @@\n-b\n+B\n";
+        let err = tool.execute(json!({ "diff": diff })).unwrap_err();
+        assert!(err.to_string().contains("more than one"), "{}", err);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("t.txt")).unwrap(),
+            "a\nb\n"
+        );
+
+        let diff = "--- a/t.txt\n+++ b/t.txt\n@@ -1 +1 @@\n-a\n+A\n@@ -2 +2 @@\n-b\n+B\n";
+        tool.execute(json!({ "diff": diff })).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("t.txt")).unwrap(),
+            "A\nB\n"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use super::snapshot::GitSnapshot;
+use crate::error::{Result, SuprascalarError};
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// 호스트 파일시스템에 접근하는 도구들이 공유하는 샌드박스
+/// 같은 루트를 쓰는 도구들은 접근 가능한 범위가 항상 일치합니다.
+#[derive(Clone, Debug, Default)]
+pub struct Sandbox {
+    // 샌드박스 루트 (None이면 호출 시점의 current_dir)
+    root: Option<PathBuf>,
+    snapshot: GitSnapshot,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 루트를 지정한 샌드박스. 루트는 생성 시점에 canonicalize되므로 존재하는 디렉토리여야 합니다.
+    pub fn with_root(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into().canonicalize().map_err(SuprascalarError::Io)?;
+        Ok(Self {
+            root: Some(root),
+            snapshot: GitSnapshot::default(),
+        })
+    }
+
+    /// 파일 변경 전 Git 스냅샷 설정 (기본: 켜짐)
+    pub fn with_git_snapshot(mut self, snapshot: GitSnapshot) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    pub fn root(&self) -> Result<PathBuf> {
+        match &self.root {
+            Some(root) => Ok(root.clone()),
+            None => env::current_dir().map_err(SuprascalarError::Io),
+        }
+    }
+
+    /// [Security Patch] Symlink 공격 방지를 위한 물리적 경로 검증
+    /// 1. 프로젝트 루트의 진짜 경로(Real Path)를 구합니다.
+    /// 2. 요청한 경로의 진짜 경로를 구합니다.
+    /// 3. 요청한 경로가 프로젝트 루트 안쪽에 있는지 확인합니다.
+    pub fn validate_path(&self, tool: &str, path_str: &str) -> Result<PathBuf> {
+        // 1. 프로젝트 루트의 물리적 경로 (Symlink 해제됨)
+        let root = self.root()?;
+        let canonical_root = root.canonicalize().map_err(SuprascalarError::Io)?;
+
+        // 2. 타겟 경로 구성
+        let target_path = root.join(path_str);
+
+        // 3. 물리적 경로 확인 (Symlink Resolution)
+        // 케이스 A: 파일/폴더가 이미 존재하는 경우
+        if target_path.exists() {
+            let real_path =
+                target_path
+                    .canonicalize()
+                    .map_err(|e| SuprascalarError::ToolExecution {
+                        tool: tool.to_string(),
+                        message: format!("Failed to resolve path '{}': {}", path_str, e),
+                    })?;
+
+            if !real_path.starts_with(&canonical_root) {
+                // '..' / 절대경로 탈출과 Symlink 탈출(중간 디렉토리 포함)을 구분해서 알려줌
+                let reason = if passes_through_symlink(&root, path_str) {
+                    "Symlink detected!"
+                } else {
+                    "Path traversal detected!"
+                };
+                return Err(SuprascalarError::InvalidToolInput(format!(
+                    "SECURITY BLOCK: {} '{}' resolves to '{}', which is outside the project root.",
+                    reason,
+                    path_str,
+                    real_path.display()
+                )));
+            }
+            return Ok(real_path);
+        }
+
+        // 케이스 B: 파일이 존재하지 않는 경우 (새로 쓰기)
+        // 없는 디렉토리 뒤의 '..'는 그 디렉토리가 생긴 뒤에야 해석되므로 (예: `missing/../../x`)
+        // 아래의 부모 검사로는 잡히지 않음. 이런 경로는 미리 거부
+        if dot_dot_after_missing(&root, path_str) {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "SECURITY BLOCK: Path traversal detected! '{}' uses '..' after a directory that does not exist.",
+                path_str
+            )));
+        }
+        // 존재하지 않는 파일은 canonicalize가 불가능하므로, "존재하는 가장 깊은 부모 디렉토리"를 검사해야 함.
+        let mut current_check = target_path.parent();
+
+        while let Some(p) = current_check {
+            if p.exists() {
+                // 존재하는 부모를 찾았다! 이 부모가 혹시 외부로 연결된 심볼릭 링크인지 확인
+                let real_parent = p.canonicalize().map_err(SuprascalarError::Io)?;
+
+                if !real_parent.starts_with(&canonical_root) {
+                    return Err(SuprascalarError::InvalidToolInput(format!(
+                        "SECURITY BLOCK: Parent directory escape detected! '{}' resolves to outside the project root.",
+                        p.display()
+                    )));
+                }
+
+                // 부모가 안전하다면, 루프 종료 (안전함)
+                break;
+            }
+            // 더 상위 부모로 이동
+            current_check = p.parent();
+        }
+
+        // 여기까지 오면 안전함 (부모들이 모두 Safe Zone 안에 있음)
+        // 단, 리턴값은 canonicalize된 경로가 아니라 논리적 경로여야 함 (파일이 아직 없으므로)
+        // 하지만 편의상 절대경로(target_path)를 반환
+        Ok(target_path)
+    }
+
+    /// 파일 변경 전 샌드박스 루트에서 Git 스냅샷 커밋 (기본적인 감사/복구용)
+    /// 스냅샷이 꺼져 있거나 루트가 Git 저장소가 아니거나 변경 사항이 없으면 아무것도 하지 않습니다.
+    pub fn create_git_snapshot(&self, label: &str, context: &str) {
+        let Ok(cwd) = self.root() else {
+            return;
+        };
+        self.snapshot
+            .take(&cwd, &format!("{} '{}'", label, context));
+    }
+}
+
+/// `root`에서 `path_str`의 구성 요소를 하나씩 따라가면서 심볼릭 링크를 지나는지 확인합니다.
+/// 대상 자체뿐 아니라 중간 디렉토리가 링크인 경우도 포함합니다.
+fn passes_through_symlink(root: &Path, path_str: &str) -> bool {
+    let mut current = root.to_path_buf();
+    Path::new(path_str).components().any(|component| {
+        current.push(component);
+        current.is_symlink()
+    })
+}
+
+/// `root`에서 `path_str`을 따라가다 존재하지 않는 구성 요소를 지난 뒤에 `..`가 나오는지 확인합니다.
+fn dot_dot_after_missing(root: &Path, path_str: &str) -> bool {
+    let mut current = root.to_path_buf();
+    let mut missing = false;
+    for component in Path::new(path_str).components() {
+        if missing && component == Component::ParentDir {
+            return true;
+        }
+        current.push(component);
+        missing = missing || !current.exists();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// `inside/file.txt`가 있는 루트와, 루트 밖의 `secret.txt`가 있는 디렉토리
+    fn setup() -> (TempDir, TempDir, Sandbox) {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("inside")).unwrap();
+        fs::write(root.path().join("inside/file.txt"), "ok").unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let sandbox = Sandbox::with_root(root.path()).unwrap();
+        (root, outside, sandbox)
+    }
+
+    fn blocked(sandbox: &Sandbox, path: &str) -> String {
+        sandbox
+            .validate_path("test", path)
+            .expect_err("path should be blocked")
+            .to_string()
+    }
+
+    #[test]
+    fn allows_paths_inside_the_root() {
+        let (root, _outside, sandbox) = setup();
+        let file = root.path().canonicalize().unwrap().join("inside/file.txt");
+        assert_eq!(
+            sandbox.validate_path("test", "inside/file.txt").unwrap(),
+            file
+        );
+        assert_eq!(
+            sandbox
+                .validate_path("test", "inside/../inside/file.txt")
+                .unwrap(),
+            file
+        );
+    }
+
+    #[test]
+    fn blocks_dot_dot_traversal() {
+        let (_root, outside, sandbox) = setup();
+        let name = outside.path().file_name().unwrap().to_str().unwrap();
+        let err = blocked(&sandbox, &format!("../{}/secret.txt", name));
+        assert!(err.contains("Path traversal detected!"), "{}", err);
+        // 아직 없는 디렉토리를 거쳐 나가는 새 파일 (mkdir 후 쓰면 루트 밖에 생김)
+        let err = blocked(&sandbox, "missing/../../escape.txt");
+        assert!(err.contains("Path traversal detected!"), "{}", err);
+        let err = blocked(&sandbox, "inside/new/../../../escape.txt");
+        assert!(err.contains("Path traversal detected!"), "{}", err);
+    }
+
+    #[test]
+    fn blocks_absolute_path_outside_the_root() {
+        let (_root, outside, sandbox) = setup();
+        let path = outside.path().join("secret.txt");
+        let err = blocked(&sandbox, path.to_str().unwrap());
+        assert!(err.contains("Path traversal detected!"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn blocks_symlink_escapes() {
+        use std::os::unix::fs::symlink;
+        let (root, outside, sandbox) = setup();
+        symlink(outside.path().join("secret.txt"), root.path().join("link")).unwrap();
+        symlink(outside.path(), root.path().join("linkdir")).unwrap();
+
+        let err = blocked(&sandbox, "link");
+        assert!(err.contains("Symlink detected!"), "{}", err);
+        // 중간 디렉토리가 링크인 경우도 Symlink로 보고
+        let err = blocked(&sandbox, "linkdir/secret.txt");
+        assert!(err.contains("Symlink detected!"), "{}", err);
+        // 아직 없는 파일이라도 링크된 부모를 통해 나가면 차단
+        let err = blocked(&sandbox, "linkdir/new/file.txt");
+        assert!(err.contains("Parent directory escape"), "{}", err);
+    }
+
+    #[test]
+    fn allows_non_existent_nested_target() {
+        let (root, _outside, sandbox) = setup();
+        let path = sandbox.validate_path("test", "new/deep/file.txt").unwrap();
+        assert_eq!(
+            path,
+            root.path()
+                .canonicalize()
+                .unwrap()
+                .join("new/deep/file.txt")
+        );
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,303 @@
+use crate::error::{Result, SuprascalarError};
+use std::path::Path;
+use std::process::Command;
+
+/// 파일을 바꾸기 전 작업 트리를 자동 커밋하는 Git 스냅샷 설정
+/// 기본적으로 켜져 있으며, 사용자의 Git 히스토리를 건드리지 않으려면 `disabled()`를 사용하세요.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitSnapshot {
+    enabled: bool,
+    // 커밋 작성자 (이름, 이메일). None이면 저장소의 git 설정을 따름
+    author: Option<(String, String)>,
+    prefix: String,
+}
+
+impl Default for GitSnapshot {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            author: None,
+            prefix: "Suprascalar Auto-save".to_string(),
+        }
+    }
+}
+
+impl GitSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 스냅샷을 만들지 않는 설정
+    pub fn disabled() -> Self {
+        Self::default().with_enabled(false)
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// 스냅샷 커밋의 작성자/커미터 (git `user.name`, `user.email`이 없는 환경에서도 커밋 가능)
+    pub fn with_author(mut self, name: impl Into<String>, email: impl Into<String>) -> Self {
+        self.author = Some((name.into(), email.into()));
+        self
+    }
+
+    /// 커밋 메시지 접두어 (기본: "Suprascalar Auto-save")
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `dir`의 저장소에 변경 사항이 있으면 "{prefix}: Before {what}" 커밋을 만듭니다.
+    /// 꺼져 있거나, Git 저장소가 아니거나, 변경 사항이 없거나,
+    /// rebase/merge 등이 진행 중이면 아무것도 하지 않습니다.
+    pub(crate) fn take(&self, dir: &Path, what: &str) {
+        if !self.enabled || in_progress_operation(dir) {
+            return;
+        }
+
+        let status = Command::new("git")
+            .args(["status", "--porcelain"])
+            .current_dir(dir)
+            .output();
+
+        let Ok(output) = status else {
+            return;
+        };
+        // git 명령어가 실패했거나(저장소 아님), 변경사항이 없으면(빈 stdout) 리턴
+        if !output.status.success() || output.stdout.is_empty() {
+            return;
+        }
+
+        let _ = Command::new("git")
+            .args(["add", "."])
+            .current_dir(dir)
+            .output();
+
+        let msg = format!("{}: Before {}", self.prefix, what);
+        let mut commit = Command::new("git");
+        if let Some((name, email)) = &self.author {
+            commit
+                .arg("-c")
+                .arg(format!("user.name={}", name))
+                .arg("-c")
+                .arg(format!("user.email={}", email));
+        }
+        let _ = commit
+            .args(["commit", "-m", &msg])
+            .current_dir(dir)
+            .output();
+    }
+
+    /// 마지막 도구 실행의 변경을 되돌립니다.
+    /// HEAD가 자동 저장 커밋이면 작업 트리를 그 커밋 상태로 되돌린 뒤(새로 생긴 파일 삭제 포함)
+    /// 커밋 자체는 풀어서 스냅샷 당시의 미커밋 변경으로 남깁니다. 연속으로 호출하면 한 단계씩 더 되돌립니다.
+    /// HEAD가 자동 저장 커밋이 아니면(그 뒤에 사용자가 커밋했거나, 변경 직전 트리가 깨끗해서
+    /// 스냅샷이 없으면) 사용자의 작업을 지울 수 있으므로 아무것도 하지 않고 `Undo` 에러를 돌려줍니다.
+    pub fn undo_last(&self, dir: &Path) -> Result<String> {
+        if in_progress_operation(dir) {
+            return Err(SuprascalarError::Undo(
+                "a merge/rebase is in progress".to_string(),
+            ));
+        }
+
+        let head = git(dir, &["log", "-1", "--format=%H%x00%s"])?;
+        let (hash, subject) = head
+            .trim_end()
+            .split_once('\0')
+            .ok_or_else(|| SuprascalarError::Undo("no commits in the repository".to_string()))?;
+        if !subject.starts_with(&format!("{}: ", self.prefix)) {
+            return Err(SuprascalarError::Undo(format!(
+                "the latest commit '{}' ({:.8}) is not an auto-save snapshot",
+                subject, hash
+            )));
+        }
+
+        git(dir, &["reset", "--hard", hash])?;
+        // 스냅샷이 `git add .`으로 모든 파일을 담았으므로, 그 뒤에 생긴 추적되지 않은 파일은 도구가 만든 것
+        git(dir, &["clean", "-fd"])?;
+        let parent = format!("{}^", hash);
+        if git(dir, &["rev-parse", "--verify", "--quiet", &parent]).is_ok() {
+            git(dir, &["reset", "--mixed", &parent])?;
+        }
+        Ok(format!(
+            "Restored the working tree to the snapshot '{}' ({:.8}).",
+            subject, hash
+        ))
+    }
+}
+
+/// git 명령을 실행하고 stdout을 돌려줍니다. 실패하면 stderr를 담은 `Undo` 에러
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| SuprascalarError::Undo(format!("failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(SuprascalarError::Undo(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// rebase/merge/cherry-pick/revert가 진행 중인지 확인합니다.
+/// 이 상태에서 커밋하면 사용자의 충돌 해결 과정이 섞이므로 스냅샷을 건너뜁니다.
+fn in_progress_operation(dir: &Path) -> bool {
+    let Ok(output) = Command::new("git")
+        .args(["rev-parse", "--absolute-git-dir"])
+        .current_dir(dir)
+        .output()
+    else {
+        return false;
+    };
+    if !output.status.success() {
+        return false;
+    }
+    let git_dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let git_dir = Path::new(&git_dir);
+    [
+        "MERGE_HEAD",
+        "rebase-merge",
+        "rebase-apply",
+        "CHERRY_PICK_HEAD",
+        "REVERT_HEAD",
+    ]
+    .iter()
+    .any(|marker| git_dir.join(marker).exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn snapshot() -> GitSnapshot {
+        GitSnapshot::new().with_author("Test", "test@example.com")
+    }
+
+    /// `a.txt`를 커밋한 임시 저장소
+    fn repo() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        run(&["init", "-q"]);
+        fs::write(dir.path().join("a.txt"), "original\n").unwrap();
+        run(&["add", "."]);
+        run(&["commit", "-q", "-m", "initial"]);
+        dir
+    }
+
+    fn head_subject(dir: &Path) -> String {
+        git(dir, &["log", "-1", "--format=%s"])
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn undo_refuses_when_head_is_not_a_snapshot() {
+        let dir = repo();
+        let snapshot = snapshot();
+
+        // 깨끗한 트리에서는 스냅샷 커밋이 생기지 않으므로 되돌릴 대상이 없음
+        snapshot.take(dir.path(), "write_file");
+        assert_eq!(head_subject(dir.path()), "initial");
+
+        fs::write(dir.path().join("a.txt"), "user edit\n").unwrap();
+        fs::write(dir.path().join("new.txt"), "user file\n").unwrap();
+
+        let err = snapshot.undo_last(dir.path()).unwrap_err();
+        assert!(matches!(err, SuprascalarError::Undo(_)), "{:?}", err);
+        assert!(err.to_string().contains("initial"), "{}", err);
+        // 사용자의 미커밋 변경과 추적되지 않은 파일은 그대로
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "user edit\n"
+        );
+        assert!(dir.path().join("new.txt").exists());
+        assert_eq!(head_subject(dir.path()), "initial");
+    }
+
+    #[test]
+    fn undo_after_mutation_on_dirty_tree_keeps_user_edits() {
+        let dir = repo();
+        let snapshot = snapshot();
+
+        fs::write(dir.path().join("a.txt"), "user edit\n").unwrap();
+        snapshot.take(dir.path(), "write_file");
+        assert_eq!(
+            head_subject(dir.path()),
+            "Suprascalar Auto-save: Before write_file"
+        );
+
+        fs::write(dir.path().join("a.txt"), "changed by tool\n").unwrap();
+        fs::write(dir.path().join("new.txt"), "created by tool\n").unwrap();
+
+        snapshot.undo_last(dir.path()).unwrap();
+        // 스냅샷 커밋은 풀리고 사용자의 수정은 미커밋 변경으로 남음
+        assert_eq!(head_subject(dir.path()), "initial");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "user edit\n"
+        );
+        assert!(!dir.path().join("new.txt").exists());
+    }
+
+    #[test]
+    fn undo_refuses_after_intervening_manual_commit() {
+        let dir = repo();
+        let snapshot = snapshot();
+
+        fs::write(dir.path().join("a.txt"), "user edit\n").unwrap();
+        snapshot.take(dir.path(), "write_file");
+        fs::write(dir.path().join("a.txt"), "changed by tool\n").unwrap();
+        git(dir.path(), &["add", "."]).unwrap();
+        git(
+            dir.path(),
+            &[
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "-q",
+                "-m",
+                "manual",
+            ],
+        )
+        .unwrap();
+
+        assert!(snapshot.undo_last(dir.path()).is_err());
+        assert_eq!(head_subject(dir.path()), "manual");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "changed by tool\n"
+        );
+    }
+
+    #[test]
+    fn disabled_snapshot_does_not_commit() {
+        let dir = repo();
+        fs::write(dir.path().join("a.txt"), "user edit\n").unwrap();
+        GitSnapshot::disabled().take(dir.path(), "write_file");
+        assert_eq!(head_subject(dir.path()), "initial");
+    }
+}
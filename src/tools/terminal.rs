@@ -1,17 +1,44 @@
-use super::Tool;
+use super::snapshot::GitSnapshot;
+use super::{OutputBudget, Tool, ToolOutputSink, parse_args};
 use crate::error::{Result, SuprascalarError};
 use regex::Regex;
+use serde::Deserialize;
 use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
 use std::sync::Mutex;
 
+#[derive(Deserialize)]
+struct ShellArgs {
+    command: String,
+    // 있으면 자식 프로세스의 표준 입력으로 전달
+    #[serde(default)]
+    stdin: Option<String>,
+}
+
 /// 터미널 세션을 유지하며 쉘 명령어를 실행하는 도구
 /// Safety Layer 포함: 위험 명령어 차단 및 Git 자동 스냅샷 기능
 pub struct TerminalSession {
     cwd: Mutex<PathBuf>,
     safety_enabled: bool,
+    output_budget: OutputBudget,
+    // 실행되는 모든 명령에 추가로 설정할 환경 변수 (상속된 값보다 우선)
+    env: HashMap<String, String>,
+    // 차단 목록을 통과시킬 명령 (정확히 같은 문자열만)
+    allowlist: Mutex<Allowlist>,
+    snapshot: GitSnapshot,
+    // 명령 실행 중 출력을 실시간으로 내보낼 곳 (기본: Silent)
+    output_sink: ToolOutputSink,
+}
+
+#[derive(Default)]
+struct Allowlist {
+    persistent: HashSet<String>,
+    // 한 번 실행되면 제거됨
+    once: HashSet<String>,
 }
 
 impl TerminalSession {
@@ -20,25 +47,87 @@ impl TerminalSession {
             // 초기 시작 위치: 현재 프로세스의 작업 디렉토리
             cwd: Mutex::new(env::current_dir().unwrap_or_else(|_| PathBuf::from("/"))),
             safety_enabled: true, // 기본적으로 안전 모드 켜짐
+            output_budget: OutputBudget::default(),
+            env: HashMap::new(),
+            allowlist: Mutex::new(Allowlist::default()),
+            snapshot: GitSnapshot::default(),
+            output_sink: ToolOutputSink::Silent,
         }
     }
 
-    /// LLM 컨텍스트 보호를 위한 출력 제한
-    fn truncate_output(output: String) -> String {
-        const MAX_CHARS: usize = 2000;
-        if output.len() > MAX_CHARS {
-            let half = MAX_CHARS / 2;
-            let start = &output[..half];
-            let end = &output[output.len() - half..];
-            format!(
-                "{}\n... [Output truncated. Total: {} chars] ...\n{}",
-                start,
-                output.len(),
-                end
-            )
-        } else {
-            output
+    /// 명령 실행 시 적용할 환경 변수 (예: `PATH`, `CARGO_TARGET_DIR`)
+    /// 프로세스 환경을 상속한 뒤 같은 키는 이 값으로 덮어씁니다.
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// 시작 작업 디렉토리를 지정합니다. 존재하는 디렉토리여야 합니다 (canonicalize).
+    pub fn with_cwd(self, cwd: impl Into<PathBuf>) -> Result<Self> {
+        let cwd = cwd.into().canonicalize().map_err(SuprascalarError::Io)?;
+        if !cwd.is_dir() {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "Working directory '{}' is not a directory.",
+                cwd.display()
+            )));
         }
+        *self.lock_cwd() = cwd;
+        Ok(self)
+    }
+
+    /// 명령 실행 전 Git 스냅샷 설정 (기본: 켜짐, `GitSnapshot::disabled()`로 끔)
+    pub fn with_git_snapshot(mut self, snapshot: GitSnapshot) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// 차단 목록에 걸리더라도 항상 실행을 허용할 명령 (예: `rm -rf target`)
+    /// 앞뒤 공백을 제외하고 정확히 같은 문자열일 때만 적용됩니다.
+    pub fn with_allowed_command(self, cmd: impl Into<String>) -> Self {
+        self.allow(cmd);
+        self
+    }
+
+    /// 실행 중에 영구 허용 명령을 추가합니다.
+    pub fn allow(&self, cmd: impl Into<String>) {
+        let cmd = cmd.into().trim().to_string();
+        self.lock_allowlist().persistent.insert(cmd);
+    }
+
+    /// 다음 한 번의 실행에 한해 차단 목록을 건너뛸 명령을 추가합니다.
+    /// 에이전트에 등록한 뒤에도 호출하려면 `Arc<TerminalSession>`으로 공유해서 등록하세요.
+    pub fn allow_once(&self, cmd: impl Into<String>) {
+        let cmd = cmd.into().trim().to_string();
+        self.lock_allowlist().once.insert(cmd);
+    }
+
+    // 다른 스레드가 락을 잡은 채 panic해도 경로 값 자체는 유효하므로 poison을 무시하고 사용
+    // (에러로 바꾸면 panic 한 번에 세션 전체가 쓸 수 없게 됨)
+    fn lock_cwd(&self) -> std::sync::MutexGuard<'_, PathBuf> {
+        self.cwd.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_allowlist(&self) -> std::sync::MutexGuard<'_, Allowlist> {
+        self.allowlist.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 허용 목록에 있으면 true (일회성 항목은 이때 소비됨)
+    fn take_allowed(&self, cmd: &str) -> bool {
+        let cmd = cmd.trim();
+        let mut allowlist = self.lock_allowlist();
+        allowlist.persistent.contains(cmd) || allowlist.once.remove(cmd)
+    }
+
+    /// LLM 컨텍스트 보호를 위한 출력 제한 설정
+    pub fn with_output_budget(mut self, budget: OutputBudget) -> Self {
+        self.output_budget = budget;
+        self
+    }
+
+    /// 명령 실행 중 stdout/stderr를 실시간으로 내보낼 곳 (기본: `ToolOutputSink::Silent`)
+    pub fn with_output_sink(mut self, sink: ToolOutputSink) -> Self {
+        self.output_sink = sink;
+        self
     }
 
     /// [Safety 1] 위험한 명령어 감지 (Blocklist)
@@ -69,6 +158,10 @@ impl TerminalSession {
             // 정규식 컴파일 (실제로는 lazy_static 등으로 최적화 가능하지만 여기선 단순화)
             if let Ok(re) = Regex::new(pattern) {
                 if re.is_match(cmd) {
+                    // 사용자가 승인한 명령은 통과
+                    if self.take_allowed(cmd) {
+                        return Ok(());
+                    }
                     return Err(SuprascalarError::CommandBlocked {
                         command: cmd.to_string(),
                         reason: reason.to_string(),
@@ -82,36 +175,8 @@ impl TerminalSession {
     /// [Safety 2] 실행 전 Git 자동 커밋 (Snapshot)
     /// 현재 작업 디렉토리가 Git 저장소이고 변경사항이 있다면 커밋을 생성합니다.
     fn create_git_snapshot(&self, dir: &Path, cmd_context: &str) {
-        // 1. 해당 디렉토리가 Git 저장소인지 확인 (.git 폴더 존재 여부)
-        // 간단한 체크: 현재 폴더에 .git이 있거나, git status가 성공하면 저장소임.
-        let status_check = Command::new("git")
-            .args(["status", "--porcelain"])
-            .current_dir(dir)
-            .output();
-
-        if let Ok(output) = status_check {
-            // git 명령어가 실패했거나(저장소 아님), 변경사항이 없으면(빈 stdout) 리턴
-            if !output.status.success() || output.stdout.is_empty() {
-                return;
-            }
-
-            // 2. 변경사항이 확인되면 자동 커밋 진행
-            // Stage all changes
-            let _ = Command::new("git")
-                .args(["add", "."])
-                .current_dir(dir)
-                .output();
-
-            // Commit
-            let commit_msg = format!("Suprascalar Auto-save: Before running '{}'", cmd_context);
-            let _ = Command::new("git")
-                .args(["commit", "-m", &commit_msg])
-                .current_dir(dir)
-                .output();
-
-            // 디버깅용 출력 (필요시 주석 해제)
-            // println!(">> [Safety] Auto-saved changes via Git.");
-        }
+        self.snapshot
+            .take(dir, &format!("running '{}'", cmd_context));
     }
 }
 
@@ -133,6 +198,10 @@ impl Tool for TerminalSession {
                 "command": {
                     "type": "string",
                     "description": "The shell command to execute"
+                },
+                "stdin": {
+                    "type": "string",
+                    "description": "Optional text to pipe to the command's standard input"
                 }
             },
             "required": ["command"]
@@ -141,9 +210,8 @@ impl Tool for TerminalSession {
 
     fn execute(&self, args: Value) -> Result<String> {
         // 1. 명령어 파싱
-        let command_str = args["command"].as_str().ok_or_else(|| {
-            SuprascalarError::InvalidToolInput("Missing 'command' parameter".to_string())
-        })?;
+        let ShellArgs { command, stdin } = parse_args(args)?;
+        let command_str = command.as_str();
 
         // [Safety 1] 금지어 검사
         self.check_safety(command_str)?;
@@ -157,26 +225,20 @@ impl Tool for TerminalSession {
                 trimmed_cmd.strip_prefix("cd ").unwrap().trim()
             };
 
-            let mut cwd_guard = self.cwd.lock().map_err(|_| {
-                SuprascalarError::TerminalState("Failed to lock terminal state".to_string())
-            })?;
-
-            let resolved = resolve_cd_target(&cwd_guard, target)?;
+            // 파일 시스템 접근(canonicalize)은 락 밖에서
+            let current = self.lock_cwd().clone();
+            let resolved = resolve_cd_target(&current, target)?;
             // 경로 존재 여부 확인 (canonicalize)
             let canonical = resolved.canonicalize().map_err(SuprascalarError::Io)?;
 
-            *cwd_guard = canonical;
-            return Ok(format!("Changed directory to: {}", cwd_guard.display()));
+            let message = format!("Changed directory to: {}", canonical.display());
+            *self.lock_cwd() = canonical;
+            return Ok(message);
         }
 
         // 3. 일반 명령어 실행 준비
         // Mutex 락을 잠깐 잡아서 경로만 복사 (실행 중에는 락 해제)
-        let run_dir = {
-            let cwd_guard = self.cwd.lock().map_err(|_| {
-                SuprascalarError::TerminalState("Failed to lock terminal state".to_string())
-            })?;
-            cwd_guard.clone()
-        };
+        let run_dir = self.lock_cwd().clone();
 
         // [Safety 2] Git 스냅샷 생성
         // 명령어를 실행하기 직전, 현재 작업 디렉토리(run_dir) 상태를 저장
@@ -185,17 +247,21 @@ impl Tool for TerminalSession {
         }
 
         // 4. 프로세스 실행
-        let output_result = if cfg!(target_os = "windows") {
-            Command::new("cmd")
-                .args(["/C", command_str])
-                .current_dir(run_dir)
-                .output()
+        let mut cmd = if cfg!(target_os = "windows") {
+            let mut cmd = Command::new("cmd");
+            cmd.args(["/C", command_str]);
+            cmd
         } else {
-            Command::new("sh")
-                .arg("-c")
-                .arg(command_str)
-                .current_dir(run_dir)
-                .output()
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command_str);
+            cmd
+        };
+        cmd.current_dir(run_dir).envs(&self.env);
+
+        let output_result = if self.output_sink.is_silent() && stdin.is_none() {
+            cmd.output()
+        } else {
+            run_piped(&mut cmd, stdin, &self.output_sink)
         };
 
         // 5. 결과 처리
@@ -218,13 +284,87 @@ impl Tool for TerminalSession {
                     )
                 };
 
-                Ok(Self::truncate_output(combined))
+                Ok(self.output_budget.truncate(combined))
             }
             Err(e) => Err(SuprascalarError::Io(e)),
         }
     }
 }
 
+/// 표준 입출력을 파이프로 연결해 실행하고, 출력은 읽는 대로 `sink`에도 내보냅니다.
+/// 파이프가 가득 차서 서로 기다리지 않도록 입력 쓰기와 stdout/stderr 읽기는 각각 별도 스레드에서 합니다.
+fn run_piped(
+    cmd: &mut Command,
+    input: Option<String>,
+    sink: &ToolOutputSink,
+) -> std::io::Result<Output> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let writer = input.map(|input| {
+        let mut child_stdin = child.stdin.take().expect("stdin is piped");
+        std::thread::spawn(move || {
+            // 자식이 입력을 다 읽지 않고 종료하면 BrokenPipe가 나므로 무시
+            let _ = child_stdin.write_all(input.as_bytes());
+            // 여기서 drop되어 EOF 전달
+        })
+    });
+    let stdout = forward_stream(child.stdout.take().expect("stdout is piped"), sink.clone());
+    let stderr = forward_stream(child.stderr.take().expect("stderr is piped"), sink.clone());
+
+    let status = child.wait()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// 스트림을 끝까지 읽어 모은 바이트를 돌려주는 스레드. 읽은 조각은 UTF-8 경계에 맞춰 `sink`로 보냅니다.
+fn forward_stream(
+    mut stream: impl Read + Send + 'static,
+    sink: ToolOutputSink,
+) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut collected = Vec::new();
+        let mut buf = [0u8; 4096];
+        // 아직 내보내지 않은 바이트의 시작 위치 (멀티바이트 문자가 잘린 경우)
+        let mut emitted = 0;
+        loop {
+            let n = match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            collected.extend_from_slice(&buf[..n]);
+            let pending = &collected[emitted..];
+            let valid = match std::str::from_utf8(pending) {
+                Ok(text) => text.len(),
+                // 끝에서 잘린 문자는 다음 조각과 합쳐서 보냄, 잘못된 바이트는 그대로 넘김
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => pending.len(),
+            };
+            if valid > 0 {
+                sink.chunk(&String::from_utf8_lossy(&pending[..valid]));
+                emitted += valid;
+            }
+        }
+        if emitted < collected.len() {
+            sink.chunk(&String::from_utf8_lossy(&collected[emitted..]));
+        }
+        collected
+    })
+}
+
 /// 'cd' 타겟 경로 해석 헬퍼 함수 (기존 로직 유지)
 fn resolve_cd_target(current_cwd: &Path, target: &str) -> Result<PathBuf> {
     if target == "~" || target.starts_with("~/") {
@@ -0,0 +1,490 @@
+use super::sandbox::Sandbox;
+use super::{OutputBudget, Tool, parse_args};
+use crate::error::{Result, SuprascalarError};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::LazyLock;
+
+/// 기본으로 보여주는 실패 테스트 개수 상한
+const DEFAULT_MAX_FAILURES: usize = 10;
+
+/// 실패 하나당 보여주는 메시지 줄 수
+const MAX_MESSAGE_LINES: usize = 8;
+
+/// pytest 요약 줄의 항목 (예: "2 failed, 10 passed, 1 skipped in 0.53s")
+static PYTEST_COUNT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\d+) (passed|failed|skipped|xfailed|xpassed|errors?)\b")
+        .expect("valid pytest summary regex")
+});
+
+/// 테스트(`cargo test` 또는 pytest)를 실행하고 통과/실패 수와 실패한 테스트의 메시지만 요약해서 돌려주는 도구
+/// TDD 방식의 에이전트가 받는 피드백을 원본 테스트 로그 대신 짧은 목록으로 만듭니다.
+pub struct TestRunner {
+    sandbox: Sandbox,
+    max_failures: usize,
+    // 테스트가 실행되지 못한 경우(컴파일 에러 등) 보여줄 원본 출력에 적용할 제한
+    output_budget: OutputBudget,
+}
+
+#[derive(Deserialize)]
+struct TestArgs {
+    // 테스트 이름 필터 (cargo: 부분 문자열, pytest: `-k` 표현식)
+    #[serde(default)]
+    filter: Option<String>,
+    // 프로젝트 디렉토리 (샌드박스 루트 기준)
+    #[serde(default = "default_path")]
+    path: String,
+    // "cargo" 또는 "pytest". 없으면 Cargo.toml 유무로 결정
+    #[serde(default)]
+    framework: Option<String>,
+}
+
+fn default_path() -> String {
+    ".".to_string()
+}
+
+/// 파싱한 테스트 결과
+#[derive(Debug, Default)]
+struct TestReport {
+    passed: usize,
+    failed: usize,
+    ignored: usize,
+    // (테스트 이름, 실패 메시지)
+    failures: Vec<(String, String)>,
+}
+
+impl TestReport {
+    fn total(&self) -> usize {
+        self.passed + self.failed + self.ignored
+    }
+}
+
+/// libtest 출력(`test name ... ok`, `---- name stdout ----` 블록)을 파싱합니다.
+fn parse_libtest(stdout: &str) -> TestReport {
+    let mut report = TestReport::default();
+    let mut failed_names = Vec::new();
+    // 실패 블록: (이름, 메시지 줄들)
+    let mut sections: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut in_section = false;
+
+    for line in stdout.lines() {
+        if let Some(rest) = line.strip_prefix("test ")
+            && let Some((name, status)) = rest.rsplit_once(" ... ")
+        {
+            match status.trim() {
+                "ok" => report.passed += 1,
+                "FAILED" => {
+                    report.failed += 1;
+                    failed_names.push(name.to_string());
+                }
+                s if s.starts_with("ignored") => report.ignored += 1,
+                _ => {}
+            }
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|l| l.strip_suffix(" stdout ----"))
+        {
+            sections.push((name.to_string(), Vec::new()));
+            in_section = true;
+            continue;
+        }
+        // 블록 뒤에 오는 실패 이름 목록/요약 줄에서 블록 종료
+        if line == "failures:" || line.starts_with("test result:") {
+            in_section = false;
+            continue;
+        }
+        if in_section && let Some((_, lines)) = sections.last_mut() {
+            lines.push(line);
+        }
+    }
+
+    for name in failed_names {
+        let message = sections
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, lines)| failure_message(lines))
+            .unwrap_or_default();
+        report.failures.push((name, message));
+    }
+    report
+}
+
+/// 실패 블록에서 panic 메시지와 위치만 남깁니다.
+/// panic 전에 테스트가 출력한 내용과 backtrace는 버립니다.
+fn failure_message(lines: &[&str]) -> String {
+    let mut location = None;
+    let mut message = Vec::new();
+    for line in lines {
+        if line.starts_with("note: run with `RUST_BACKTRACE") {
+            continue;
+        }
+        if line.starts_with("stack backtrace:") {
+            break;
+        }
+        // "thread 'x' (123) panicked at src/lib.rs:10:5:" (이전 버전은 같은 줄에 메시지가 붙음)
+        if line.starts_with("thread '")
+            && let Some((_, at)) = line.split_once(" panicked at ")
+        {
+            message.clear();
+            // Rust 1.73 이전: "panicked at 'msg', src/lib.rs:10:5"
+            if let Some((msg, loc)) = at
+                .strip_prefix('\'')
+                .and_then(|rest| rest.rsplit_once("', "))
+            {
+                location = Some(loc.to_string());
+                message.push(msg.to_string());
+                continue;
+            }
+            match at.split_once(": ") {
+                Some((loc, msg)) if !loc.contains(' ') => {
+                    location = Some(loc.to_string());
+                    message.push(msg.trim_matches('\'').to_string());
+                }
+                _ => location = Some(at.trim_end_matches(':').to_string()),
+            }
+            continue;
+        }
+        message.push(line.to_string());
+    }
+
+    let mut out: Vec<String> = message
+        .into_iter()
+        .skip_while(|l| l.trim().is_empty())
+        .collect();
+    while out.last().is_some_and(|l| l.trim().is_empty()) {
+        out.pop();
+    }
+    if out.len() > MAX_MESSAGE_LINES {
+        let omitted = out.len() - MAX_MESSAGE_LINES;
+        out.truncate(MAX_MESSAGE_LINES);
+        out.push(format!("... ({} more lines)", omitted));
+    }
+    if let Some(location) = location {
+        out.push(format!("at {}", location));
+    }
+    out.join("\n")
+}
+
+/// `pytest -q -rf --tb=no` 출력(`FAILED path::name - msg`, 마지막 요약 줄)을 파싱합니다.
+fn parse_pytest(stdout: &str) -> TestReport {
+    let mut report = TestReport::default();
+    for line in stdout.lines() {
+        if let Some(rest) = line.strip_prefix("FAILED ") {
+            let (name, message) = rest.split_once(" - ").unwrap_or((rest, ""));
+            report
+                .failures
+                .push((name.trim().to_string(), message.trim().to_string()));
+        }
+    }
+
+    if let Some(summary) = stdout
+        .lines()
+        .rev()
+        .find(|l| PYTEST_COUNT_RE.is_match(l) && l.contains(" in "))
+    {
+        for cap in PYTEST_COUNT_RE.captures_iter(summary) {
+            let n: usize = cap[1].parse().unwrap_or(0);
+            match &cap[2] {
+                "passed" | "xpassed" => report.passed += n,
+                "failed" | "error" | "errors" => report.failed += n,
+                _ => report.ignored += n,
+            }
+        }
+    }
+    report
+}
+
+impl Default for TestRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRunner {
+    pub fn new() -> Self {
+        Self {
+            sandbox: Sandbox::new(),
+            max_failures: DEFAULT_MAX_FAILURES,
+            output_budget: OutputBudget::default(),
+        }
+    }
+
+    /// 샌드박스 루트를 지정합니다. `path` 인자는 이 루트 안쪽이어야 합니다.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Result<Self> {
+        self.sandbox = Sandbox::with_root(root)?;
+        Ok(self)
+    }
+
+    /// 다른 도구(예: `FileIO`)와 같은 샌드박스를 공유합니다.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// 메시지와 함께 보여줄 실패 테스트의 최대 개수
+    pub fn with_max_failures(mut self, max: usize) -> Self {
+        self.max_failures = max;
+        self
+    }
+
+    /// 테스트가 실행되지 못했을 때 보여줄 원본 출력의 제한
+    pub fn with_output_budget(mut self, budget: OutputBudget) -> Self {
+        self.output_budget = budget;
+        self
+    }
+
+    fn summarize(&self, label: &str, success: bool, report: &TestReport) -> String {
+        let mut out = format!(
+            "{}: {} ({} passed, {} failed, {} ignored)\n",
+            label,
+            if success { "OK" } else { "FAILED" },
+            report.passed,
+            report.failed,
+            report.ignored
+        );
+        if report.failures.is_empty() {
+            return out;
+        }
+
+        out.push_str("Failing tests:\n");
+        for (name, message) in report.failures.iter().take(self.max_failures) {
+            out.push_str(&format!("- {}\n", name));
+            for line in message.lines() {
+                out.push_str(&format!("    {}\n", line));
+            }
+        }
+        if report.failures.len() > self.max_failures {
+            out.push_str(&format!(
+                "... and {} more failing tests\n",
+                report.failures.len() - self.max_failures
+            ));
+        }
+        out
+    }
+}
+
+impl Tool for TestRunner {
+    fn name(&self) -> &str {
+        "run_tests"
+    }
+
+    fn description(&self) -> &str {
+        "Runs the project's tests (cargo test or pytest), optionally filtered by name, and \
+        returns pass/fail counts plus the names and assertion messages of failing tests. \
+        Prefer this over running tests in the shell."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "filter": {
+                    "type": "string",
+                    "description": "Only run tests whose name matches (cargo: substring, pytest: -k expression)"
+                },
+                "path": {
+                    "type": "string",
+                    "description": "Project directory (default: '.')"
+                },
+                "framework": {
+                    "type": "string",
+                    "enum": ["cargo", "pytest"],
+                    "description": "Test runner to use (default: cargo if Cargo.toml exists, otherwise pytest)"
+                }
+            }
+        })
+    }
+
+    fn execute(&self, args: Value) -> Result<String> {
+        let args: TestArgs = parse_args(args)?;
+        let filter = args.filter.as_deref().map(str::trim).unwrap_or("");
+        // 필터가 옵션으로 해석되지 않도록
+        if filter.starts_with('-') {
+            return Err(SuprascalarError::InvalidToolInput(format!(
+                "Test filter must not start with '-': '{}'",
+                filter
+            )));
+        }
+
+        // [Security] 작업 디렉토리는 샌드박스 안쪽으로 제한
+        let dir = self.sandbox.validate_path(self.name(), &args.path)?;
+        if !dir.is_dir() {
+            return Ok(format!("Error: Directory '{}' does not exist.", args.path));
+        }
+
+        let framework = match args.framework.as_deref() {
+            Some(f) => f,
+            None if dir.join("Cargo.toml").exists() => "cargo",
+            None => "pytest",
+        };
+
+        let mut command = match framework {
+            "cargo" => {
+                let mut c = Command::new("cargo");
+                c.arg("test");
+                if !filter.is_empty() {
+                    c.arg(filter);
+                }
+                c.arg("--no-fail-fast").env("RUST_BACKTRACE", "0");
+                c
+            }
+            "pytest" => {
+                let mut c = Command::new("python3");
+                c.args(["-m", "pytest", "-q", "-rf", "--tb=no"]);
+                if !filter.is_empty() {
+                    c.args(["-k", filter]);
+                }
+                c
+            }
+            other => {
+                return Err(SuprascalarError::InvalidToolInput(format!(
+                    "Unsupported test framework '{}'. Use cargo or pytest.",
+                    other
+                )));
+            }
+        };
+
+        let output =
+            command
+                .current_dir(&dir)
+                .output()
+                .map_err(|e| SuprascalarError::ToolExecution {
+                    tool: self.name().to_string(),
+                    message: format!("Failed to run {}: {}", framework, e),
+                })?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let report = if framework == "cargo" {
+            parse_libtest(&stdout)
+        } else {
+            parse_pytest(&stdout)
+        };
+
+        let label = match filter {
+            "" => format!("{} test", framework),
+            f => format!("{} test '{}'", framework, f),
+        };
+        let mut result = self.summarize(&label, output.status.success(), &report);
+
+        if !output.status.success() && report.total() == 0 {
+            // 테스트가 하나도 실행되지 않은 실패 (컴파일 에러, pytest 미설치 등)
+            let mut raw = String::from_utf8_lossy(&output.stderr).into_owned();
+            if raw.trim().is_empty() {
+                raw = stdout.into_owned();
+            }
+            result.push_str("\nNo tests ran. Output:\n");
+            result.push_str(&self.output_budget.truncate(raw));
+        } else if report.total() == 0 {
+            result.push_str("No tests matched.\n");
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBTEST_OUTPUT: &str = "\
+running 4 tests
+test parser::tests::parses_empty ... ok
+test parser::tests::rejects_garbage ... FAILED
+test net::tests::slow ... ignored, needs network
+test math::tests::adds ... FAILED
+
+failures:
+
+---- parser::tests::rejects_garbage stdout ----
+parsing: garbage
+
+thread 'parser::tests::rejects_garbage' (4242) panicked at src/parser.rs:88:9:
+assertion `left == right` failed
+  left: Ok(())
+ right: Err(Garbage)
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+---- math::tests::adds stdout ----
+thread 'math::tests::adds' panicked at 'attempt to add with overflow', src/math.rs:3:5
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+
+failures:
+    math::tests::adds
+    parser::tests::rejects_garbage
+
+test result: FAILED. 1 passed; 2 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.01s
+";
+
+    #[test]
+    fn libtest_counts_and_failure_messages() {
+        let report = parse_libtest(LIBTEST_OUTPUT);
+        assert_eq!((report.passed, report.failed, report.ignored), (1, 2, 1));
+        assert_eq!(
+            report.failures,
+            vec![
+                (
+                    "parser::tests::rejects_garbage".to_string(),
+                    // 패닉 전에 테스트가 출력한 줄은 버리고 새 형식의 위치를 끝에 붙임
+                    "assertion `left == right` failed\n  left: Ok(())\n right: Err(Garbage)\n\
+                     at src/parser.rs:88:9"
+                        .to_string()
+                ),
+                (
+                    "math::tests::adds".to_string(),
+                    // 이전 형식: 메시지가 위치 앞, 같은 줄에 있음
+                    "attempt to add with overflow\nat src/math.rs:3:5".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn libtest_failure_message_is_capped_and_stops_at_backtrace() {
+        let mut lines = vec!["thread 'big' panicked at src/lib.rs:1:1:"];
+        let body: Vec<String> = (0..12).map(|i| format!("line {}", i)).collect();
+        lines.extend(body.iter().map(String::as_str));
+        lines.push("stack backtrace:");
+        lines.push("   0: std::panicking::begin_panic");
+
+        let message = failure_message(&lines);
+        let out: Vec<&str> = message.lines().collect();
+        assert_eq!(out.len(), MAX_MESSAGE_LINES + 2);
+        assert_eq!(out[MAX_MESSAGE_LINES], "... (4 more lines)");
+        assert_eq!(out[MAX_MESSAGE_LINES + 1], "at src/lib.rs:1:1");
+        assert!(!message.contains("begin_panic"));
+    }
+
+    #[test]
+    fn pytest_summary_and_failures() {
+        let output = "\
+..F.sx
+=========================== short test summary info ============================
+FAILED tests/test_math.py::test_div - ZeroDivisionError: division by zero
+FAILED tests/test_io.py::test_read
+2 failed, 3 passed, 1 skipped, 1 xfailed in 0.53s
+";
+        let report = parse_pytest(output);
+        assert_eq!((report.passed, report.failed, report.ignored), (3, 2, 2));
+        assert_eq!(
+            report.failures,
+            vec![
+                (
+                    "tests/test_math.py::test_div".to_string(),
+                    "ZeroDivisionError: division by zero".to_string()
+                ),
+                ("tests/test_io.py::test_read".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn pytest_counts_collection_errors_as_failures() {
+        let report = parse_pytest("ERROR tests/test_bad.py\n1 error in 0.10s\n");
+        assert_eq!((report.passed, report.failed), (0, 1));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::error::Result;
+use candle_core::Device;
+use candle_core::backend::BackendDevice;
+use candle_core::utils::{cuda_is_available, metal_is_available};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 실행 중인 작업(chat 루프, 생성 루프)을 외부에서 중단하기 위한 토큰
+/// 클론은 같은 플래그를 공유하므로, UI의 "중지" 버튼 등에서 `cancel()`을 호출하면 됩니다.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// 다음 요청을 위해 취소 상태를 해제합니다.
+    pub fn reset(&self) {
+        self.flag.store(false, Ordering::SeqCst);
+    }
+}
+
+/// 사용 가능한 가속기를 골라 `Device`를 만듭니다.
+/// CUDA(`cuda` feature) → Metal(`metal` feature, Apple Silicon) → CPU 순으로 시도합니다.
+pub fn select_device() -> Result<Device> {
+    if cuda_is_available() {
+        Ok(Device::new_cuda(0)?)
+    } else if metal_is_available() {
+        Ok(Device::new_metal(0)?)
+    } else {
+        Ok(Device::Cpu)
+    }
+}
+
+/// 디바이스에 큐잉된 커널이 모두 끝날 때까지 기다립니다.
+/// 타이밍 측정이나 두 모델 간 텐서 전달(draft → verify) 전에 호출해야 결과가 준비된 것이 보장됩니다.
+/// CPU 연산은 호출 시점에 이미 끝나 있으므로 동기화가 필요 없습니다.
+pub fn sync_device(device: &Device) -> Result<()> {
+    match device {
+        Device::Cuda(dev) => dev.synchronize()?,
+        Device::Metal(dev) => dev.synchronize()?,
+        Device::Cpu => {}
+    }
+    Ok(())
+}